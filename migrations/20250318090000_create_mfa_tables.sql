@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS user_totp (
+  user_id UUID PRIMARY KEY,
+  secret TEXT,
+  confirmed_at TIMESTAMPTZ,
+  pending_secret TEXT,
+  last_used_step BIGINT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS user_recovery_codes (
+  user_id UUID NOT NULL,
+  code_hash TEXT NOT NULL,
+  used_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (user_id, code_hash)
+);
+
+CREATE TABLE IF NOT EXISTS mfa_token_attempts (
+  jti TEXT PRIMARY KEY,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  consumed_at TIMESTAMPTZ,
+  expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS impersonations (
+  id TEXT PRIMARY KEY,
+  actor_id UUID NOT NULL,
+  user_id UUID NOT NULL,
+  reason TEXT NOT NULL,
+  started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL,
+  ended_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS impersonations_user_id_idx ON impersonations (user_id);
+CREATE INDEX IF NOT EXISTS impersonations_actor_id_idx ON impersonations (actor_id);
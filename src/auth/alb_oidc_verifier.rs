@@ -0,0 +1,234 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use jsonwebtoken::{crypto, Algorithm, DecodingKey};
+use lambda_http::http::{Method, Request};
+use serde_json::{Map, Value};
+
+use crate::{auth::http_client::IHttpClient, error::SerializableError, Error};
+
+/// Verifies the `x-amzn-oidc-data` JWT an ALB OIDC action passes along. It
+/// is signed with ES256 by a regional ALB key named by `kid`, and its
+/// `signer` header must be the expected load balancer.
+///
+/// ALB pads the token's base64 segments, so it can't go through `JwtUtil`.
+pub struct AlbOidcVerifier {
+  http_client: Box<dyn IHttpClient>,
+  signer: String,
+  issuer: Option<String>,
+  keys: RwLock<HashMap<String, DecodingKey>>,
+}
+
+impl AlbOidcVerifier {
+  /// `signer` is the load balancer ARN; its region selects the public key
+  /// endpoint.
+  pub fn new(http_client: Box<dyn IHttpClient>, signer: &str) -> Self {
+    Self {
+      http_client,
+      signer: signer.to_string(),
+      issuer: None,
+      keys: RwLock::new(HashMap::new()),
+    }
+  }
+
+  /// Also requires the `iss` claim, i.e. the identity provider, to match.
+  pub fn with_issuer(mut self, issuer: &str) -> Self {
+    self.issuer = Some(issuer.to_string());
+
+    self
+  }
+
+  pub async fn verify(&self, token: &str) -> Result<Map<String, Value>, Error> {
+    let [encoded_header, encoded_claims, signature] = token.split('.').collect::<Vec<&str>>()[..] else {
+      return Err(unauthorized("Malformed x-amzn-oidc-data header"));
+    };
+
+    let header = decode_part(encoded_header)?;
+    if header.get("alg").and_then(Value::as_str) != Some("ES256") {
+      return Err(unauthorized("Unexpected x-amzn-oidc-data algorithm"));
+    }
+
+    if header.get("signer").and_then(Value::as_str) != Some(self.signer.as_str()) {
+      return Err(unauthorized("x-amzn-oidc-data was signed by another load balancer"));
+    }
+
+    let key_id = header.get("kid").and_then(Value::as_str)
+      .ok_or_else(|| unauthorized("x-amzn-oidc-data is missing a key id"))?;
+    let key = self.public_key(key_id).await?;
+    let message = format!("{}.{}", encoded_header, encoded_claims);
+    let verified = crypto::verify(signature.trim_end_matches('='), message.as_bytes(), &key, Algorithm::ES256)
+      .unwrap_or(false);
+
+    if !verified {
+      return Err(unauthorized("Invalid x-amzn-oidc-data signature"));
+    }
+
+    let claims = decode_part(encoded_claims)?;
+    let expires_at = claims.get("exp").and_then(Value::as_i64)
+      .ok_or_else(|| unauthorized("x-amzn-oidc-data is missing exp"))?;
+
+    if expires_at <= Utc::now().timestamp() {
+      return Err(unauthorized("x-amzn-oidc-data has expired"));
+    }
+
+    if let Some(issuer) = &self.issuer
+      && claims.get("iss").and_then(Value::as_str) != Some(issuer.as_str()) {
+      return Err(unauthorized("x-amzn-oidc-data was issued by another identity provider"));
+    }
+
+    Ok(claims)
+  }
+
+  /// ALB keys never change for a `kid`, so they are cached for good.
+  async fn public_key(&self, key_id: &str) -> Result<DecodingKey, Error> {
+    let cached = self.keys.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(key_id).cloned();
+    if let Some(key) = cached {
+      return Ok(key);
+    }
+
+    // Key ids are looked up by URL, so only allow what ALB issues.
+    if key_id.is_empty() || !key_id.chars().all(|character| character.is_ascii_alphanumeric() || character == '-') {
+      return Err(unauthorized("Invalid x-amzn-oidc-data key id"));
+    }
+
+    let region = self.signer.split(':').nth(3).filter(|region| !region.is_empty())
+      .ok_or_else(|| identity_provider_error("Signer is not a load balancer ARN"))?;
+    let request = Request::builder()
+      .method(Method::GET)
+      .uri(format!("https://public-keys.auth.elb.{}.amazonaws.com/{}", region, key_id))
+      .body(String::new())
+      .map_err(|error| identity_provider_error(&error.to_string()))?;
+    let response = self.http_client.send(request).await?;
+
+    if !response.status().is_success() {
+      return Err(identity_provider_error(&format!("ALB public key endpoint returned {}", response.status())));
+    }
+
+    let key = DecodingKey::from_ec_pem(response.body().as_bytes())
+      .map_err(|error| identity_provider_error(&format!("Invalid ALB public key: {}", error)))?;
+    self.keys.write().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(key_id.to_string(), key.clone());
+
+    Ok(key)
+  }
+}
+
+fn decode_part(part: &str) -> Result<Map<String, Value>, Error> {
+  URL_SAFE_NO_PAD.decode(part.trim_end_matches('=')).ok()
+    .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+    .ok_or_else(|| unauthorized("Malformed x-amzn-oidc-data header"))
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+fn identity_provider_error(message: &str) -> Error {
+  Error::IdentityProvider(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  use base64::engine::general_purpose::URL_SAFE;
+  use jsonwebtoken::EncodingKey;
+  use lambda_http::http::{Response, StatusCode};
+
+  use crate::auth::http_client::MockIHttpClient;
+
+  pub(crate) const ALB_SIGNER: &str = "arn:aws:elasticloadbalancing:eu-west-1:123456789012:loadbalancer/app/my-alb/50dc6c495c0c9188";
+
+  /// An `x-amzn-oidc-data` token the way ALB signs it: ES256 with
+  /// ec_private.pem, padded base64 segments and a `signer` header.
+  pub(crate) fn alb_oidc_token(claims: Value, signer: &str) -> String {
+    let header = serde_json::json!({ "alg": "ES256", "kid": "key-1", "signer": signer, "typ": "JWT" });
+    let message = format!("{}.{}", URL_SAFE.encode(header.to_string()), URL_SAFE.encode(claims.to_string()));
+    let key = EncodingKey::from_ec_pem(include_bytes!("../../tests/fixtures/keys/ec_private.pem")).unwrap();
+    let signature = crypto::sign(message.as_bytes(), &key, Algorithm::ES256).unwrap();
+
+    format!("{}.{}", message, signature)
+  }
+
+  /// The eu-west-1 ALB public key endpoint serving ec_public.pem as `key-1`,
+  /// expected to be called once.
+  pub(crate) fn alb_key_endpoint() -> MockIHttpClient {
+    let mut http_client = MockIHttpClient::new();
+    http_client.expect_send()
+      .withf(|request| request.uri() == "https://public-keys.auth.elb.eu-west-1.amazonaws.com/key-1")
+      .times(1)
+      .returning(|_| Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(include_str!("../../tests/fixtures/keys/ec_public.pem").to_string())
+        .unwrap()));
+
+    http_client
+  }
+
+  fn claims() -> Value {
+    serde_json::json!({
+      "sub": "1234567890",
+      "email": "john.doe@example.com",
+      "iss": "https://idp.example.com",
+      "exp": Utc::now().timestamp() + 60,
+    })
+  }
+
+  #[tokio::test]
+  async fn test_verify() {
+    // arrange
+    let verifier = AlbOidcVerifier::new(Box::new(alb_key_endpoint()), ALB_SIGNER).with_issuer("https://idp.example.com");
+    let token = alb_oidc_token(claims(), ALB_SIGNER);
+
+    // act
+    let first_result = verifier.verify(&token).await;
+    let second_result = verifier.verify(&token).await;
+
+    // assert
+    assert_eq!(first_result.unwrap().get("email"), Some(&Value::from("john.doe@example.com")));
+    assert!(second_result.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_rejects_forged_claims() {
+    // arrange
+    let verifier = AlbOidcVerifier::new(Box::new(alb_key_endpoint()), ALB_SIGNER);
+    let token = alb_oidc_token(claims(), ALB_SIGNER);
+    let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+    parts[1] = URL_SAFE_NO_PAD.encode(serde_json::json!({ "sub": "admin", "exp": Utc::now().timestamp() + 60 }).to_string());
+
+    // act
+    let result = verifier.verify(&parts.join(".")).await;
+
+    // assert
+    match result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Invalid x-amzn-oidc-data signature"),
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_rejects_other_signer_issuer_and_expired_token() {
+    // arrange
+    let verifier = AlbOidcVerifier::new(Box::new(alb_key_endpoint()), ALB_SIGNER).with_issuer("https://idp.example.com");
+    let mut expired_claims = claims();
+    expired_claims["exp"] = Value::from(Utc::now().timestamp() - 1);
+    let mut other_issuer_claims = claims();
+    other_issuer_claims["iss"] = Value::from("https://evil.example.com");
+
+    // act
+    let other_signer_result = verifier.verify(&alb_oidc_token(claims(), &ALB_SIGNER.replace("my-alb", "other-alb"))).await;
+    let expired_result = verifier.verify(&alb_oidc_token(expired_claims, ALB_SIGNER)).await;
+    let other_issuer_result = verifier.verify(&alb_oidc_token(other_issuer_claims, ALB_SIGNER)).await;
+
+    // assert
+    match other_signer_result {
+      Err(Error::Unauthorized(error)) => {
+        assert_eq!(error.message, "x-amzn-oidc-data was signed by another load balancer")
+      },
+      _ => panic!("Expected Unauthorized error"),
+    }
+    assert!(matches!(expired_result, Err(Error::Unauthorized(_))));
+    assert!(matches!(other_issuer_result, Err(Error::Unauthorized(_))));
+  }
+}
@@ -0,0 +1,264 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use lambda_http::http::HeaderMap;
+
+use crate::{
+  auth::{
+    api_key_store::{api_key_prefix, ApiKeyRecord, IApiKeyStore},
+    load_principal,
+    principal_loader::IPrincipalLoader,
+    IAuth,
+  },
+  error::SerializableError,
+  types::auth::{AuthPayload, AuthUser},
+  Error,
+};
+
+/// Authenticates machine-to-machine callers by an API key header,
+/// `X-Api-Key` unless configured otherwise.
+pub struct ApiKeyAuth<T: AuthPayload = AuthUser> {
+  api_key_store: Box<dyn IApiKeyStore>,
+  header_name: String,
+  principal_loader: Option<Box<dyn IPrincipalLoader<T>>>,
+  api_key: Option<ApiKeyRecord>,
+  user: Option<T>,
+}
+
+impl ApiKeyAuth {
+  pub fn new(api_key_store: Box<dyn IApiKeyStore>) -> Self {
+    Self::for_payload(api_key_store)
+  }
+}
+
+impl<T: AuthPayload> ApiKeyAuth<T> {
+  pub fn for_payload(api_key_store: Box<dyn IApiKeyStore>) -> Self {
+    Self {
+      api_key_store,
+      header_name: "X-Api-Key".to_string(),
+      principal_loader: None,
+      api_key: None,
+      user: None,
+    }
+  }
+
+  pub fn with_header(mut self, header_name: &str) -> Self {
+    self.header_name = header_name.to_string();
+
+    self
+  }
+
+  /// Rejects keys of users the loader no longer returns, e.g. deleted or
+  /// deactivated ones. `user` then returns the loaded principal instead of
+  /// the one stored with the key.
+  pub fn with_principal_loader(mut self, principal_loader: Box<dyn IPrincipalLoader<T>>) -> Self {
+    self.principal_loader = Some(principal_loader);
+
+    self
+  }
+
+  pub fn user(&self) -> Option<T> {
+    self.user.clone()
+  }
+
+  pub fn api_key(&self) -> Option<&ApiKeyRecord> {
+    self.api_key.as_ref()
+  }
+
+  pub fn scopes(&self) -> &[String] {
+    self.api_key.as_ref().map_or(&[], |api_key| &api_key.scopes)
+  }
+
+  pub fn has_scope(&self, scope: &str) -> bool {
+    self.scopes().iter().any(|granted| granted == scope)
+  }
+}
+
+#[async_trait]
+impl<T: AuthPayload> IAuth for ApiKeyAuth<T> {
+  async fn authenticate(&mut self, headers: &HeaderMap) -> Result<(), Error> {
+    let key = headers
+      .get(self.header_name.as_str())
+      .ok_or_else(|| unauthorized(&format!("Missing {} header", self.header_name)))?
+      .to_str()
+      .map_err(|_| unauthorized("Malformed API key"))?
+      .trim();
+
+    let prefix = api_key_prefix(key).ok_or_else(|| unauthorized("Malformed API key"))?;
+
+    let api_key = self.api_key_store
+      .find_by_prefix(prefix)
+      .await?
+      .filter(|api_key| api_key.matches(key))
+      .ok_or_else(|| unauthorized("Invalid API key"))?;
+
+    if api_key.revoked_at.is_some() {
+      return Err(unauthorized("API key has been revoked"));
+    }
+
+    if api_key.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+      return Err(unauthorized("API key has expired"));
+    }
+
+    let user: T = match self.principal_loader.as_deref() {
+      Some(principal_loader) => load_principal(principal_loader, api_key.user_id).await?,
+      None => serde_json::from_value(api_key.principal.clone()).map_err(|error| {
+        Error::DatabaseRowMapping(SerializableError { message: error.to_string() })
+      })?,
+    };
+
+    self.api_key_store.touch(api_key.id).await?;
+
+    self.user = Some(user);
+    self.api_key = Some(api_key);
+
+    Ok(())
+  }
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::Duration;
+  use uuid::Uuid;
+
+  use crate::auth::{api_key_store::MockIApiKeyStore, principal_loader::MockIPrincipalLoader};
+
+  fn auth_user() -> AuthUser {
+    AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    }
+  }
+
+  fn headers(header_name: &str, key: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(lambda_http::http::HeaderName::from_bytes(header_name.as_bytes()).unwrap(), key.parse().unwrap());
+
+    headers
+  }
+
+  fn error_message(result: Result<(), Error>) -> String {
+    match result {
+      Err(Error::Unauthorized(error)) => error.message,
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_success() {
+    // arrange
+    let user = auth_user();
+    let (key, record) = ApiKeyRecord::generate("fk", "CI", &user, &["orders:read"], None).unwrap();
+    let record_id = record.id;
+    let mut api_key_store = MockIApiKeyStore::new();
+    let prefix = record.prefix.clone();
+    api_key_store.expect_find_by_prefix()
+      .withf(move |candidate| candidate == prefix)
+      .returning(move |_| Ok(Some(record.clone())));
+    api_key_store.expect_touch()
+      .with(mockall::predicate::eq(record_id))
+      .times(1)
+      .returning(|_| Ok(()));
+
+    // act
+    let mut auth = ApiKeyAuth::new(Box::new(api_key_store));
+    let result = auth.authenticate(&headers("X-Api-Key", &key)).await;
+
+    // assert
+    assert!(result.is_ok());
+    assert_eq!(auth.user(), Some(user));
+    assert!(auth.has_scope("orders:read"));
+    assert!(!auth.has_scope("orders:write"));
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_wrong_secret() {
+    // arrange
+    let (key, record) = ApiKeyRecord::generate("fk", "CI", &auth_user(), &[], None).unwrap();
+    let mut api_key_store = MockIApiKeyStore::new();
+    api_key_store.expect_find_by_prefix().returning(move |_| Ok(Some(record.clone())));
+    api_key_store.expect_touch().never();
+    let tampered_key = format!("{}_{}", api_key_prefix(&key).unwrap(), "0".repeat(64));
+
+    // act
+    let mut auth = ApiKeyAuth::new(Box::new(api_key_store));
+    let result = auth.authenticate(&headers("X-Api-Key", &tampered_key)).await;
+
+    // assert
+    assert_eq!(error_message(result), "Invalid API key");
+    assert_eq!(auth.user(), None);
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_expired_and_revoked() {
+    // arrange
+    let (expired_key, mut expired_record) = ApiKeyRecord::generate("fk", "CI", &auth_user(), &[], None).unwrap();
+    expired_record.expires_at = Some(Utc::now() - Duration::minutes(1));
+    let (revoked_key, mut revoked_record) = ApiKeyRecord::generate("fk", "CI", &auth_user(), &[], None).unwrap();
+    revoked_record.revoked_at = Some(Utc::now());
+    let mut api_key_store = MockIApiKeyStore::new();
+    api_key_store.expect_find_by_prefix().returning(move |prefix| {
+      Ok([&expired_record, &revoked_record].into_iter().find(|record| record.prefix == prefix).cloned())
+    });
+
+    // act
+    let mut auth = ApiKeyAuth::new(Box::new(api_key_store));
+    let expired_result = auth.authenticate(&headers("X-Api-Key", &expired_key)).await;
+    let revoked_result = auth.authenticate(&headers("X-Api-Key", &revoked_key)).await;
+
+    // assert
+    assert_eq!(error_message(expired_result), "API key has expired");
+    assert_eq!(error_message(revoked_result), "API key has been revoked");
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_custom_header() {
+    // arrange
+    let mut api_key_store = MockIApiKeyStore::new();
+    api_key_store.expect_find_by_prefix().never();
+
+    // act
+    let mut auth = ApiKeyAuth::new(Box::new(api_key_store)).with_header("X-Service-Key");
+    let missing_result = auth.authenticate(&headers("X-Api-Key", "fk_abc_def")).await;
+    let malformed_result = auth.authenticate(&headers("X-Service-Key", "not-a-key")).await;
+
+    // assert
+    assert_eq!(error_message(missing_result), "Missing X-Service-Key header");
+    assert_eq!(error_message(malformed_result), "Malformed API key");
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_with_principal_loader() {
+    // arrange
+    let user = auth_user();
+    let renamed_user = AuthUser { last_name: "Smith".to_string(), ..user.clone() };
+    let (key, record) = ApiKeyRecord::generate("fk", "CI", &user, &[], None).unwrap();
+    let (deactivated_key, deactivated_record) = ApiKeyRecord::generate("fk", "CI", &auth_user(), &[], None).unwrap();
+    let mut api_key_store = MockIApiKeyStore::new();
+    api_key_store.expect_find_by_prefix().returning(move |prefix| {
+      Ok([&record, &deactivated_record].into_iter().find(|record| record.prefix == prefix).cloned())
+    });
+    api_key_store.expect_touch().times(1).returning(|_| Ok(()));
+    let loaded_user = renamed_user.clone();
+    let mut principal_loader = MockIPrincipalLoader::new();
+    principal_loader.expect_load()
+      .returning(move |user_id| Ok((user_id == loaded_user.id).then(|| loaded_user.clone())));
+    let mut auth = ApiKeyAuth::new(Box::new(api_key_store)).with_principal_loader(Box::new(principal_loader));
+
+    // act
+    let deactivated_result = auth.authenticate(&headers("X-Api-Key", &deactivated_key)).await;
+    let result = auth.authenticate(&headers("X-Api-Key", &key)).await;
+
+    // assert
+    assert_eq!(error_message(deactivated_result), "User no longer exists or has been deactivated");
+    assert!(result.is_ok());
+    assert_eq!(auth.user(), Some(renamed_user));
+  }
+}
@@ -0,0 +1,210 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  error::SerializableError,
+  types::auth::AuthPayload,
+  Error,
+};
+
+/// A stored API key. Only a SHA-256 hash of the key is kept; `prefix`
+/// identifies the key without revealing it.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ApiKeyRecord {
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub name: String,
+  pub prefix: String,
+  pub key_hash: String,
+  pub scopes: Vec<String>,
+  /// The principal `ApiKeyAuth::user` returns, serialized when the key was
+  /// created.
+  pub principal: Value,
+  pub expires_at: Option<DateTime<Utc>>,
+  pub last_used_at: Option<DateTime<Utc>>,
+  pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyRecord {
+  /// Generates a key `<namespace>_<lookup>_<secret>` for `user`. Returns the
+  /// plaintext key, to be shown once, and the record to save.
+  pub fn generate<T: AuthPayload>(
+    namespace: &str,
+    name: &str,
+    user: &T,
+    scopes: &[&str],
+    expires_at: Option<DateTime<Utc>>,
+  ) -> Result<(String, Self), Error> {
+    let prefix = format!("{}_{}", namespace, random_hex(8));
+    let key = format!("{}_{}", prefix, random_hex(32));
+    let principal = serde_json::to_value(user).map_err(|error| Error::Unhandled(SerializableError {
+      message: error.to_string()
+    }))?;
+
+    Ok((key.clone(), Self {
+      id: Uuid::new_v4(),
+      user_id: user.user_id(),
+      name: name.to_string(),
+      prefix,
+      key_hash: hash_api_key(&key),
+      scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+      principal,
+      expires_at,
+      last_used_at: None,
+      revoked_at: None,
+    }))
+  }
+
+  /// Compares `key` against the stored hash in constant time.
+  pub fn matches(&self, key: &str) -> bool {
+    let key_hash = hash_api_key(key);
+
+    key_hash.len() == self.key_hash.len() && key_hash.bytes()
+      .zip(self.key_hash.bytes())
+      .fold(0u8, |difference, (left, right)| difference | (left ^ right)) == 0
+  }
+}
+
+/// The lookup prefix of `key`, i.e. everything before the secret.
+pub fn api_key_prefix(key: &str) -> Option<&str> {
+  let (prefix, secret) = key.rsplit_once('_')?;
+
+  (!secret.is_empty() && prefix.contains('_')).then_some(prefix)
+}
+
+pub fn hash_api_key(key: &str) -> String {
+  Sha256::digest(key.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn random_hex(length: usize) -> String {
+  let mut bytes = vec![0u8; length];
+  OsRng.fill_bytes(&mut bytes);
+
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IApiKeyStore: Send + Sync {
+  async fn create(&self, api_key: &ApiKeyRecord) -> Result<(), Error>;
+
+  async fn find_by_prefix(&self, prefix: &str) -> Result<Option<ApiKeyRecord>, Error>;
+
+  /// Records that the key was just used.
+  async fn touch(&self, id: Uuid) -> Result<(), Error>;
+
+  async fn revoke(&self, id: Uuid) -> Result<(), Error>;
+}
+
+/// `IApiKeyStore` backed by the `api_keys` table from `database::MIGRATOR`.
+#[derive(Clone)]
+pub struct PgApiKeyStore {
+  pool: PgPool,
+}
+
+impl PgApiKeyStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl IApiKeyStore for PgApiKeyStore {
+  async fn create(&self, api_key: &ApiKeyRecord) -> Result<(), Error> {
+    sqlx::query(
+      "INSERT INTO api_keys (id, user_id, name, prefix, key_hash, scopes, principal, expires_at)
+       VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+    )
+      .bind(api_key.id)
+      .bind(api_key.user_id)
+      .bind(&api_key.name)
+      .bind(&api_key.prefix)
+      .bind(&api_key.key_hash)
+      .bind(&api_key.scopes)
+      .bind(&api_key.principal)
+      .bind(api_key.expires_at)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn find_by_prefix(&self, prefix: &str) -> Result<Option<ApiKeyRecord>, Error> {
+    Ok(sqlx::query_as(
+      "SELECT id, user_id, name, prefix, key_hash, scopes, principal, expires_at, last_used_at, revoked_at
+       FROM api_keys WHERE prefix = $1"
+    )
+      .bind(prefix)
+      .fetch_optional(&self.pool)
+      .await?)
+  }
+
+  async fn touch(&self, id: Uuid) -> Result<(), Error> {
+    sqlx::query("UPDATE api_keys SET last_used_at = NOW() WHERE id = $1")
+      .bind(id)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn revoke(&self, id: Uuid) -> Result<(), Error> {
+    sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+      .bind(id)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::types::auth::AuthUser;
+
+  fn auth_user() -> AuthUser {
+    AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    }
+  }
+
+  #[test]
+  fn test_generate() {
+    // arrange
+    let user = auth_user();
+
+    // act
+    let (key, record) = ApiKeyRecord::generate("fk_live", "CI", &user, &["orders:read"], None).unwrap();
+
+    // assert
+    assert!(key.starts_with(&format!("{}_", record.prefix)));
+    assert!(record.prefix.starts_with("fk_live_"));
+    assert_eq!(api_key_prefix(&key), Some(record.prefix.as_str()));
+    assert_ne!(record.key_hash, key);
+    assert!(record.matches(&key));
+    assert!(!record.matches(&format!("{}0", key)));
+    assert_eq!(record.user_id, user.id);
+    assert_eq!(record.scopes, vec!["orders:read".to_string()]);
+  }
+
+  #[test]
+  fn test_api_key_prefix_rejects_malformed_keys() {
+    // assert
+    assert_eq!(api_key_prefix("no-separators"), None);
+    assert_eq!(api_key_prefix("fk_"), None);
+    assert_eq!(api_key_prefix("fk_secret"), None);
+  }
+}
@@ -0,0 +1,159 @@
+use lambda_http::http::HeaderMap;
+
+use crate::{error::SerializableError, Error};
+
+/// Authorization schemes `Auth` accepts. Schemes are compared
+/// case-insensitively, as RFC 7235 requires.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthConfig {
+  schemes: Vec<String>,
+}
+
+impl Default for AuthConfig {
+  fn default() -> Self {
+    Self::new("Watashiwasta")
+  }
+}
+
+impl AuthConfig {
+  pub fn new(scheme: &str) -> Self {
+    Self { schemes: vec![scheme.trim().to_string()] }
+  }
+
+  pub fn bearer() -> Self {
+    Self::new("Bearer")
+  }
+
+  /// Accepts `scheme` in addition to the already configured ones.
+  pub fn with_scheme(mut self, scheme: &str) -> Self {
+    self.schemes.push(scheme.trim().to_string());
+
+    self
+  }
+
+  pub fn schemes(&self) -> &[String] {
+    &self.schemes
+  }
+
+  /// Returns the credentials of the `Authorization` header, e.g. the token
+  /// in `Bearer <token>`.
+  pub fn extract_token<'a>(&self, headers: &'a HeaderMap) -> Result<&'a str, Error> {
+    let header_value = headers
+      .get("Authorization")
+      .ok_or_else(|| unauthorized("Missing Authorization header"))?
+      .to_str()
+      .map_err(|_| unauthorized("Malformed Authorization header"))?;
+
+    self.parse_credentials(header_value)
+  }
+
+  /// Parses `<scheme> <token68>`, tolerating extra whitespace around and
+  /// between the two parts.
+  pub fn parse_credentials<'a>(&self, header_value: &'a str) -> Result<&'a str, Error> {
+    let header_value = header_value.trim_matches(is_whitespace);
+    let (scheme, token) = header_value
+      .split_once(is_whitespace)
+      .unwrap_or((header_value, ""));
+
+    if !self.schemes.iter().any(|accepted| accepted.eq_ignore_ascii_case(scheme)) {
+      return Err(unauthorized("Unsupported authorization scheme"));
+    }
+
+    let token = token.trim_matches(is_whitespace);
+    if !is_token68(token) {
+      return Err(unauthorized("Malformed authorization token"));
+    }
+
+    Ok(token)
+  }
+}
+
+fn is_whitespace(character: char) -> bool {
+  character == ' ' || character == '\t'
+}
+
+/// `token68` from RFC 7235: `1*( ALPHA / DIGIT / "-" / "." / "_" / "~" /
+/// "+" / "/" ) *"="`.
+fn is_token68(token: &str) -> bool {
+  let value = token.trim_end_matches('=');
+
+  !value.is_empty() && value.chars().all(|character| {
+    character.is_ascii_alphanumeric() || matches!(character, '-' | '.' | '_' | '~' | '+' | '/')
+  })
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn error_message(result: Result<&str, Error>) -> String {
+    match result {
+      Err(Error::Unauthorized(error)) => error.message,
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[test]
+  fn test_parse_credentials_is_case_insensitive_and_whitespace_tolerant() {
+    // arrange
+    let config = AuthConfig::bearer();
+
+    // act
+    let standard = config.parse_credentials("Bearer abc.def-ghi");
+    let lowercase = config.parse_credentials("bearer abc.def-ghi");
+    let padded = config.parse_credentials("  BEARER \t abc.def-ghi  ");
+
+    // assert
+    assert_eq!(standard.unwrap(), "abc.def-ghi");
+    assert_eq!(lowercase.unwrap(), "abc.def-ghi");
+    assert_eq!(padded.unwrap(), "abc.def-ghi");
+  }
+
+  #[test]
+  fn test_parse_credentials_accepts_any_configured_scheme() {
+    // arrange
+    let config = AuthConfig::bearer().with_scheme("Watashiwasta");
+
+    // act
+    let bearer = config.parse_credentials("Bearer token");
+    let custom = config.parse_credentials("Watashiwasta token");
+
+    // assert
+    assert!(bearer.is_ok());
+    assert!(custom.is_ok());
+  }
+
+  #[test]
+  fn test_parse_credentials_errors() {
+    // arrange
+    let config = AuthConfig::bearer();
+
+    // act
+    let wrong_scheme = config.parse_credentials("Basic dXNlcjpwYXNz");
+    let prefix_only = config.parse_credentials("Bearertoken");
+    let missing_token = config.parse_credentials("Bearer ");
+    let invalid_token = config.parse_credentials("Bearer abc def");
+
+    // assert
+    assert_eq!(error_message(wrong_scheme), "Unsupported authorization scheme");
+    assert_eq!(error_message(prefix_only), "Unsupported authorization scheme");
+    assert_eq!(error_message(missing_token), "Malformed authorization token");
+    assert_eq!(error_message(invalid_token), "Malformed authorization token");
+  }
+
+  #[test]
+  fn test_extract_token_missing_header() {
+    // arrange
+    let headers = HeaderMap::new();
+
+    // act
+    let result = AuthConfig::default().extract_token(&headers);
+
+    // assert
+    assert_eq!(error_message(result), "Missing Authorization header");
+  }
+}
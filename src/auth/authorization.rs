@@ -0,0 +1,224 @@
+use std::collections::BTreeSet;
+
+use async_trait::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  error::SerializableError,
+  types::{auth::AuthPayload, utils::jwt_util::AuthClaims},
+  Error,
+};
+
+/// Roles and permissions of an authenticated principal.
+///
+/// Permissions are `resource:action` strings. A permission ending in `:*`
+/// grants every action on the resource and `*` grants everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Grants {
+  roles: BTreeSet<String>,
+  permissions: BTreeSet<String>,
+}
+
+impl Grants {
+  pub fn new(roles: &[&str], permissions: &[&str]) -> Self {
+    Self {
+      roles: roles.iter().map(|role| role.to_string()).collect(),
+      permissions: permissions.iter().map(|permission| permission.to_string()).collect(),
+    }
+  }
+
+  pub fn from_claims<T: AuthPayload>(claims: &AuthClaims<T>) -> Self {
+    Self {
+      roles: claims.roles.iter().cloned().collect(),
+      permissions: claims.permissions.iter().cloned().collect(),
+    }
+  }
+
+  pub fn merge(&mut self, other: Grants) {
+    self.roles.extend(other.roles);
+    self.permissions.extend(other.permissions);
+  }
+
+  pub fn roles(&self) -> impl Iterator<Item = &str> {
+    self.roles.iter().map(String::as_str)
+  }
+
+  pub fn permissions(&self) -> impl Iterator<Item = &str> {
+    self.permissions.iter().map(String::as_str)
+  }
+
+  pub fn has_role(&self, role: &str) -> bool {
+    self.roles.contains(role)
+  }
+
+  pub fn has_permission(&self, permission: &str) -> bool {
+    self.permissions.iter().any(|granted| permission_matches(granted, permission))
+  }
+
+  pub fn require_role(&self, role: &str) -> Result<(), Error> {
+    if !self.has_role(role) {
+      return Err(forbidden(&format!("Missing role {}", role)));
+    }
+
+    Ok(())
+  }
+
+  pub fn require_permission(&self, permission: &str) -> Result<(), Error> {
+    if !self.has_permission(permission) {
+      return Err(forbidden(&format!("Missing permission {}", permission)));
+    }
+
+    Ok(())
+  }
+
+  pub fn require_any_permission(&self, permissions: &[&str]) -> Result<(), Error> {
+    if !permissions.iter().any(|permission| self.has_permission(permission)) {
+      return Err(forbidden(&format!("Missing any of permissions {}", permissions.join(", "))));
+    }
+
+    Ok(())
+  }
+}
+
+fn permission_matches(granted: &str, permission: &str) -> bool {
+  if granted == "*" || granted == permission {
+    return true;
+  }
+
+  granted.strip_suffix('*')
+    .is_some_and(|resource| resource.ends_with(':') && permission.starts_with(resource))
+}
+
+fn forbidden(message: &str) -> Error {
+  Error::Forbidden(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IPermissionStore: Send + Sync {
+  async fn grants_for_user(&self, user_id: Uuid) -> Result<Grants, Error>;
+
+  async fn assign_role(&self, user_id: Uuid, role: &str) -> Result<(), Error>;
+
+  async fn remove_role(&self, user_id: Uuid, role: &str) -> Result<(), Error>;
+}
+
+/// `IPermissionStore` backed by the `user_roles` and `role_permissions`
+/// tables from `database::MIGRATOR`.
+#[derive(Clone)]
+pub struct PgPermissionStore {
+  pool: PgPool,
+}
+
+impl PgPermissionStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl IPermissionStore for PgPermissionStore {
+  async fn grants_for_user(&self, user_id: Uuid) -> Result<Grants, Error> {
+    let roles: Vec<String> = sqlx::query_scalar("SELECT role FROM user_roles WHERE user_id = $1")
+      .bind(user_id)
+      .fetch_all(&self.pool)
+      .await?;
+    let permissions: Vec<String> = sqlx::query_scalar(
+      "SELECT DISTINCT role_permissions.permission FROM role_permissions
+       JOIN user_roles ON user_roles.role = role_permissions.role
+       WHERE user_roles.user_id = $1"
+    )
+      .bind(user_id)
+      .fetch_all(&self.pool)
+      .await?;
+
+    Ok(Grants {
+      roles: roles.into_iter().collect(),
+      permissions: permissions.into_iter().collect(),
+    })
+  }
+
+  async fn assign_role(&self, user_id: Uuid, role: &str) -> Result<(), Error> {
+    sqlx::query("INSERT INTO user_roles (user_id, role) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+      .bind(user_id)
+      .bind(role)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn remove_role(&self, user_id: Uuid, role: &str) -> Result<(), Error> {
+    sqlx::query("DELETE FROM user_roles WHERE user_id = $1 AND role = $2")
+      .bind(user_id)
+      .bind(role)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_has_permission_with_wildcards() {
+    // arrange
+    let grants = Grants::new(&[], &["orders:*", "invoices:read"]);
+
+    // assert
+    assert!(grants.has_permission("orders:write"));
+    assert!(grants.has_permission("invoices:read"));
+    assert!(!grants.has_permission("invoices:write"));
+    assert!(!grants.has_permission("ordersx:write"));
+    assert!(Grants::new(&[], &["*"]).has_permission("anything:at-all"));
+  }
+
+  #[test]
+  fn test_require_permission() {
+    // arrange
+    let grants = Grants::new(&["admin"], &["orders:read"]);
+
+    // act
+    let allowed_result = grants.require_permission("orders:read");
+    let denied_result = grants.require_permission("orders:write");
+    let any_result = grants.require_any_permission(&["orders:write", "orders:read"]);
+
+    // assert
+    assert!(allowed_result.is_ok());
+    match denied_result {
+      Err(Error::Forbidden(error)) => assert_eq!(error.message, "Missing permission orders:write"),
+      _ => panic!("Expected Forbidden error"),
+    }
+    assert!(any_result.is_ok());
+  }
+
+  #[test]
+  fn test_require_role() {
+    // arrange
+    let grants = Grants::new(&["admin"], &[]);
+
+    // assert
+    assert!(grants.require_role("admin").is_ok());
+    assert!(matches!(grants.require_role("owner"), Err(Error::Forbidden(_))));
+  }
+
+  #[test]
+  fn test_merge() {
+    // arrange
+    let mut grants = Grants::new(&["member"], &["orders:read"]);
+
+    // act
+    grants.merge(Grants::new(&["admin"], &["orders:write"]));
+
+    // assert
+    assert_eq!(grants.roles().collect::<Vec<_>>(), vec!["admin", "member"]);
+    assert!(grants.has_permission("orders:read"));
+    assert!(grants.has_permission("orders:write"));
+  }
+}
@@ -0,0 +1,372 @@
+use async_trait::async_trait;
+use lambda_http::{
+  aws_lambda_events::apigw::ApiGatewayRequestAuthorizer,
+  http::HeaderMap,
+  request::RequestContext,
+  Request, RequestExt,
+};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::{
+  auth::{alb_oidc_verifier::AlbOidcVerifier, IAuth},
+  error::SerializableError,
+  types::auth::{AuthPayload, AuthUser},
+  Error,
+};
+
+type PrincipalMapper<T> = Box<dyn Fn(&Map<String, Value>) -> Result<T, Error> + Send + Sync>;
+
+/// Authenticates requests an API Gateway JWT/Lambda authorizer or an ALB
+/// OIDC action has already verified, reading the claims it passes along
+/// instead of verifying the token a second time.
+///
+/// Only use this behind a gateway that enforces the authorizer. The ALB
+/// `x-amzn-oidc-data` header is ignored unless `with_alb_verifier` is set.
+pub struct AuthorizerContextAuth<T: AuthPayload = AuthUser> {
+  principal_mapper: PrincipalMapper<T>,
+  alb_verifier: Option<AlbOidcVerifier>,
+  claims: Option<Map<String, Value>>,
+  user: Option<T>,
+}
+
+impl AuthorizerContextAuth {
+  /// Maps `user_details` when the gateway verified one of our own tokens,
+  /// otherwise the standard OIDC claims (`sub`, `given_name`, ...).
+  pub fn new() -> Self {
+    Self::for_payload().with_principal_mapper(auth_user_from_claims)
+  }
+}
+
+impl Default for AuthorizerContextAuth {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<T: AuthPayload> AuthorizerContextAuth<T> {
+  /// Deserializes the principal from the `user_details` claim, or from the
+  /// claims themselves if there is none.
+  pub fn for_payload() -> Self {
+    Self { principal_mapper: Box::new(payload_from_claims), alb_verifier: None, claims: None, user: None }
+  }
+
+  pub fn with_principal_mapper(
+    mut self,
+    principal_mapper: impl Fn(&Map<String, Value>) -> Result<T, Error> + Send + Sync + 'static,
+  ) -> Self {
+    self.principal_mapper = Box::new(principal_mapper);
+
+    self
+  }
+
+  /// Accepts ALB OIDC requests, whose `x-amzn-oidc-data` header is only
+  /// trusted once `alb_verifier` has checked its signature.
+  pub fn with_alb_verifier(mut self, alb_verifier: AlbOidcVerifier) -> Self {
+    self.alb_verifier = Some(alb_verifier);
+
+    self
+  }
+
+  pub fn user(&self) -> Option<T> {
+    self.user.clone()
+  }
+
+  pub fn claims(&self) -> Option<&Map<String, Value>> {
+    self.claims.as_ref()
+  }
+
+  pub fn scopes(&self) -> Vec<String> {
+    match self.claims.as_ref().and_then(|claims| claims.get("scope")) {
+      Some(Value::String(scope)) => scope.split_whitespace().map(str::to_string).collect(),
+      Some(Value::Array(scopes)) => scopes.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+      _ => Vec::new(),
+    }
+  }
+
+  pub fn has_scope(&self, scope: &str) -> bool {
+    self.scopes().iter().any(|granted| granted == scope)
+  }
+
+  fn authenticate_claims(&mut self, claims: Option<Map<String, Value>>) -> Result<(), Error> {
+    let claims = claims.filter(|claims| !claims.is_empty())
+      .ok_or_else(|| unauthorized("Missing authorizer context"))?;
+    let user = (self.principal_mapper)(&claims)?;
+
+    self.claims = Some(claims);
+    self.user = Some(user);
+
+    Ok(())
+  }
+
+  /// ALB OIDC actions pass the user claims as a signed JWT in
+  /// `x-amzn-oidc-data`.
+  async fn alb_claims(&self, headers: &HeaderMap) -> Result<Option<Map<String, Value>>, Error> {
+    let (Some(alb_verifier), Some(header_value)) = (&self.alb_verifier, headers.get("x-amzn-oidc-data")) else {
+      return Ok(None);
+    };
+
+    let token = header_value.to_str().map_err(|_| unauthorized("Malformed x-amzn-oidc-data header"))?;
+
+    alb_verifier.verify(token).await.map(Some)
+  }
+}
+
+#[async_trait]
+impl<T: AuthPayload> IAuth for AuthorizerContextAuth<T> {
+  /// Headers only carry the ALB OIDC claims; use `authenticate_request` for
+  /// API Gateway authorizers.
+  async fn authenticate(&mut self, headers: &HeaderMap) -> Result<(), Error> {
+    let claims = self.alb_claims(headers).await?;
+
+    self.authenticate_claims(claims)
+  }
+
+  async fn authenticate_request(&mut self, request: &Request) -> Result<(), Error> {
+    let claims = match request.request_context_ref() {
+      Some(RequestContext::ApiGatewayV1(context)) => rest_claims(&context.authorizer),
+      Some(RequestContext::WebSocket(context)) => rest_claims(&context.authorizer),
+      Some(RequestContext::ApiGatewayV2(context)) => context.authorizer.as_ref().and_then(http_api_claims),
+      Some(RequestContext::Alb(_)) => self.alb_claims(request.headers()).await?,
+      _ => None,
+    };
+
+    self.authenticate_claims(claims)
+  }
+}
+
+/// REST and WebSocket APIs: Cognito authorizers nest the token claims under
+/// `claims`, Lambda authorizers pass their context fields flat.
+fn rest_claims(authorizer: &ApiGatewayRequestAuthorizer) -> Option<Map<String, Value>> {
+  if let Some(Value::Object(claims)) = authorizer.fields.get("claims") {
+    return Some(claims.clone());
+  }
+
+  let mut claims: Map<String, Value> = authorizer.fields.clone().into_iter().collect();
+  if !claims.contains_key("sub")
+    && let Some(principal_id) = claims.get("principalId").cloned() {
+    claims.insert("sub".to_string(), principal_id);
+  }
+
+  Some(claims)
+}
+
+/// HTTP APIs: JWT authorizers pass string claims plus scopes, Lambda
+/// authorizers their context under `lambda`.
+fn http_api_claims(authorizer: &ApiGatewayRequestAuthorizer) -> Option<Map<String, Value>> {
+  if let Some(jwt) = authorizer.jwt.as_ref() {
+    let mut claims: Map<String, Value> = jwt.claims.iter()
+      .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+      .collect();
+
+    if let Some(scopes) = jwt.scopes.as_ref() {
+      claims.insert("scope".to_string(), Value::String(scopes.join(" ")));
+    }
+
+    return Some(claims);
+  }
+
+  Some(authorizer.fields.clone().into_iter().collect())
+}
+
+fn payload_from_claims<T: AuthPayload>(claims: &Map<String, Value>) -> Result<T, Error> {
+  let value = match claims.get("user_details") {
+    // JWT authorizers flatten nested claims to JSON strings.
+    Some(Value::String(user_details)) => serde_json::from_str(user_details)
+      .map_err(|_| unauthorized("Malformed user_details claim"))?,
+    Some(user_details) => user_details.clone(),
+    None => Value::Object(claims.clone()),
+  };
+
+  serde_json::from_value(value).map_err(|error| unauthorized(&format!("Invalid authorizer claims: {}", error)))
+}
+
+fn auth_user_from_claims(claims: &Map<String, Value>) -> Result<AuthUser, Error> {
+  if claims.contains_key("user_details") {
+    return payload_from_claims(claims);
+  }
+
+  let claim = |names: &[&str]| names.iter()
+    .find_map(|name| claims.get(*name).and_then(Value::as_str))
+    .map(str::to_string);
+
+  let id = claim(&["sub"])
+    .and_then(|subject| Uuid::parse_str(&subject).ok())
+    .ok_or_else(|| unauthorized("Authorizer subject is not a valid user id"))?;
+
+  Ok(AuthUser {
+    id,
+    first_name: claim(&["given_name", "first_name"]).unwrap_or_default(),
+    middle_name: claim(&["middle_name"]),
+    last_name: claim(&["family_name", "last_name"]).unwrap_or_default(),
+    email: claim(&["email"]).unwrap_or_default(),
+  })
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  use lambda_http::aws_lambda_events::apigw::{
+    ApiGatewayProxyRequestContext, ApiGatewayRequestAuthorizerJwtDescription,
+    ApiGatewayV2httpRequestContext,
+  };
+
+  use chrono::Utc;
+
+  use crate::auth::{
+    alb_oidc_verifier::tests::{alb_key_endpoint, alb_oidc_token, ALB_SIGNER},
+    http_client::MockIHttpClient,
+  };
+
+  fn http_api_request(authorizer: ApiGatewayRequestAuthorizer) -> Request {
+    Request::default().with_request_context(RequestContext::ApiGatewayV2(ApiGatewayV2httpRequestContext {
+      authorizer: Some(authorizer),
+      ..Default::default()
+    }))
+  }
+
+  #[tokio::test]
+  async fn test_http_api_jwt_authorizer() {
+    // arrange
+    let user_id = Uuid::new_v4();
+    let request = http_api_request(ApiGatewayRequestAuthorizer {
+      jwt: Some(ApiGatewayRequestAuthorizerJwtDescription {
+        claims: HashMap::from([
+          ("sub".to_string(), user_id.to_string()),
+          ("given_name".to_string(), "John".to_string()),
+          ("family_name".to_string(), "Doe".to_string()),
+          ("email".to_string(), "john.doe@example.com".to_string()),
+        ]),
+        scopes: Some(vec!["orders:read".to_string()]),
+      }),
+      ..Default::default()
+    });
+
+    // act
+    let mut auth = AuthorizerContextAuth::new();
+    let result = auth.authenticate_request(&request).await;
+
+    // assert
+    assert!(result.is_ok());
+    let user = auth.user().unwrap();
+    assert_eq!(user.id, user_id);
+    assert_eq!(user.first_name, "John");
+    assert_eq!(user.email, "john.doe@example.com");
+    assert!(auth.has_scope("orders:read"));
+  }
+
+  #[tokio::test]
+  async fn test_rest_lambda_authorizer_with_user_details() {
+    // arrange
+    let user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+    let request = Request::default().with_request_context(RequestContext::ApiGatewayV1(ApiGatewayProxyRequestContext {
+      authorizer: ApiGatewayRequestAuthorizer {
+        fields: HashMap::from([
+          ("principalId".to_string(), Value::String(user.id.to_string())),
+          ("user_details".to_string(), Value::String(serde_json::to_string(&user).unwrap())),
+        ]),
+        ..Default::default()
+      },
+      ..Default::default()
+    }));
+
+    // act
+    let mut auth = AuthorizerContextAuth::new();
+    let result = auth.authenticate_request(&request).await;
+
+    // assert
+    assert!(result.is_ok());
+    assert_eq!(auth.user(), Some(user.clone()));
+    assert_eq!(auth.claims().unwrap().get("sub"), Some(&Value::String(user.id.to_string())));
+  }
+
+  #[tokio::test]
+  async fn test_alb_oidc_header() {
+    // arrange
+    let user_id = Uuid::new_v4();
+    let token = alb_oidc_token(serde_json::json!({
+      "sub": user_id.to_string(),
+      "email": "john.doe@example.com",
+      "exp": Utc::now().timestamp() + 60,
+    }), ALB_SIGNER);
+    let mut headers = HeaderMap::new();
+    headers.insert("x-amzn-oidc-data", token.parse().unwrap());
+
+    // act
+    let mut auth = AuthorizerContextAuth::new()
+      .with_alb_verifier(AlbOidcVerifier::new(Box::new(alb_key_endpoint()), ALB_SIGNER));
+    let result = auth.authenticate(&headers).await;
+
+    // assert
+    assert!(result.is_ok());
+    assert_eq!(auth.user().unwrap().id, user_id);
+  }
+
+  #[tokio::test]
+  async fn test_alb_oidc_header_is_ignored_without_verifier_or_alb_context() {
+    // arrange
+    let token = alb_oidc_token(serde_json::json!({
+      "sub": Uuid::new_v4().to_string(),
+      "exp": Utc::now().timestamp() + 60,
+    }), ALB_SIGNER);
+    let mut request = Request::default();
+    request.headers_mut().insert("x-amzn-oidc-data", token.parse().unwrap());
+    let mut http_client = MockIHttpClient::new();
+    http_client.expect_send().never();
+
+    // act
+    let mut auth = AuthorizerContextAuth::new();
+    let unverified_result = auth.authenticate(request.headers()).await;
+    let mut verifying_auth = AuthorizerContextAuth::new()
+      .with_alb_verifier(AlbOidcVerifier::new(Box::new(http_client), ALB_SIGNER));
+    let missing_context_result = verifying_auth.authenticate_request(&request).await;
+
+    // assert
+    assert!(matches!(unverified_result, Err(Error::Unauthorized(_))));
+    assert!(matches!(missing_context_result, Err(Error::Unauthorized(_))));
+    assert_eq!(auth.user(), None);
+    assert_eq!(verifying_auth.user(), None);
+  }
+
+  #[tokio::test]
+  async fn test_missing_authorizer_context() {
+    // act
+    let mut auth = AuthorizerContextAuth::new();
+    let result = auth.authenticate_request(&Request::default()).await;
+
+    // assert
+    match result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Missing authorizer context"),
+      _ => panic!("Expected Unauthorized error"),
+    }
+    assert_eq!(auth.user(), None);
+  }
+
+  #[tokio::test]
+  async fn test_invalid_subject() {
+    // arrange
+    let request = http_api_request(ApiGatewayRequestAuthorizer {
+      fields: HashMap::from([("sub".to_string(), Value::String("not-a-uuid".to_string()))]),
+      ..Default::default()
+    });
+
+    // act
+    let mut auth = AuthorizerContextAuth::new();
+    let result = auth.authenticate_request(&request).await;
+
+    // assert
+    assert!(matches!(result, Err(Error::Unauthorized(_))));
+  }
+}
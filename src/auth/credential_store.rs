@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{types::auth::AuthUser, Error};
+
+/// A user row together with its password hash.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct UserCredentials {
+  pub id: Uuid,
+  pub first_name: String,
+  pub middle_name: Option<String>,
+  pub last_name: String,
+  pub email: String,
+  pub password_hash: Option<String>,
+}
+
+impl UserCredentials {
+  pub fn user(&self) -> AuthUser {
+    AuthUser {
+      id: self.id,
+      first_name: self.first_name.clone(),
+      middle_name: self.middle_name.clone(),
+      last_name: self.last_name.clone(),
+      email: self.email.clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait ICredentialStore: Send + Sync {
+  /// Looks the user up by email, case-insensitively.
+  async fn find_by_email(&self, email: &str) -> Result<Option<UserCredentials>, Error>;
+
+  async fn update_password_hash(&self, user_id: Uuid, password_hash: &str) -> Result<(), Error>;
+}
+
+/// `ICredentialStore` backed by the application's `users` table, which
+/// `database::MIGRATOR` doesn't create. It needs at least these columns:
+///
+/// ```sql
+/// id UUID PRIMARY KEY,
+/// first_name TEXT NOT NULL,
+/// middle_name TEXT,
+/// last_name TEXT NOT NULL,
+/// email TEXT NOT NULL,
+/// password_hash TEXT,
+/// password_updated_at TIMESTAMPTZ,
+/// deactivated_at TIMESTAMPTZ
+/// ```
+///
+/// Emails are looked up by `LOWER(email)`, so index that expression.
+#[derive(Clone)]
+pub struct PgCredentialStore {
+  pool: PgPool,
+}
+
+impl PgCredentialStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl ICredentialStore for PgCredentialStore {
+  async fn find_by_email(&self, email: &str) -> Result<Option<UserCredentials>, Error> {
+    Ok(sqlx::query_as(
+      "SELECT id, first_name, middle_name, last_name, email, password_hash
+       FROM users WHERE LOWER(email) = LOWER($1) AND deactivated_at IS NULL"
+    )
+      .bind(email)
+      .fetch_optional(&self.pool)
+      .await?)
+  }
+
+  async fn update_password_hash(&self, user_id: Uuid, password_hash: &str) -> Result<(), Error> {
+    sqlx::query("UPDATE users SET password_hash = $2, password_updated_at = NOW() WHERE id = $1")
+      .bind(user_id)
+      .bind(password_hash)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailMessage {
+  pub to: String,
+  pub subject: String,
+  pub text_body: String,
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IEmailSender: Send + Sync {
+  async fn send(&self, message: &EmailMessage) -> Result<(), Error>;
+}
+
+/// Keeps sent messages in memory instead of delivering them, for tests and
+/// local development. Clones share the same messages.
+#[derive(Clone, Default)]
+pub struct InMemoryEmailSender {
+  messages: Arc<Mutex<Vec<EmailMessage>>>,
+}
+
+impl InMemoryEmailSender {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn messages(&self) -> Vec<EmailMessage> {
+    self.messages.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+  }
+
+  pub fn last_message_to(&self, to: &str) -> Option<EmailMessage> {
+    self.messages().into_iter().rev().find(|message| message.to == to)
+  }
+}
+
+#[async_trait]
+impl IEmailSender for InMemoryEmailSender {
+  async fn send(&self, message: &EmailMessage) -> Result<(), Error> {
+    self.messages.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(message.clone());
+
+    Ok(())
+  }
+}
@@ -0,0 +1,15 @@
+use async_trait::async_trait;
+use lambda_http::http::{Request, Response};
+
+use crate::Error;
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+/// Outbound HTTP used to talk to identity providers. Implement it over the
+/// HTTP client of your choice; transport failures should surface as
+/// `Error::IdentityProvider`.
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IHttpClient: Send + Sync {
+  async fn send(&self, request: Request<String>) -> Result<Response<String>, Error>;
+}
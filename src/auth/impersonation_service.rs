@@ -0,0 +1,414 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+  auth::{
+    authorization::IPermissionStore,
+    impersonation_store::{IImpersonationStore, ImpersonationRecord},
+    revocation_store::IRevocationStore,
+    token_issuer::TokenIssuer,
+    Auth,
+  },
+  error::SerializableError,
+  types::{
+    auth::{AuthPayload, AuthUser, IssuedToken},
+    utils::jwt_util::TokenType,
+  },
+  utils::jwt_util::IJwtUtil,
+  Error,
+};
+
+/// Lets staff act as another user. Issues `Impersonation` tokens naming the
+/// staff member in the `act` claim and audits every impersonation.
+///
+/// Impersonation tokens get no refresh token and never outlive the token
+/// of the user who started them. Users who may impersonate others, or who
+/// hold a protected role, can't be impersonated; their grants come from
+/// `permission_store`.
+pub struct ImpersonationService<T: AuthPayload = AuthUser> {
+  token_issuer: TokenIssuer<T>,
+  impersonation_store: Box<dyn IImpersonationStore>,
+  permission_store: Box<dyn IPermissionStore>,
+  revocation_store: Option<Box<dyn IRevocationStore>>,
+  required_permission: String,
+  protected_roles: Vec<String>,
+}
+
+impl ImpersonationService {
+  pub fn new(
+    jwt_util: Box<dyn IJwtUtil>,
+    impersonation_store: Box<dyn IImpersonationStore>,
+    permission_store: Box<dyn IPermissionStore>,
+  ) -> Self {
+    Self::for_payload(jwt_util, impersonation_store, permission_store)
+  }
+}
+
+impl<T: AuthPayload> ImpersonationService<T> {
+  pub fn for_payload(
+    jwt_util: Box<dyn IJwtUtil<T>>,
+    impersonation_store: Box<dyn IImpersonationStore>,
+    permission_store: Box<dyn IPermissionStore>,
+  ) -> Self {
+    Self::from_issuer(TokenIssuer::for_payload(jwt_util), impersonation_store, permission_store)
+  }
+
+  /// Uses an already configured issuer, e.g. one with a shorter
+  /// `Impersonation` lifetime than the default 10 minutes.
+  pub fn from_issuer(
+    token_issuer: TokenIssuer<T>,
+    impersonation_store: Box<dyn IImpersonationStore>,
+    permission_store: Box<dyn IPermissionStore>,
+  ) -> Self {
+    Self {
+      token_issuer,
+      impersonation_store,
+      permission_store,
+      revocation_store: None,
+      required_permission: "users:impersonate".to_string(),
+      protected_roles: Vec::new(),
+    }
+  }
+
+  /// Stamps tokens with the user's token version and lets `end` revoke the
+  /// token right away instead of only recording the end. `Auth` needs the
+  /// same store to enforce it.
+  pub fn with_revocation_store(mut self, revocation_store: Box<dyn IRevocationStore>) -> Self {
+    self.revocation_store = Some(revocation_store);
+
+    self
+  }
+
+  /// Permission the acting user needs, `users:impersonate` by default.
+  pub fn with_required_permission(mut self, required_permission: &str) -> Self {
+    self.required_permission = required_permission.to_string();
+
+    self
+  }
+
+  /// Roles whose holders can't be impersonated, e.g. `admin`, on top of
+  /// users with the required permission.
+  pub fn with_protected_roles(mut self, protected_roles: &[&str]) -> Self {
+    self.protected_roles = protected_roles.iter().map(|role| role.to_string()).collect();
+
+    self
+  }
+
+  /// Issues a token for acting as `user` on behalf of the user
+  /// authenticated by `auth`.
+  pub async fn start(&self, auth: &Auth<T>, user: &T, reason: &str) -> Result<IssuedToken, Error> {
+    let actor_claims = auth.claims().ok_or_else(|| unauthorized("Not authenticated"))?;
+
+    if auth.is_impersonating() {
+      return Err(forbidden("Cannot impersonate while impersonating"));
+    }
+
+    auth.require_permission(&self.required_permission)?;
+
+    let actor = &actor_claims.user_details;
+    if actor.user_id() == user.user_id() {
+      return Err(forbidden("Cannot impersonate yourself"));
+    }
+
+    if reason.trim().is_empty() {
+      return Err(forbidden("A reason is required to impersonate a user"));
+    }
+
+    let user_grants = self.permission_store.grants_for_user(user.user_id()).await?;
+    if user_grants.has_permission(&self.required_permission)
+      || self.protected_roles.iter().any(|role| user_grants.has_role(role)) {
+      return Err(forbidden("Cannot impersonate a privileged user"));
+    }
+
+    let token_version = match self.revocation_store.as_deref() {
+      Some(revocation_store) => Some(revocation_store.token_version(user.user_id()).await?),
+      None => None,
+    };
+    let mut claims = self.token_issuer.claims(user, TokenType::Impersonation)
+      .with_actor(actor)
+      .with_token_version(token_version);
+    claims.expires_in = claims.expires_in.min(actor_claims.expires_in);
+    let issued_token = self.token_issuer.sign(claims)?;
+
+    self.impersonation_store.start(&ImpersonationRecord {
+      id: issued_token.jti.clone(),
+      actor_id: actor.user_id(),
+      user_id: user.user_id(),
+      reason: reason.trim().to_string(),
+      started_at: timestamp(issued_token.issued_at),
+      expires_at: timestamp(issued_token.expires_at),
+      ended_at: None,
+    }).await?;
+
+    Ok(issued_token)
+  }
+
+  /// Ends the impersonation `auth` was authenticated with.
+  pub async fn end(&self, auth: &Auth<T>) -> Result<(), Error> {
+    let claims = auth.claims()
+      .filter(|_| auth.is_impersonating())
+      .ok_or_else(|| unauthorized("Not impersonating"))?;
+    let jti = claims.jti.as_deref().ok_or_else(|| unauthorized("Impersonation token has no token id"))?;
+
+    self.impersonation_store.end(jti).await?;
+
+    if let Some(revocation_store) = self.revocation_store.as_deref() {
+      revocation_store.revoke(jti, claims.user_details.user_id(), claims.expires_in).await?;
+    }
+
+    Ok(())
+  }
+}
+
+fn timestamp(seconds: usize) -> DateTime<Utc> {
+  DateTime::from_timestamp(seconds as i64, 0).unwrap_or_default()
+}
+
+fn forbidden(message: &str) -> Error {
+  Error::Forbidden(SerializableError { message: message.to_string() })
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::Duration;
+  use lambda_http::http::HeaderMap;
+  use mockall::predicate::eq;
+  use uuid::Uuid;
+
+  use crate::{
+    auth::{
+      authorization::{Grants, MockIPermissionStore},
+      impersonation_store::MockIImpersonationStore,
+      revocation_store::MockIRevocationStore,
+      IAuth,
+    },
+    utils::JwtUtil,
+  };
+
+  fn user(email: &str) -> AuthUser {
+    AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: email.to_string(),
+    }
+  }
+
+  async fn authenticate(token: &str) -> Auth {
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", format!("Watashiwasta {}", token).parse().unwrap());
+    let mut auth = Auth::new(Box::new(JwtUtil::new("some_key")));
+    auth.authenticate(&headers).await.unwrap();
+
+    auth
+  }
+
+  async fn staff_auth(staff: &AuthUser, permissions: &[&str], lifetime: Duration) -> Auth {
+    let token_issuer = TokenIssuer::new(Box::new(JwtUtil::new("some_key")))
+      .with_lifetime(TokenType::AccessToken, lifetime);
+    let claims = token_issuer.claims(staff, TokenType::AccessToken).with_permissions(permissions);
+
+    authenticate(&token_issuer.sign(claims).unwrap().token).await
+  }
+
+  /// A permission store where nobody has any roles or permissions.
+  fn permission_store() -> MockIPermissionStore {
+    let mut permission_store = MockIPermissionStore::new();
+    permission_store.expect_grants_for_user().returning(|_| Ok(Grants::default()));
+
+    permission_store
+  }
+
+  fn error_message(result: Result<IssuedToken, Error>) -> String {
+    match result {
+      Err(Error::Forbidden(error)) => error.message,
+      _ => panic!("Expected Forbidden error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_start() {
+    // arrange
+    let staff = user("support@example.com");
+    let customer = user("john.doe@example.com");
+    let staff_auth = staff_auth(&staff, &["users:impersonate"], Duration::minutes(15)).await;
+    let (staff_id, customer_id) = (staff.id, customer.id);
+    let mut impersonation_store = MockIImpersonationStore::new();
+    impersonation_store.expect_start()
+      .withf(move |record| {
+        record.actor_id == staff_id && record.user_id == customer_id && record.reason == "Ticket #4521"
+      })
+      .times(1)
+      .returning(|_| Ok(()));
+    let service = ImpersonationService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(impersonation_store),
+      Box::new(permission_store()),
+    );
+
+    // act
+    let issued_token = service.start(&staff_auth, &customer, " Ticket #4521 ").await.unwrap();
+
+    // assert
+    let auth = authenticate(&issued_token.token).await;
+    assert_eq!(issued_token.token_type, TokenType::Impersonation);
+    assert_eq!(issued_token.expires_in, Duration::minutes(10).num_seconds());
+    assert!(auth.is_impersonating());
+    assert_eq!(auth.user(), Some(customer));
+    assert_eq!(auth.actor(), Some(staff));
+  }
+
+  #[tokio::test]
+  async fn test_start_never_outlives_actor_token() {
+    // arrange
+    let staff_auth = staff_auth(&user("support@example.com"), &["users:impersonate"], Duration::minutes(2)).await;
+    let mut impersonation_store = MockIImpersonationStore::new();
+    impersonation_store.expect_start().returning(|_| Ok(()));
+    let service = ImpersonationService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(impersonation_store),
+      Box::new(permission_store()),
+    );
+
+    // act
+    let issued_token = service.start(&staff_auth, &user("john.doe@example.com"), "Ticket #4521").await.unwrap();
+
+    // assert
+    assert_eq!(issued_token.expires_at, staff_auth.claims().unwrap().expires_in);
+  }
+
+  #[tokio::test]
+  async fn test_start_requires_permission() {
+    // arrange
+    let staff_auth = staff_auth(&user("support@example.com"), &[], Duration::minutes(15)).await;
+    let service = ImpersonationService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(MockIImpersonationStore::new()),
+      Box::new(permission_store()),
+    );
+
+    // act
+    let result = service.start(&staff_auth, &user("john.doe@example.com"), "Ticket #4521").await;
+
+    // assert
+    assert_eq!(error_message(result), "Missing permission users:impersonate");
+  }
+
+  #[tokio::test]
+  async fn test_start_rejects_nested_impersonation() {
+    // arrange
+    let staff = user("support@example.com");
+    let mut impersonation_store = MockIImpersonationStore::new();
+    impersonation_store.expect_start().times(1).returning(|_| Ok(()));
+    let service = ImpersonationService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(impersonation_store),
+      Box::new(permission_store()),
+    );
+    let staff_auth = staff_auth(&staff, &["users:impersonate"], Duration::minutes(15)).await;
+    let issued_token = service.start(&staff_auth, &user("john.doe@example.com"), "Ticket #4521").await.unwrap();
+    let impersonation_auth = authenticate(&issued_token.token).await;
+
+    // act
+    let result = service.start(&impersonation_auth, &user("jane.doe@example.com"), "Ticket #4521").await;
+
+    // assert
+    assert_eq!(error_message(result), "Cannot impersonate while impersonating");
+  }
+
+  #[tokio::test]
+  async fn test_start_rejects_privileged_users() {
+    // arrange
+    let staff_auth = staff_auth(&user("support@example.com"), &["users:impersonate"], Duration::minutes(15)).await;
+    let (other_staff, admin, customer) = (user("help@example.com"), user("admin@example.com"), user("john.doe@example.com"));
+    let (other_staff_id, admin_id) = (other_staff.id, admin.id);
+    let mut permission_store = MockIPermissionStore::new();
+    permission_store.expect_grants_for_user().returning(move |user_id| Ok(match user_id {
+      user_id if user_id == other_staff_id => Grants::new(&[], &["users:*"]),
+      user_id if user_id == admin_id => Grants::new(&["admin"], &[]),
+      _ => Grants::default(),
+    }));
+    let mut impersonation_store = MockIImpersonationStore::new();
+    impersonation_store.expect_start().times(1).returning(|_| Ok(()));
+    let service = ImpersonationService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(impersonation_store),
+      Box::new(permission_store),
+    ).with_protected_roles(&["admin"]);
+
+    // act
+    let other_staff_result = service.start(&staff_auth, &other_staff, "Ticket #4521").await;
+    let admin_result = service.start(&staff_auth, &admin, "Ticket #4521").await;
+    let customer_result = service.start(&staff_auth, &customer, "Ticket #4521").await;
+
+    // assert
+    assert_eq!(error_message(other_staff_result), "Cannot impersonate a privileged user");
+    assert_eq!(error_message(admin_result), "Cannot impersonate a privileged user");
+    assert!(customer_result.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_start_stamps_token_version() {
+    // arrange
+    let staff_auth = staff_auth(&user("support@example.com"), &["users:impersonate"], Duration::minutes(15)).await;
+    let customer = user("john.doe@example.com");
+    let customer_id = customer.id;
+    let mut impersonation_store = MockIImpersonationStore::new();
+    impersonation_store.expect_start().returning(|_| Ok(()));
+    let mut revocation_store = MockIRevocationStore::new();
+    revocation_store.expect_token_version().with(eq(customer_id)).returning(|_| Ok(2));
+    let service = ImpersonationService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(impersonation_store),
+      Box::new(permission_store()),
+    ).with_revocation_store(Box::new(revocation_store));
+    let mut auth_revocation_store = MockIRevocationStore::new();
+    auth_revocation_store.expect_is_revoked().returning(|_| Ok(false));
+    auth_revocation_store.expect_token_version().returning(|_| Ok(2));
+    let mut headers = HeaderMap::new();
+    let mut auth = Auth::new(Box::new(JwtUtil::new("some_key"))).with_revocation_store(Box::new(auth_revocation_store));
+
+    // act
+    let issued_token = service.start(&staff_auth, &customer, "Ticket #4521").await.unwrap();
+    headers.insert("Authorization", format!("Watashiwasta {}", issued_token.token).parse().unwrap());
+    let result = auth.authenticate(&headers).await;
+
+    // assert
+    assert!(result.is_ok());
+    assert_eq!(auth.claims().unwrap().token_version, Some(2));
+  }
+
+  #[tokio::test]
+  async fn test_end_revokes_token() {
+    // arrange
+    let mut impersonation_store = MockIImpersonationStore::new();
+    impersonation_store.expect_start().returning(|_| Ok(()));
+    impersonation_store.expect_end().times(1).returning(|_| Ok(()));
+    let staff_auth = staff_auth(&user("support@example.com"), &["users:impersonate"], Duration::minutes(15)).await;
+    let customer = user("john.doe@example.com");
+    let service = ImpersonationService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(impersonation_store),
+      Box::new(permission_store()),
+    );
+    let issued_token = service.start(&staff_auth, &customer, "Ticket #4521").await.unwrap();
+    let impersonation_auth = authenticate(&issued_token.token).await;
+    let mut revocation_store = MockIRevocationStore::new();
+    revocation_store.expect_revoke()
+      .with(eq(issued_token.jti.clone()), eq(customer.id), eq(issued_token.expires_at))
+      .times(1)
+      .returning(|_, _, _| Ok(()));
+    let service = service.with_revocation_store(Box::new(revocation_store));
+
+    // act
+    let result = service.end(&impersonation_auth).await;
+
+    // assert
+    assert!(result.is_ok());
+  }
+}
@@ -0,0 +1,100 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::Error;
+
+/// Audit record of one impersonation, keyed by the `jti` of the token
+/// issued for it.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ImpersonationRecord {
+  pub id: String,
+  pub actor_id: Uuid,
+  pub user_id: Uuid,
+  pub reason: String,
+  pub started_at: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+  pub ended_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IImpersonationStore: Send + Sync {
+  async fn start(&self, record: &ImpersonationRecord) -> Result<(), Error>;
+
+  async fn end(&self, id: &str) -> Result<(), Error>;
+
+  /// Impersonations of the user, newest first.
+  async fn list_for_user(&self, user_id: Uuid) -> Result<Vec<ImpersonationRecord>, Error>;
+
+  /// Impersonations started by the actor, newest first.
+  async fn list_for_actor(&self, actor_id: Uuid) -> Result<Vec<ImpersonationRecord>, Error>;
+}
+
+const IMPERSONATION_COLUMNS: &str = "id, actor_id, user_id, reason, started_at, expires_at, ended_at";
+
+/// `IImpersonationStore` backed by the `impersonations` table from
+/// `database::MIGRATOR`.
+#[derive(Clone)]
+pub struct PgImpersonationStore {
+  pool: PgPool,
+}
+
+impl PgImpersonationStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl IImpersonationStore for PgImpersonationStore {
+  async fn start(&self, record: &ImpersonationRecord) -> Result<(), Error> {
+    sqlx::query(&format!(
+      "INSERT INTO impersonations ({}) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+      IMPERSONATION_COLUMNS
+    ))
+      .bind(&record.id)
+      .bind(record.actor_id)
+      .bind(record.user_id)
+      .bind(&record.reason)
+      .bind(record.started_at)
+      .bind(record.expires_at)
+      .bind(record.ended_at)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn end(&self, id: &str) -> Result<(), Error> {
+    sqlx::query("UPDATE impersonations SET ended_at = NOW() WHERE id = $1 AND ended_at IS NULL")
+      .bind(id)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn list_for_user(&self, user_id: Uuid) -> Result<Vec<ImpersonationRecord>, Error> {
+    Ok(sqlx::query_as(&format!(
+      "SELECT {} FROM impersonations WHERE user_id = $1 ORDER BY started_at DESC",
+      IMPERSONATION_COLUMNS
+    ))
+      .bind(user_id)
+      .fetch_all(&self.pool)
+      .await?)
+  }
+
+  async fn list_for_actor(&self, actor_id: Uuid) -> Result<Vec<ImpersonationRecord>, Error> {
+    Ok(sqlx::query_as(&format!(
+      "SELECT {} FROM impersonations WHERE actor_id = $1 ORDER BY started_at DESC",
+      IMPERSONATION_COLUMNS
+    ))
+      .bind(actor_id)
+      .fetch_all(&self.pool)
+      .await?)
+  }
+}
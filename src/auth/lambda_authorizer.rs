@@ -0,0 +1,404 @@
+use lambda_http::{
+  aws_lambda_events::{
+    apigw::{
+      ApiGatewayCustomAuthorizerPolicy, ApiGatewayCustomAuthorizerRequest,
+      ApiGatewayCustomAuthorizerRequestTypeRequest, ApiGatewayCustomAuthorizerResponse,
+      ApiGatewayV2CustomAuthorizerSimpleResponse, ApiGatewayV2CustomAuthorizerV2Request,
+    },
+    iam::{IamPolicyEffect, IamPolicyStatement},
+    query_map::QueryMap,
+  },
+  http::{HeaderMap, HeaderValue},
+};
+use serde_json::{Map, Value};
+
+use crate::{
+  auth::{Auth, TokenSource},
+  error::SerializableError,
+  types::auth::{AuthPayload, AuthUser},
+  Error,
+};
+
+/// Turns API Gateway Lambda authorizer events into policies using `Auth`.
+///
+/// Callers with a missing, invalid or insufficient token get a `Deny`
+/// policy (403 from the gateway); any other error is returned so the
+/// invocation fails (500).
+/// The principal's fields are passed as context, plus `user_details` as a
+/// JSON string that `AuthorizerContextAuth` reads back in the backend.
+pub struct LambdaAuthorizer<T: AuthPayload = AuthUser> {
+  auth: Auth<T>,
+  method_scope: bool,
+}
+
+impl<T: AuthPayload> LambdaAuthorizer<T> {
+  pub fn new(auth: Auth<T>) -> Self {
+    Self { auth, method_scope: false }
+  }
+
+  /// Allows only the invoked method instead of the whole stage. API Gateway
+  /// caches policies per token, so only use this with caching disabled.
+  pub fn with_method_scope(mut self) -> Self {
+    self.method_scope = true;
+
+    self
+  }
+
+  pub fn auth(&self) -> &Auth<T> {
+    &self.auth
+  }
+
+  /// `TOKEN` authorizers, where the identity source header is passed as
+  /// `authorizationToken`.
+  pub async fn authorize_token(
+    &mut self,
+    event: &ApiGatewayCustomAuthorizerRequest,
+  ) -> Result<ApiGatewayCustomAuthorizerResponse, Error> {
+    let mut headers = HeaderMap::new();
+    if let Some(authorization_token) = event.authorization_token.as_deref()
+      && let Ok(header_value) = HeaderValue::from_str(authorization_token) {
+      headers.insert("Authorization", header_value);
+    }
+
+    let result = self.auth.authenticate_source(TokenSource::from_headers(&headers)).await;
+
+    self.policy_response(result, event.method_arn.as_deref())
+  }
+
+  /// `REQUEST` authorizers of REST and WebSocket APIs.
+  pub async fn authorize_request(
+    &mut self,
+    event: &ApiGatewayCustomAuthorizerRequestTypeRequest,
+  ) -> Result<ApiGatewayCustomAuthorizerResponse, Error> {
+    let source = TokenSource { headers: &event.headers, query_parameters: Some(&event.query_string_parameters) };
+    let result = self.auth.authenticate_source(source).await;
+
+    self.policy_response(result, event.method_arn.as_deref())
+  }
+
+  /// HTTP API authorizers using the simple response format (payload
+  /// version 2.0 with `enableSimpleResponses`).
+  pub async fn authorize_http_api(
+    &mut self,
+    event: &ApiGatewayV2CustomAuthorizerV2Request,
+  ) -> Result<ApiGatewayV2CustomAuthorizerSimpleResponse, Error> {
+    let mut headers = event.headers.clone();
+    // Payload 2.0 moves cookies out of the headers.
+    if !event.cookies.is_empty()
+      && let Ok(cookie) = HeaderValue::from_str(&event.cookies.join("; ")) {
+      headers.insert("Cookie", cookie);
+    }
+
+    let query_parameters = QueryMap::from(event.query_string_parameters.clone());
+    let source = TokenSource { headers: &headers, query_parameters: Some(&query_parameters) };
+
+    match self.auth.authenticate_source(source).await {
+      Ok(()) => Ok(ApiGatewayV2CustomAuthorizerSimpleResponse { is_authorized: true, context: self.context()? }),
+      Err(error) if is_denied(&error) => Ok(ApiGatewayV2CustomAuthorizerSimpleResponse {
+        is_authorized: false,
+        context: Value::Object(Map::new()),
+      }),
+      Err(error) => Err(error),
+    }
+  }
+
+  fn policy_response(
+    &self,
+    result: Result<(), Error>,
+    method_arn: Option<&str>,
+  ) -> Result<ApiGatewayCustomAuthorizerResponse, Error> {
+    let method_arn = method_arn.ok_or_else(|| Error::Unhandled(SerializableError {
+      message: "Missing methodArn in authorizer event".to_string()
+    }))?;
+
+    let (principal_id, effect, context) = match result {
+      Ok(()) => {
+        let principal_id = self.auth.user().map(|user| user.user_id().to_string());
+        (principal_id, IamPolicyEffect::Allow, self.context()?)
+      },
+      Err(error) if is_denied(&error) => {
+        (Some("anonymous".to_string()), IamPolicyEffect::Deny, Value::Object(Map::new()))
+      },
+      Err(error) => return Err(error),
+    };
+
+    let resource = match effect {
+      IamPolicyEffect::Allow if !self.method_scope => stage_resource(method_arn),
+      _ => method_arn.to_string(),
+    };
+
+    Ok(ApiGatewayCustomAuthorizerResponse {
+      principal_id,
+      policy_document: ApiGatewayCustomAuthorizerPolicy {
+        version: Some("2012-10-17".to_string()),
+        statement: vec![IamPolicyStatement {
+          action: vec!["execute-api:Invoke".to_string()],
+          effect,
+          resource: vec![resource],
+          condition: None,
+        }],
+      },
+      context,
+      usage_identifier_key: None,
+    })
+  }
+
+  /// API Gateway only accepts string, number and boolean context values, so
+  /// nested principal fields are passed as JSON strings.
+  fn context(&self) -> Result<Value, Error> {
+    let (Some(claims), Some(user)) = (self.auth.claims(), self.auth.user()) else {
+      return Ok(Value::Object(Map::new()));
+    };
+
+    let user_details = serde_json::to_value(&user).map_err(|error| Error::Unhandled(SerializableError {
+      message: error.to_string()
+    }))?;
+
+    let mut context = Map::new();
+    if let Value::Object(fields) = &user_details {
+      for (name, value) in fields {
+        match value {
+          Value::Null => {},
+          Value::String(_) | Value::Number(_) | Value::Bool(_) => { context.insert(name.clone(), value.clone()); },
+          _ => { context.insert(name.clone(), Value::String(value.to_string())); },
+        }
+      }
+    }
+
+    context.insert("sub".to_string(), Value::String(user.user_id().to_string()));
+    context.insert("user_details".to_string(), Value::String(user_details.to_string()));
+
+    let scopes = claims.scopes();
+    if !scopes.is_empty() {
+      context.insert("scope".to_string(), Value::String(scopes.join(" ")));
+    }
+
+    Ok(Value::Object(context))
+  }
+}
+
+fn is_denied(error: &Error) -> bool {
+  matches!(error, Error::Unauthorized(_) | Error::Forbidden(_) | Error::JwtTokenInvalid(_))
+}
+
+/// `arn:aws:execute-api:<region>:<account>:<api>/<stage>/<method>/<path>`
+/// becomes `arn:aws:execute-api:<region>:<account>:<api>/<stage>/*`.
+fn stage_resource(method_arn: &str) -> String {
+  let mut parts = method_arn.splitn(3, '/');
+
+  match (parts.next(), parts.next()) {
+    (Some(api), Some(stage)) => format!("{}/{}/*", api, stage),
+    _ => method_arn.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  use chrono::Utc;
+  use lambda_http::aws_lambda_events::apigw::ApiGatewayCustomAuthorizerRequestTypeRequestContext;
+  use uuid::Uuid;
+
+  use crate::{
+    auth::{principal_loader::MockIPrincipalLoader, AuthConfig, CookieExtractor},
+    types::utils::jwt_util::{AuthClaims, TokenType},
+    utils::{jwt_util::{IJwtUtil, MockIJwtUtil}, JwtUtil},
+  };
+
+  const METHOD_ARN: &str = "arn:aws:execute-api:ap-southeast-1:123456789012:abc123/prod/GET/orders/42";
+
+  fn auth_user() -> AuthUser {
+    AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    }
+  }
+
+  fn auth_for(user: AuthUser) -> Auth {
+    let mut jwt_util = MockIJwtUtil::new();
+    jwt_util.expect_extract_claims().returning(move |token| {
+      match token {
+        "valid_token" => Ok(AuthClaims::new(user.clone(), TokenType::AccessToken, Utc::now().timestamp() as usize + 60)
+          .with_scopes(&["orders:read"])),
+        _ => Err(Error::JwtTokenInvalid(SerializableError { message: "InvalidToken".to_string() })),
+      }
+    });
+
+    Auth::new(Box::new(jwt_util)).with_config(AuthConfig::bearer())
+  }
+
+  #[tokio::test]
+  async fn test_authorize_token_allows_stage() {
+    // arrange
+    let user = auth_user();
+    let event = ApiGatewayCustomAuthorizerRequest {
+      type_: Some("TOKEN".to_string()),
+      authorization_token: Some("Bearer valid_token".to_string()),
+      method_arn: Some(METHOD_ARN.to_string()),
+    };
+
+    // act
+    let mut authorizer = LambdaAuthorizer::new(auth_for(user.clone()));
+    let response = authorizer.authorize_token(&event).await.unwrap();
+
+    // assert
+    assert_eq!(response.principal_id, Some(user.id.to_string()));
+    let statement = &response.policy_document.statement[0];
+    assert_eq!(statement.effect, IamPolicyEffect::Allow);
+    assert_eq!(statement.action, vec!["execute-api:Invoke".to_string()]);
+    assert_eq!(statement.resource, vec!["arn:aws:execute-api:ap-southeast-1:123456789012:abc123/prod/*".to_string()]);
+    assert_eq!(response.context["email"], "john.doe@example.com");
+    assert_eq!(response.context["scope"], "orders:read");
+    assert!(response.context.get("middle_name").is_none());
+    let user_details: AuthUser = serde_json::from_str(response.context["user_details"].as_str().unwrap()).unwrap();
+    assert_eq!(user_details, user);
+  }
+
+  #[tokio::test]
+  async fn test_context_uses_current_principal() {
+    // arrange
+    let user = auth_user();
+    let current_user = AuthUser { email: "jdoe@example.com".to_string(), ..user.clone() };
+    let loaded_user = current_user.clone();
+    let mut principal_loader = MockIPrincipalLoader::new();
+    principal_loader.expect_load().returning(move |_| Ok(Some(loaded_user.clone())));
+    let event = ApiGatewayCustomAuthorizerRequest {
+      type_: Some("TOKEN".to_string()),
+      authorization_token: Some("Bearer valid_token".to_string()),
+      method_arn: Some(METHOD_ARN.to_string()),
+    };
+
+    // act
+    let mut authorizer = LambdaAuthorizer::new(auth_for(user).with_principal_loader(Box::new(principal_loader)));
+    let response = authorizer.authorize_token(&event).await.unwrap();
+
+    // assert
+    assert_eq!(response.context["email"], "jdoe@example.com");
+    let user_details: AuthUser = serde_json::from_str(response.context["user_details"].as_str().unwrap()).unwrap();
+    assert_eq!(user_details, current_user);
+  }
+
+  #[tokio::test]
+  async fn test_authorize_token_denies_invalid_token() {
+    // arrange
+    let event = ApiGatewayCustomAuthorizerRequest {
+      type_: Some("TOKEN".to_string()),
+      authorization_token: Some("Bearer expired_token".to_string()),
+      method_arn: Some(METHOD_ARN.to_string()),
+    };
+
+    // act
+    let mut authorizer = LambdaAuthorizer::new(auth_for(auth_user()));
+    let response = authorizer.authorize_token(&event).await.unwrap();
+
+    // assert
+    let statement = &response.policy_document.statement[0];
+    assert_eq!(statement.effect, IamPolicyEffect::Deny);
+    assert_eq!(statement.resource, vec![METHOD_ARN.to_string()]);
+    assert_eq!(response.context, Value::Object(Map::new()));
+  }
+
+  #[tokio::test]
+  async fn test_authorize_token_denies_foreign_signature() {
+    // arrange
+    let user = auth_user();
+    let foreign_token = JwtUtil::new("other_key")
+      .generate_token(&AuthClaims::new(user, TokenType::AccessToken, Utc::now().timestamp() as usize + 60))
+      .unwrap();
+    let event = ApiGatewayCustomAuthorizerRequest {
+      type_: Some("TOKEN".to_string()),
+      authorization_token: Some(format!("Bearer {}", foreign_token)),
+      method_arn: Some(METHOD_ARN.to_string()),
+    };
+    let auth = Auth::new(Box::new(JwtUtil::new("some_key"))).with_config(AuthConfig::bearer());
+
+    // act
+    let mut authorizer = LambdaAuthorizer::new(auth);
+    let response = authorizer.authorize_token(&event).await.unwrap();
+
+    // assert
+    assert_eq!(response.policy_document.statement[0].effect, IamPolicyEffect::Deny);
+  }
+
+  #[tokio::test]
+  async fn test_authorize_token_denies_malformed_token() {
+    // arrange
+    let event = ApiGatewayCustomAuthorizerRequest {
+      type_: Some("TOKEN".to_string()),
+      authorization_token: Some("Bearer x.y.z".to_string()),
+      method_arn: Some(METHOD_ARN.to_string()),
+    };
+    let auth = Auth::new(Box::new(JwtUtil::new("some_key"))).with_config(AuthConfig::bearer());
+
+    // act
+    let mut authorizer = LambdaAuthorizer::new(auth);
+    let response = authorizer.authorize_token(&event).await.unwrap();
+
+    // assert
+    assert_eq!(response.policy_document.statement[0].effect, IamPolicyEffect::Deny);
+  }
+
+  #[tokio::test]
+  async fn test_authorize_request_with_method_scope() {
+    // arrange
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", "Bearer valid_token".parse().unwrap());
+    let event = ApiGatewayCustomAuthorizerRequestTypeRequest {
+      type_: Some("REQUEST".to_string()),
+      method_arn: Some(METHOD_ARN.to_string()),
+      resource: None,
+      path: None,
+      http_method: None,
+      headers,
+      multi_value_headers: HeaderMap::new(),
+      query_string_parameters: QueryMap::default(),
+      multi_value_query_string_parameters: QueryMap::default(),
+      path_parameters: HashMap::new(),
+      stage_variables: HashMap::new(),
+      request_context: ApiGatewayCustomAuthorizerRequestTypeRequestContext::default(),
+    };
+
+    // act
+    let mut authorizer = LambdaAuthorizer::new(auth_for(auth_user())).with_method_scope();
+    let response = authorizer.authorize_request(&event).await.unwrap();
+
+    // assert
+    let statement = &response.policy_document.statement[0];
+    assert_eq!(statement.effect, IamPolicyEffect::Allow);
+    assert_eq!(statement.resource, vec![METHOD_ARN.to_string()]);
+  }
+
+  #[tokio::test]
+  async fn test_authorize_http_api_reads_cookies() {
+    // arrange
+    let user = auth_user();
+    let auth = auth_for(user.clone()).with_extractor(Box::new(CookieExtractor::new("access_token")));
+    let event = ApiGatewayV2CustomAuthorizerV2Request {
+      cookies: vec!["theme=dark".to_string(), "access_token=valid_token".to_string()],
+      ..Default::default()
+    };
+    let missing_event = ApiGatewayV2CustomAuthorizerV2Request::default();
+
+    // act
+    let mut authorizer = LambdaAuthorizer::new(auth);
+    let response = authorizer.authorize_http_api(&event).await.unwrap();
+    let missing_response = authorizer.authorize_http_api(&missing_event).await.unwrap();
+
+    // assert
+    assert!(response.is_authorized);
+    assert_eq!(response.context["sub"], user.id.to_string());
+    assert!(!missing_response.is_authorized);
+  }
+
+  #[test]
+  fn test_stage_resource() {
+    // assert
+    assert_eq!(stage_resource(METHOD_ARN), "arn:aws:execute-api:ap-southeast-1:123456789012:abc123/prod/*");
+    assert_eq!(stage_resource("not-an-arn"), "not-an-arn");
+  }
+}
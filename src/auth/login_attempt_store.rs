@@ -0,0 +1,235 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+
+use crate::Error;
+
+/// Failed logins counted against one key, e.g. `email:jane@example.com` or
+/// `ip:203.0.113.7`. Attempts are counted up front and taken back when they
+/// don't fail, so `failures` includes attempts still in progress.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct LoginAttempts {
+  pub key: String,
+  pub failures: i32,
+  pub last_failed_at: DateTime<Utc>,
+  pub blocked_until: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait ILoginAttemptStore: Send + Sync {
+  async fn find(&self, key: &str) -> Result<Option<LoginAttempts>, Error>;
+
+  /// Counts an attempt and returns the new count, or `None` without
+  /// counting while the key is blocked. In the same step the key is blocked
+  /// for `delays[count - 1]`, or the last delay past the end, so concurrent
+  /// attempts can't all get past a block that isn't stored yet. Failures are
+  /// forgotten once the last one is older than `window`.
+  async fn record_attempt(&self, key: &str, window: Duration, delays: &[Duration]) -> Result<Option<i32>, Error>;
+
+  /// Takes back one counted attempt, e.g. one that succeeded, and puts the
+  /// block back to the one for the remaining count, taken from the same
+  /// `delays` as `record_attempt`.
+  async fn release(&self, key: &str, delays: &[Duration]) -> Result<(), Error>;
+
+  async fn reset(&self, key: &str) -> Result<(), Error>;
+
+  /// Deletes keys that are no longer blocked and whose last failure is
+  /// older than `window`. Returns the number of keys removed.
+  async fn delete_expired(&self, window: Duration) -> Result<u64, Error>;
+}
+
+#[derive(Default)]
+pub struct InMemoryLoginAttemptStore {
+  attempts: Mutex<HashMap<String, LoginAttempts>>,
+}
+
+impl InMemoryLoginAttemptStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+#[async_trait]
+impl ILoginAttemptStore for InMemoryLoginAttemptStore {
+  async fn find(&self, key: &str) -> Result<Option<LoginAttempts>, Error> {
+    let attempts = self.attempts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    Ok(attempts.get(key).cloned())
+  }
+
+  async fn record_attempt(&self, key: &str, window: Duration, delays: &[Duration]) -> Result<Option<i32>, Error> {
+    let mut attempts = self.attempts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let now = Utc::now();
+    let entry = attempts.entry(key.to_string()).or_insert_with(|| LoginAttempts {
+      key: key.to_string(),
+      failures: 0,
+      last_failed_at: now,
+      blocked_until: None,
+    });
+
+    if entry.blocked_until.is_some_and(|blocked_until| blocked_until > now) {
+      return Ok(None);
+    }
+
+    if entry.last_failed_at < now - window {
+      entry.failures = 0;
+    }
+
+    entry.failures += 1;
+    entry.last_failed_at = now;
+    let delay = delays.get(entry.failures as usize - 1).or(delays.last()).copied().unwrap_or_default();
+    entry.blocked_until = Some(now + delay);
+
+    Ok(Some(entry.failures))
+  }
+
+  async fn release(&self, key: &str, delays: &[Duration]) -> Result<(), Error> {
+    let mut attempts = self.attempts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if let Some(entry) = attempts.get_mut(key) {
+      entry.failures = (entry.failures - 1).max(0);
+      entry.blocked_until = (entry.failures > 0).then(|| {
+        let delay = delays.get(entry.failures as usize - 1).or(delays.last()).copied().unwrap_or_default();
+        entry.last_failed_at + delay
+      });
+    }
+
+    Ok(())
+  }
+
+  async fn reset(&self, key: &str) -> Result<(), Error> {
+    let mut attempts = self.attempts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    attempts.remove(key);
+
+    Ok(())
+  }
+
+  async fn delete_expired(&self, window: Duration) -> Result<u64, Error> {
+    let mut attempts = self.attempts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let now = Utc::now();
+    let count = attempts.len();
+    attempts.retain(|_, entry| {
+      entry.last_failed_at >= now - window || entry.blocked_until.is_some_and(|blocked_until| blocked_until > now)
+    });
+
+    Ok((count - attempts.len()) as u64)
+  }
+}
+
+/// `ILoginAttemptStore` backed by the `login_attempts` table from
+/// `database::MIGRATOR`. Run `LoginAttemptTracker::delete_expired`
+/// periodically to keep it small.
+#[derive(Clone)]
+pub struct PgLoginAttemptStore {
+  pool: PgPool,
+}
+
+impl PgLoginAttemptStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl ILoginAttemptStore for PgLoginAttemptStore {
+  async fn find(&self, key: &str) -> Result<Option<LoginAttempts>, Error> {
+    Ok(sqlx::query_as("SELECT key, failures, last_failed_at, blocked_until FROM login_attempts WHERE key = $1")
+      .bind(key)
+      .fetch_optional(&self.pool)
+      .await?)
+  }
+
+  async fn record_attempt(&self, key: &str, window: Duration, delays: &[Duration]) -> Result<Option<i32>, Error> {
+    let delays: Vec<f64> = delays.iter().map(|delay| delay.num_milliseconds() as f64 / 1000.0).collect();
+
+    // The conflict update waits on the row lock, so concurrent attempts are
+    // counted one after another and each sees the block of the one before.
+    Ok(sqlx::query_scalar(
+      "INSERT INTO login_attempts AS attempts (key, failures, last_failed_at, blocked_until)
+       VALUES ($1, 1, NOW(), NOW() + make_interval(secs => COALESCE(($3::FLOAT8[])[1], 0)))
+       ON CONFLICT (key) DO UPDATE SET
+         failures = CASE
+           WHEN attempts.last_failed_at < NOW() - make_interval(secs => $2) THEN 1
+           ELSE attempts.failures + 1
+         END,
+         last_failed_at = NOW(),
+         blocked_until = NOW() + make_interval(secs => COALESCE(($3::FLOAT8[])[LEAST(
+           CASE WHEN attempts.last_failed_at < NOW() - make_interval(secs => $2) THEN 1 ELSE attempts.failures + 1 END,
+           cardinality($3::FLOAT8[])
+         )], 0))
+         WHERE attempts.blocked_until IS NULL OR attempts.blocked_until <= NOW()
+       RETURNING failures"
+    )
+      .bind(key)
+      .bind(window.num_seconds() as f64)
+      .bind(delays)
+      .fetch_optional(&self.pool)
+      .await?)
+  }
+
+  async fn release(&self, key: &str, delays: &[Duration]) -> Result<(), Error> {
+    let delays: Vec<f64> = delays.iter().map(|delay| delay.num_milliseconds() as f64 / 1000.0).collect();
+
+    sqlx::query(
+      "UPDATE login_attempts SET
+         failures = GREATEST(failures - 1, 0),
+         blocked_until = CASE WHEN failures > 1 THEN last_failed_at + make_interval(secs => COALESCE(
+           ($2::FLOAT8[])[LEAST(failures - 1, cardinality($2::FLOAT8[]))], 0
+         )) END
+       WHERE key = $1"
+    )
+      .bind(key)
+      .bind(delays)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn reset(&self, key: &str) -> Result<(), Error> {
+    sqlx::query("DELETE FROM login_attempts WHERE key = $1")
+      .bind(key)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn delete_expired(&self, window: Duration) -> Result<u64, Error> {
+    Ok(sqlx::query(
+      "DELETE FROM login_attempts
+       WHERE last_failed_at < NOW() - make_interval(secs => $1)
+         AND (blocked_until IS NULL OR blocked_until <= NOW())"
+    )
+      .bind(window.num_seconds() as f64)
+      .execute(&self.pool)
+      .await?
+      .rows_affected())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_delete_expired() {
+    // arrange
+    let store = InMemoryLoginAttemptStore::new();
+    store.record_attempt("email:john.doe@example.com", Duration::hours(24), &[]).await.unwrap();
+    store.record_attempt("email:jane.doe@example.com", Duration::hours(24), &[Duration::minutes(1)]).await.unwrap();
+
+    // act
+    let deleted = store.delete_expired(Duration::zero()).await.unwrap();
+
+    // assert
+    assert_eq!(deleted, 1);
+    assert!(store.find("email:john.doe@example.com").await.unwrap().is_none());
+    assert!(store.find("email:jane.doe@example.com").await.unwrap().is_some());
+  }
+}
@@ -0,0 +1,345 @@
+use std::future::Future;
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+  auth::login_attempt_store::ILoginAttemptStore,
+  error::RetryAfterError,
+  Error,
+};
+
+/// Throttles failed logins per email, or per user for second factors, and
+/// per client IP. Attempts are counted before they are verified, so
+/// concurrent guesses can't all get in before the first failure is stored.
+///
+/// After `free_attempts` failures each further failure blocks the key for
+/// an exponentially growing delay; at `lockout_threshold` failures it is
+/// locked out for `lockout_duration`.
+///
+/// Unknown emails are tracked like known ones so lockouts don't reveal
+/// which accounts exist.
+pub struct LoginAttemptTracker {
+  store: Box<dyn ILoginAttemptStore>,
+  free_attempts: i32,
+  base_delay: Duration,
+  max_delay: Duration,
+  lockout_threshold: i32,
+  lockout_duration: Duration,
+  failure_window: Duration,
+}
+
+impl LoginAttemptTracker {
+  /// 5 free attempts, then 1s, 2s, 4s, ... up to 15 minutes, and a
+  /// 30 minute lockout at 10 failures within 24 hours.
+  pub fn new(store: Box<dyn ILoginAttemptStore>) -> Self {
+    Self {
+      store,
+      free_attempts: 5,
+      base_delay: Duration::seconds(1),
+      max_delay: Duration::minutes(15),
+      lockout_threshold: 10,
+      lockout_duration: Duration::minutes(30),
+      failure_window: Duration::hours(24),
+    }
+  }
+
+  pub fn with_backoff(mut self, free_attempts: i32, base_delay: Duration, max_delay: Duration) -> Self {
+    self.free_attempts = free_attempts;
+    self.base_delay = base_delay;
+    self.max_delay = max_delay;
+
+    self
+  }
+
+  pub fn with_lockout(mut self, lockout_threshold: i32, lockout_duration: Duration) -> Self {
+    self.lockout_threshold = lockout_threshold;
+    self.lockout_duration = lockout_duration;
+
+    self
+  }
+
+  /// Failures are forgotten once the last one is older than this.
+  pub fn with_failure_window(mut self, failure_window: Duration) -> Self {
+    self.failure_window = failure_window;
+
+    self
+  }
+
+  /// Runs `attempt` as a counted attempt for `email` and `client_ip`. It
+  /// stays counted if it fails with `Error::Unauthorized`; otherwise it is
+  /// taken back, and success also clears the email's failures.
+  pub async fn track<R>(
+    &self,
+    email: &str,
+    client_ip: Option<&str>,
+    attempt: impl Future<Output = Result<R, Error>>,
+  ) -> Result<R, Error> {
+    self.track_keys(&keys(email_key(email), client_ip), attempt).await
+  }
+
+  /// `track` for a second factor of an already identified user, counted per
+  /// user instead of per email.
+  pub async fn track_user<R>(
+    &self,
+    user_id: Uuid,
+    client_ip: Option<&str>,
+    attempt: impl Future<Output = Result<R, Error>>,
+  ) -> Result<R, Error> {
+    self.track_keys(&keys(format!("user:{}", user_id), client_ip), attempt).await
+  }
+
+  /// Counts an attempt for the email and the client IP, failing with
+  /// `Error::TooManyRequests` instead while either is blocked. Call before
+  /// verifying credentials; the attempt counts as failed until `release`d.
+  pub async fn check(&self, email: &str, client_ip: Option<&str>) -> Result<(), Error> {
+    self.check_keys(&keys(email_key(email), client_ip)).await
+  }
+
+  /// Takes back the attempt counted by `check`, for attempts that didn't
+  /// fail on the credentials.
+  pub async fn release(&self, email: &str, client_ip: Option<&str>) -> Result<(), Error> {
+    self.release_keys(&keys(email_key(email), client_ip)).await
+  }
+
+  /// Clears the failures of `email`, unlocking it. Call after a successful
+  /// login or password reset; the client IP keeps its count.
+  pub async fn reset(&self, email: &str) -> Result<(), Error> {
+    self.store.reset(&email_key(email)).await
+  }
+
+  /// Deletes keys whose failures are past the failure window and that are
+  /// no longer blocked. Returns the number of keys removed.
+  pub async fn delete_expired(&self) -> Result<u64, Error> {
+    self.store.delete_expired(self.failure_window).await
+  }
+
+  async fn track_keys<R>(&self, keys: &[String], attempt: impl Future<Output = Result<R, Error>>) -> Result<R, Error> {
+    self.check_keys(keys).await?;
+
+    let result = attempt.await;
+    match &result {
+      Ok(_) => {
+        self.release_keys(&keys[1..]).await?;
+        self.store.reset(&keys[0]).await?;
+      },
+      Err(Error::Unauthorized(_)) => {},
+      Err(_) => self.release_keys(keys).await?,
+    }
+
+    result
+  }
+
+  async fn check_keys(&self, keys: &[String]) -> Result<(), Error> {
+    let delays = self.delays();
+
+    for (index, key) in keys.iter().enumerate() {
+      if self.store.record_attempt(key, self.failure_window, &delays).await?.is_none() {
+        self.release_keys(&keys[..index]).await?;
+
+        let now = Utc::now();
+        let blocked_until = self.store.find(key).await?.and_then(|attempts| attempts.blocked_until).unwrap_or(now);
+        return Err(too_many_requests(blocked_until, now));
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn release_keys(&self, keys: &[String]) -> Result<(), Error> {
+    let delays = self.delays();
+
+    for key in keys {
+      self.store.release(key, &delays).await?;
+    }
+
+    Ok(())
+  }
+
+  /// The block after each failure count up to the lockout, which also
+  /// applies to any count past it.
+  fn delays(&self) -> Vec<Duration> {
+    (1..=self.lockout_threshold.max(1))
+      .map(|failures| self.delay(failures).unwrap_or_else(Duration::zero))
+      .collect()
+  }
+
+  fn delay(&self, failures: i32) -> Option<Duration> {
+    if failures >= self.lockout_threshold {
+      return Some(self.lockout_duration);
+    }
+
+    let backoff_step = failures - self.free_attempts;
+    if backoff_step <= 0 {
+      return None;
+    }
+
+    let factor = 2i32.checked_pow(backoff_step as u32 - 1).unwrap_or(i32::MAX);
+    Some(self.base_delay.checked_mul(factor).unwrap_or(self.max_delay).min(self.max_delay))
+  }
+}
+
+/// The account key first, then the client IP's.
+fn keys(account_key: String, client_ip: Option<&str>) -> Vec<String> {
+  let mut keys = vec![account_key];
+  keys.extend(client_ip.map(|client_ip| format!("ip:{}", client_ip)));
+
+  keys
+}
+
+fn email_key(email: &str) -> String {
+  format!("email:{}", email.trim().to_lowercase())
+}
+
+fn too_many_requests(blocked_until: DateTime<Utc>, now: DateTime<Utc>) -> Error {
+  let retry_after = (blocked_until - now).num_milliseconds().max(0) as u64;
+
+  Error::TooManyRequests(RetryAfterError {
+    message: "Too many failed login attempts".to_string(),
+    retry_after: retry_after.div_ceil(1000),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::{auth::login_attempt_store::InMemoryLoginAttemptStore, error::SerializableError};
+
+  fn tracker() -> LoginAttemptTracker {
+    LoginAttemptTracker::new(Box::new(InMemoryLoginAttemptStore::new()))
+      .with_backoff(2, Duration::minutes(1), Duration::minutes(10))
+      .with_lockout(6, Duration::hours(1))
+  }
+
+  /// Makes attempts that each fail on the credentials.
+  async fn fail(tracker: &LoginAttemptTracker, times: usize, email: &str, client_ip: Option<&str>) {
+    for _ in 0..times {
+      tracker.check(email, client_ip).await.unwrap();
+    }
+  }
+
+  fn retry_after(result: Result<(), Error>) -> u64 {
+    match result {
+      Err(Error::TooManyRequests(error)) => error.retry_after,
+      _ => panic!("Expected TooManyRequests error"),
+    }
+  }
+
+  #[test]
+  fn test_delay() {
+    // arrange
+    let tracker = tracker();
+
+    // act
+    let delays: Vec<Option<Duration>> = (1..=7).map(|failures| tracker.delay(failures)).collect();
+
+    // assert
+    assert_eq!(delays, vec![
+      None,
+      None,
+      Some(Duration::minutes(1)),
+      Some(Duration::minutes(2)),
+      Some(Duration::minutes(4)),
+      Some(Duration::hours(1)),
+      Some(Duration::hours(1)),
+    ]);
+    assert_eq!(tracker.with_lockout(100, Duration::hours(1)).delay(50), Some(Duration::minutes(10)));
+  }
+
+  #[tokio::test]
+  async fn test_free_attempts_then_backoff() {
+    // arrange
+    let tracker = tracker();
+    fail(&tracker, 2, "john.doe@example.com", None).await;
+
+    // act
+    let counted_result = tracker.check("john.doe@example.com", None).await;
+    let blocked_result = tracker.check(" John.Doe@example.com ", None).await;
+
+    // assert
+    assert!(counted_result.is_ok());
+    assert!((59..=60).contains(&retry_after(blocked_result)));
+  }
+
+  #[tokio::test]
+  async fn test_lockout() {
+    // arrange
+    let tracker = tracker().with_backoff(6, Duration::minutes(1), Duration::minutes(10));
+
+    // act
+    fail(&tracker, 6, "john.doe@example.com", None).await;
+
+    // assert
+    assert!((3599..=3600).contains(&retry_after(tracker.check("john.doe@example.com", None).await)));
+  }
+
+  #[tokio::test]
+  async fn test_client_ip_is_tracked_across_emails() {
+    // arrange
+    let tracker = tracker();
+
+    // act
+    fail(&tracker, 1, "a@example.com", Some("203.0.113.7")).await;
+    fail(&tracker, 1, "b@example.com", Some("203.0.113.7")).await;
+    fail(&tracker, 1, "c@example.com", Some("203.0.113.7")).await;
+
+    // assert
+    assert!(tracker.check("d@example.com", None).await.is_ok());
+    assert!(tracker.check("d@example.com", Some("203.0.113.7")).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_reset_unlocks_email() {
+    // arrange
+    let tracker = tracker().with_backoff(6, Duration::minutes(1), Duration::minutes(10));
+    fail(&tracker, 6, "john.doe@example.com", Some("203.0.113.7")).await;
+
+    // act
+    tracker.reset("john.doe@example.com").await.unwrap();
+
+    // assert
+    assert!(tracker.check("john.doe@example.com", None).await.is_ok());
+    assert!(tracker.check("john.doe@example.com", Some("203.0.113.7")).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_track_only_keeps_unauthorized_attempts() {
+    // arrange
+    let tracker = tracker();
+    let user_id = Uuid::new_v4();
+
+    // act
+    for _ in 0..3 {
+      let _ = tracker.track("john.doe@example.com", Some("203.0.113.7"), async { Ok::<_, Error>(()) }).await;
+      let _ = tracker.track_user(user_id, None, async {
+        Err::<(), _>(Error::Unhandled(SerializableError { message: "Boom".to_string() }))
+      }).await;
+    }
+    let failed_result = tracker.track_user(user_id, None, async {
+      Err::<(), _>(Error::Unauthorized(SerializableError { message: "Invalid code".to_string() }))
+    }).await;
+
+    // assert
+    assert!(matches!(failed_result, Err(Error::Unauthorized(_))));
+    assert!(tracker.check("john.doe@example.com", Some("203.0.113.7")).await.is_ok());
+    assert!(tracker.check("jane.doe@example.com", Some("203.0.113.7")).await.is_ok());
+    assert!(tracker.check("john.doe@example.com", None).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_release_restores_block_for_remaining_attempts() {
+    // arrange
+    let tracker = tracker();
+    fail(&tracker, 2, "a@example.com", Some("203.0.113.7")).await;
+
+    // act
+    tracker.track("b@example.com", Some("203.0.113.7"), async { Ok::<_, Error>(()) }).await.unwrap();
+    let released_result = tracker.check("c@example.com", Some("203.0.113.7")).await;
+    let blocked_result = tracker.check("d@example.com", Some("203.0.113.7")).await;
+
+    // assert
+    assert!(released_result.is_ok());
+    assert!((59..=60).contains(&retry_after(blocked_result)));
+  }
+}
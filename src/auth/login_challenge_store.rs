@@ -0,0 +1,114 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::Error;
+
+/// An outstanding passwordless login: the `jti` of the emailed token and a
+/// hash of the one-time code sent along with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginChallenge {
+  pub id: String,
+  pub user_id: Uuid,
+  pub email: String,
+  pub code_hash: String,
+  pub expires_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait ILoginChallengeStore: Send + Sync {
+  /// Stores the challenge unless its email already has `max_open`
+  /// unexpired, unused challenges. Returns `false` in that case.
+  async fn save(&self, challenge: &LoginChallenge, max_open: i32) -> Result<bool, Error>;
+
+  /// Consumes the challenge of a magic link token. Returns `false` if it is
+  /// unknown, expired or was already used.
+  async fn consume_token(&self, id: &str) -> Result<bool, Error>;
+
+  /// Consumes the newest open challenge for `email` if `code_hash` matches
+  /// and returns its user. A wrong code counts as an attempt against every
+  /// open challenge; after `max_attempts` they stop accepting codes.
+  async fn consume_code(&self, email: &str, code_hash: &str, max_attempts: i32) -> Result<Option<Uuid>, Error>;
+}
+
+/// `ILoginChallengeStore` backed by the `login_challenges` table from
+/// `database::MIGRATOR`.
+#[derive(Clone)]
+pub struct PgLoginChallengeStore {
+  pool: PgPool,
+}
+
+impl PgLoginChallengeStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl ILoginChallengeStore for PgLoginChallengeStore {
+  async fn save(&self, challenge: &LoginChallenge, max_open: i32) -> Result<bool, Error> {
+    let result = sqlx::query(
+      "INSERT INTO login_challenges (id, user_id, email, code_hash, expires_at)
+       SELECT $1, $2, $3, $4, $5
+       WHERE (
+         SELECT COUNT(*) FROM login_challenges
+         WHERE email = $3 AND consumed_at IS NULL AND expires_at > NOW()
+       ) < $6"
+    )
+      .bind(&challenge.id)
+      .bind(challenge.user_id)
+      .bind(&challenge.email)
+      .bind(&challenge.code_hash)
+      .bind(challenge.expires_at)
+      .bind(max_open as i64)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(result.rows_affected() > 0)
+  }
+
+  async fn consume_token(&self, id: &str) -> Result<bool, Error> {
+    let result = sqlx::query(
+      "UPDATE login_challenges SET consumed_at = NOW()
+       WHERE id = $1 AND consumed_at IS NULL AND expires_at > NOW()"
+    )
+      .bind(id)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(result.rows_affected() > 0)
+  }
+
+  async fn consume_code(&self, email: &str, code_hash: &str, max_attempts: i32) -> Result<Option<Uuid>, Error> {
+    // One statement, so concurrent guesses wait on the row locks and each
+    // sees the attempts counted by the others.
+    let updated: Vec<(Uuid, bool)> = sqlx::query_as(
+      "WITH open_challenges AS (
+         SELECT id, code_hash, created_at FROM login_challenges
+         WHERE email = $1 AND consumed_at IS NULL AND expires_at > NOW() AND attempts < $3
+         FOR UPDATE
+       ), guesses AS (
+         SELECT id, id = (SELECT id FROM open_challenges ORDER BY created_at DESC LIMIT 1)
+           AND code_hash = $2 AS matched
+         FROM open_challenges
+       )
+       UPDATE login_challenges AS challenges SET
+         attempts = challenges.attempts + CASE WHEN (SELECT bool_or(matched) FROM guesses) THEN 0 ELSE 1 END,
+         consumed_at = CASE WHEN guesses.matched THEN NOW() END
+       FROM guesses
+       WHERE challenges.id = guesses.id
+       RETURNING challenges.user_id, guesses.matched"
+    )
+      .bind(email)
+      .bind(code_hash)
+      .bind(max_attempts)
+      .fetch_all(&self.pool)
+      .await?;
+
+    Ok(updated.into_iter().find(|(_, matched)| *matched).map(|(user_id, _)| user_id))
+  }
+}
@@ -0,0 +1,258 @@
+use crate::{
+  auth::{
+    credential_store::ICredentialStore,
+    login_attempt_tracker::LoginAttemptTracker,
+    token_pair_service::TokenPairService,
+  },
+  error::SerializableError,
+  types::auth::{AuthUser, LoginResult},
+  utils::password_hasher::IPasswordHasher,
+  Error,
+};
+
+/// Verifies email/password credentials and issues a token pair.
+///
+/// Hashes created with outdated parameters are replaced on a successful
+/// login. Unknown emails still cost one hash so response times don't reveal
+/// which accounts exist.
+pub struct LoginService {
+  credential_store: Box<dyn ICredentialStore>,
+  password_hasher: Box<dyn IPasswordHasher>,
+  token_pair_service: TokenPairService,
+  attempt_tracker: Option<LoginAttemptTracker>,
+}
+
+impl LoginService {
+  pub fn new(
+    credential_store: Box<dyn ICredentialStore>,
+    password_hasher: Box<dyn IPasswordHasher>,
+    token_pair_service: TokenPairService,
+  ) -> Self {
+    Self { credential_store, password_hasher, token_pair_service, attempt_tracker: None }
+  }
+
+  /// Throttles failed logins. Use `login_from` to count them per client IP
+  /// as well as per email.
+  pub fn with_attempt_tracker(mut self, attempt_tracker: LoginAttemptTracker) -> Self {
+    self.attempt_tracker = Some(attempt_tracker);
+
+    self
+  }
+
+  pub async fn login(&self, email: &str, password: &str) -> Result<LoginResult, Error> {
+    self.login_from(email, password, None).await
+  }
+
+  /// `login` for a request from `client_ip`, e.g. `auth::client_ip` of the
+  /// request.
+  pub async fn login_from(&self, email: &str, password: &str, client_ip: Option<&str>) -> Result<LoginResult, Error> {
+    let user = self.verify_credentials_from(email, password, client_ip).await?;
+    let token_pair = self.token_pair_service.issue(&user).await?;
+
+    Ok(LoginResult { user, token_pair })
+  }
+
+  /// The password step of `login` without issuing tokens, for flows that
+  /// continue with a second factor.
+  pub async fn verify_credentials(&self, email: &str, password: &str) -> Result<AuthUser, Error> {
+    self.verify_credentials_from(email, password, None).await
+  }
+
+  pub async fn verify_credentials_from(
+    &self,
+    email: &str,
+    password: &str,
+    client_ip: Option<&str>,
+  ) -> Result<AuthUser, Error> {
+    match self.attempt_tracker.as_ref() {
+      Some(attempt_tracker) => attempt_tracker.track(email, client_ip, self.check_password(email, password)).await,
+      None => self.check_password(email, password).await,
+    }
+  }
+
+  async fn check_password(&self, email: &str, password: &str) -> Result<AuthUser, Error> {
+    let credentials = self.credential_store.find_by_email(email.trim()).await?;
+
+    let Some((credentials, password_hash)) = credentials
+      .and_then(|credentials| credentials.password_hash.clone().map(|password_hash| (credentials, password_hash)))
+    else {
+      self.password_hasher.hash(password)?;
+      return Err(invalid_credentials());
+    };
+
+    if !self.password_hasher.verify(password, &password_hash)? {
+      return Err(invalid_credentials());
+    }
+
+    if self.password_hasher.needs_rehash(&password_hash) {
+      let password_hash = self.password_hasher.hash(password)?;
+      self.credential_store.update_password_hash(credentials.id, &password_hash).await?;
+    }
+
+    Ok(credentials.user())
+  }
+}
+
+fn invalid_credentials() -> Error {
+  Error::Unauthorized(SerializableError { message: "Invalid email or password".to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use uuid::Uuid;
+
+  use crate::{
+    auth::{
+      credential_store::{MockICredentialStore, UserCredentials},
+      login_attempt_store::InMemoryLoginAttemptStore,
+      refresh_token_store::InMemoryRefreshTokenStore,
+    },
+    types::utils::jwt_util::AuthClaims,
+    utils::{jwt_util::IJwtUtil, password_hasher::Argon2Hasher, JwtUtil},
+  };
+
+  fn credentials(password_hash: Option<String>) -> UserCredentials {
+    UserCredentials {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+      password_hash,
+    }
+  }
+
+  fn token_pair_service() -> TokenPairService {
+    TokenPairService::new(Box::new(JwtUtil::new("some_key")), Box::new(InMemoryRefreshTokenStore::new()))
+  }
+
+  fn hasher(memory_cost: u32) -> Argon2Hasher {
+    Argon2Hasher::new().with_params(memory_cost, 1, 1).unwrap()
+  }
+
+  fn error_message(result: Result<LoginResult, Error>) -> String {
+    match result {
+      Err(Error::Unauthorized(error)) => error.message,
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_login_success() {
+    // arrange
+    let credentials = credentials(Some(hasher(1024).hash("secret").unwrap()));
+    let user = credentials.user();
+    let mut credential_store = MockICredentialStore::new();
+    credential_store.expect_find_by_email()
+      .withf(|email| email == "john.doe@example.com")
+      .returning(move |_| Ok(Some(credentials.clone())));
+    credential_store.expect_update_password_hash().never();
+
+    // act
+    let service = LoginService::new(Box::new(credential_store), Box::new(hasher(1024)), token_pair_service());
+    let result = service.login(" john.doe@example.com ", "secret").await.unwrap();
+
+    // assert
+    assert_eq!(result.user, user);
+    let claims: AuthClaims = JwtUtil::new("some_key").extract_claims(&result.token_pair.access_token).await.unwrap();
+    assert_eq!(claims.user_details, user);
+  }
+
+  #[tokio::test]
+  async fn test_login_rehashes_outdated_hash() {
+    // arrange
+    let credentials = credentials(Some(hasher(1024).hash("secret").unwrap()));
+    let user_id = credentials.id;
+    let mut credential_store = MockICredentialStore::new();
+    credential_store.expect_find_by_email().returning(move |_| Ok(Some(credentials.clone())));
+    credential_store.expect_update_password_hash()
+      .withf(move |id, password_hash| *id == user_id && password_hash.starts_with("$argon2id$v=19$m=2048,"))
+      .times(1)
+      .returning(|_, _| Ok(()));
+
+    // act
+    let service = LoginService::new(Box::new(credential_store), Box::new(hasher(2048)), token_pair_service());
+    let result = service.login("john.doe@example.com", "secret").await;
+
+    // assert
+    assert!(result.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_login_invalid_credentials() {
+    // arrange
+    let credentials = credentials(Some(hasher(1024).hash("secret").unwrap()));
+    let mut credential_store = MockICredentialStore::new();
+    credential_store.expect_find_by_email().returning(move |email| {
+      Ok((email == "john.doe@example.com").then(|| credentials.clone()))
+    });
+    credential_store.expect_update_password_hash().never();
+
+    // act
+    let service = LoginService::new(Box::new(credential_store), Box::new(hasher(1024)), token_pair_service());
+    let wrong_password_result = service.login("john.doe@example.com", "wrong").await;
+    let unknown_email_result = service.login("jane.doe@example.com", "secret").await;
+
+    // assert
+    assert_eq!(error_message(wrong_password_result), "Invalid email or password");
+    assert_eq!(error_message(unknown_email_result), "Invalid email or password");
+  }
+
+  #[tokio::test]
+  async fn test_login_without_password() {
+    // arrange
+    let mut credential_store = MockICredentialStore::new();
+    credential_store.expect_find_by_email().returning(|_| Ok(Some(credentials(None))));
+
+    // act
+    let service = LoginService::new(Box::new(credential_store), Box::new(hasher(1024)), token_pair_service());
+    let result = service.login("john.doe@example.com", "secret").await;
+
+    // assert
+    assert_eq!(error_message(result), "Invalid email or password");
+  }
+
+  #[tokio::test]
+  async fn test_login_is_throttled_after_failures() {
+    // arrange
+    let credentials = credentials(Some(hasher(1024).hash("secret").unwrap()));
+    let mut credential_store = MockICredentialStore::new();
+    credential_store.expect_find_by_email().returning(move |_| Ok(Some(credentials.clone())));
+    let attempt_tracker = LoginAttemptTracker::new(Box::new(InMemoryLoginAttemptStore::new()))
+      .with_lockout(2, chrono::Duration::minutes(15));
+    let service = LoginService::new(Box::new(credential_store), Box::new(hasher(1024)), token_pair_service())
+      .with_attempt_tracker(attempt_tracker);
+    service.login_from("john.doe@example.com", "wrong", Some("203.0.113.7")).await.unwrap_err();
+    service.login_from("john.doe@example.com", "wrong", Some("203.0.113.7")).await.unwrap_err();
+
+    // act
+    let result = service.login_from("john.doe@example.com", "secret", Some("198.51.100.1")).await;
+
+    // assert
+    match result {
+      Err(Error::TooManyRequests(error)) => assert!(error.retry_after > 0),
+      _ => panic!("Expected TooManyRequests error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_successful_login_resets_failures() {
+    // arrange
+    let credentials = credentials(Some(hasher(1024).hash("secret").unwrap()));
+    let mut credential_store = MockICredentialStore::new();
+    credential_store.expect_find_by_email().returning(move |_| Ok(Some(credentials.clone())));
+    let attempt_tracker = LoginAttemptTracker::new(Box::new(InMemoryLoginAttemptStore::new()))
+      .with_lockout(2, chrono::Duration::minutes(15));
+    let service = LoginService::new(Box::new(credential_store), Box::new(hasher(1024)), token_pair_service())
+      .with_attempt_tracker(attempt_tracker);
+
+    // act
+    service.login("john.doe@example.com", "wrong").await.unwrap_err();
+    service.login("john.doe@example.com", "secret").await.unwrap();
+    let result = service.login("john.doe@example.com", "wrong").await;
+
+    // assert
+    assert_eq!(error_message(result), "Invalid email or password");
+  }
+}
@@ -0,0 +1,536 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use chrono::DateTime;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+  auth::{
+    credential_store::ICredentialStore,
+    email_sender::{EmailMessage, IEmailSender},
+    login_attempt_tracker::LoginAttemptTracker,
+    login_challenge_store::{ILoginChallengeStore, LoginChallenge},
+    mfa_service::MfaService,
+    token_pair_service::TokenPairService,
+  },
+  error::SerializableError,
+  types::{auth::{AuthUser, LoginResult, MagicLinkLogin}, utils::jwt_util::TokenType},
+  Error,
+};
+
+/// Passwordless login. `send` emails a single-use `MagicLink` token as a
+/// link together with a six digit code; either one can be exchanged once
+/// for a token pair, or an `MfaPending` token for users enrolled in TOTP
+/// when `with_mfa_service` is set.
+///
+/// Unknown emails are accepted silently so the response doesn't reveal
+/// which accounts exist.
+pub struct MagicLinkService {
+  credential_store: Box<dyn ICredentialStore>,
+  challenge_store: Box<dyn ILoginChallengeStore>,
+  email_sender: Box<dyn IEmailSender>,
+  token_pair_service: TokenPairService,
+  link_url: String,
+  subject: String,
+  max_attempts: i32,
+  max_open_challenges: i32,
+  attempt_tracker: Option<LoginAttemptTracker>,
+  mfa_service: Option<MfaService>,
+}
+
+impl MagicLinkService {
+  /// `link_url` is the page that reads the `token` query parameter and
+  /// calls `verify_link`, e.g. `https://app.example.com/login/magic`.
+  pub fn new(
+    credential_store: Box<dyn ICredentialStore>,
+    challenge_store: Box<dyn ILoginChallengeStore>,
+    email_sender: Box<dyn IEmailSender>,
+    token_pair_service: TokenPairService,
+    link_url: &str,
+  ) -> Self {
+    Self {
+      credential_store,
+      challenge_store,
+      email_sender,
+      token_pair_service,
+      link_url: link_url.to_string(),
+      subject: "Your sign-in link".to_string(),
+      max_attempts: 5,
+      max_open_challenges: 3,
+      attempt_tracker: None,
+      mfa_service: None,
+    }
+  }
+
+  pub fn with_subject(mut self, subject: &str) -> Self {
+    self.subject = subject.to_string();
+
+    self
+  }
+
+  /// Wrong codes allowed before outstanding codes for an email stop
+  /// working, 5 by default.
+  pub fn with_max_attempts(mut self, max_attempts: i32) -> Self {
+    self.max_attempts = max_attempts;
+
+    self
+  }
+
+  /// Unexpired, unused challenges an email may have at once, 3 by default.
+  /// Further `send` calls are ignored until one is used or expires, which
+  /// bounds the guesses per email to this times `max_attempts`.
+  pub fn with_max_open_challenges(mut self, max_open_challenges: i32) -> Self {
+    self.max_open_challenges = max_open_challenges;
+
+    self
+  }
+
+  /// Throttles wrong codes per email across challenges, and per client IP
+  /// when verified with `verify_code_from`.
+  pub fn with_attempt_tracker(mut self, attempt_tracker: LoginAttemptTracker) -> Self {
+    self.attempt_tracker = Some(attempt_tracker);
+
+    self
+  }
+
+  /// Requires the second factor from users enrolled in TOTP, like the
+  /// password login does. Without it the link or code alone logs them in.
+  pub fn with_mfa_service(mut self, mfa_service: MfaService) -> Self {
+    self.mfa_service = Some(mfa_service);
+
+    self
+  }
+
+  pub async fn send(&self, email: &str) -> Result<(), Error> {
+    let email = normalize_email(email);
+    let Some(credentials) = self.credential_store.find_by_email(&email).await? else {
+      return Ok(());
+    };
+
+    let token_issuer = self.token_pair_service.token_issuer();
+    let issued_token = token_issuer.sign(
+      token_issuer.claims(&credentials.user(), TokenType::MagicLink).with_claim("email", email.clone())
+    )?;
+    let code = generate_code();
+
+    let challenge = LoginChallenge {
+      id: issued_token.jti.clone(),
+      user_id: credentials.id,
+      email: email.clone(),
+      code_hash: hash_code(&email, &code),
+      expires_at: DateTime::from_timestamp(issued_token.expires_at as i64, 0).unwrap_or_default(),
+    };
+
+    if !self.challenge_store.save(&challenge, self.max_open_challenges).await? {
+      return Ok(());
+    }
+
+    let separator = if self.link_url.contains('?') { '&' } else { '?' };
+    let link = format!("{}{}token={}", self.link_url, separator, issued_token.token);
+
+    self.email_sender.send(&EmailMessage {
+      to: email,
+      subject: self.subject.clone(),
+      text_body: format!(
+        "Use this link to sign in:\n\n{}\n\nOr enter this code: {}\n\nBoth expire in {} minutes and work only once.",
+        link,
+        code,
+        issued_token.expires_in / 60,
+      ),
+    }).await
+  }
+
+  pub async fn verify_link(&self, token: &str) -> Result<MagicLinkLogin, Error> {
+    let claims = self.token_pair_service.token_issuer().jwt_util().extract_claims(token).await?;
+
+    if claims.token_type != TokenType::MagicLink {
+      return Err(unauthorized("Token is not a magic link token"));
+    }
+
+    let email = claims.claim("email").and_then(Value::as_str);
+    if email != Some(normalize_email(&claims.user_details.email).as_str()) {
+      return Err(unauthorized("Magic link token is not bound to this user"));
+    }
+
+    let token_id = claims.jti.as_deref().ok_or_else(|| unauthorized("Magic link token is missing a token id"))?;
+    if !self.challenge_store.consume_token(token_id).await? {
+      return Err(unauthorized("Magic link has already been used or has expired"));
+    }
+
+    let user = self.token_pair_service.current_principal(&claims.user_details).await?;
+
+    self.complete_login(user).await
+  }
+
+  pub async fn verify_code(&self, email: &str, code: &str) -> Result<MagicLinkLogin, Error> {
+    self.verify_code_from(email, code, None).await
+  }
+
+  /// `verify_code` for a request from `client_ip`, e.g. `auth::client_ip`
+  /// of the request.
+  pub async fn verify_code_from(&self, email: &str, code: &str, client_ip: Option<&str>) -> Result<MagicLinkLogin, Error> {
+    let email = normalize_email(email);
+    let verification = self.consume_code(&email, code);
+    let user = match self.attempt_tracker.as_ref() {
+      Some(attempt_tracker) => attempt_tracker.track(&email, client_ip, verification).await?,
+      None => verification.await?,
+    };
+
+    self.complete_login(user).await
+  }
+
+  async fn complete_login(&self, user: AuthUser) -> Result<MagicLinkLogin, Error> {
+    if let Some(mfa_service) = self.mfa_service.as_ref()
+      && mfa_service.is_enrolled(user.id).await? {
+      let mfa_token = mfa_service.issue_mfa_token(&user)?;
+      return Ok(MagicLinkLogin::MfaRequired { user, mfa_token });
+    }
+
+    let token_pair = self.token_pair_service.issue(&user).await?;
+
+    Ok(MagicLinkLogin::Complete(LoginResult { user, token_pair }))
+  }
+
+  async fn consume_code(&self, email: &str, code: &str) -> Result<AuthUser, Error> {
+    let user_id = self.challenge_store
+      .consume_code(email, &hash_code(email, code.trim()), self.max_attempts)
+      .await?
+      .ok_or_else(|| unauthorized("Invalid or expired code"))?;
+
+    Ok(self.credential_store.find_by_email(email).await?
+      .filter(|credentials| credentials.id == user_id)
+      .ok_or_else(|| unauthorized("Invalid or expired code"))?
+      .user())
+  }
+}
+
+fn normalize_email(email: &str) -> String {
+  email.trim().to_lowercase()
+}
+
+fn generate_code() -> String {
+  format!("{:06}", OsRng.next_u32() % 1_000_000)
+}
+
+fn hash_code(email: &str, code: &str) -> String {
+  Sha256::digest(format!("{}:{}", email, code).as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+
+  use chrono::Utc;
+  use uuid::Uuid;
+
+  use crate::{
+    auth::{
+      credential_store::{MockICredentialStore, UserCredentials},
+      email_sender::InMemoryEmailSender,
+      login_attempt_store::InMemoryLoginAttemptStore,
+      login_challenge_store::MockILoginChallengeStore,
+      mfa_store::{MockIMfaStore, TotpCredential},
+      principal_loader::MockIPrincipalLoader,
+      refresh_token_store::InMemoryRefreshTokenStore,
+    },
+    utils::{JwtUtil, TotpUtil},
+  };
+
+  fn credentials() -> UserCredentials {
+    UserCredentials {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+      password_hash: None,
+    }
+  }
+
+  fn credential_store(credentials: UserCredentials) -> MockICredentialStore {
+    let mut credential_store = MockICredentialStore::new();
+    credential_store.expect_find_by_email()
+      .returning(move |email| Ok((email == credentials.email).then(|| credentials.clone())));
+
+    credential_store
+  }
+
+  /// A challenge store that keeps challenges in memory like the Postgres one.
+  fn challenge_store() -> MockILoginChallengeStore {
+    let challenges: Arc<Mutex<Vec<(LoginChallenge, bool)>>> = Arc::default();
+    let mut challenge_store = MockILoginChallengeStore::new();
+
+    let saved = challenges.clone();
+    challenge_store.expect_save().returning(move |challenge, max_open| {
+      let mut challenges = saved.lock().unwrap();
+      let open = challenges.iter().filter(|(saved, consumed)| saved.email == challenge.email && !consumed).count();
+      if open >= max_open as usize {
+        return Ok(false);
+      }
+
+      challenges.push((challenge.clone(), false));
+      Ok(true)
+    });
+
+    let by_token = challenges.clone();
+    challenge_store.expect_consume_token().returning(move |id| {
+      let mut challenges = by_token.lock().unwrap();
+      Ok(challenges.iter_mut()
+        .find(|(challenge, consumed)| challenge.id == id && !consumed)
+        .map(|(_, consumed)| *consumed = true)
+        .is_some())
+    });
+
+    let by_code = challenges.clone();
+    challenge_store.expect_consume_code().returning(move |email, code_hash, _| {
+      let mut challenges = by_code.lock().unwrap();
+      Ok(challenges.iter_mut()
+        .rev()
+        .find(|(challenge, consumed)| challenge.email == email && !consumed)
+        .filter(|(challenge, _)| challenge.code_hash == code_hash)
+        .map(|(challenge, consumed)| {
+          *consumed = true;
+          challenge.user_id
+        }))
+    });
+
+    challenge_store
+  }
+
+  fn service(credentials: UserCredentials, email_sender: InMemoryEmailSender) -> MagicLinkService {
+    MagicLinkService::new(
+      Box::new(credential_store(credentials)),
+      Box::new(challenge_store()),
+      Box::new(email_sender),
+      TokenPairService::new(Box::new(JwtUtil::new("some_key")), Box::new(InMemoryRefreshTokenStore::new())),
+      "https://app.example.com/login/magic",
+    )
+  }
+
+  fn link_token(message: &EmailMessage) -> String {
+    let start = message.text_body.find("?token=").unwrap() + "?token=".len();
+
+    message.text_body[start..].split_whitespace().next().unwrap().to_string()
+  }
+
+  fn code(message: &EmailMessage) -> String {
+    message.text_body.split("enter this code: ").nth(1).unwrap()[..6].to_string()
+  }
+
+  fn login_result(result: MagicLinkLogin) -> LoginResult {
+    match result {
+      MagicLinkLogin::Complete(login_result) => login_result,
+      MagicLinkLogin::MfaRequired { .. } => panic!("Expected a completed login"),
+    }
+  }
+
+  fn error_message(result: Result<MagicLinkLogin, Error>) -> String {
+    match result {
+      Err(Error::Unauthorized(error)) => error.message,
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_verify_link_once() {
+    // arrange
+    let credentials = credentials();
+    let email_sender = InMemoryEmailSender::new();
+    let service = service(credentials.clone(), email_sender.clone());
+    service.send(" John.Doe@example.com ").await.unwrap();
+    let message = email_sender.last_message_to("john.doe@example.com").unwrap();
+
+    // act
+    let result = service.verify_link(&link_token(&message)).await.unwrap();
+    let replay_result = service.verify_link(&link_token(&message)).await;
+
+    // assert
+    assert_eq!(message.subject, "Your sign-in link");
+    assert_eq!(login_result(result).user, credentials.user());
+    assert_eq!(error_message(replay_result), "Magic link has already been used or has expired");
+  }
+
+  #[tokio::test]
+  async fn test_verify_code_once() {
+    // arrange
+    let credentials = credentials();
+    let email_sender = InMemoryEmailSender::new();
+    let service = service(credentials.clone(), email_sender.clone());
+    service.send("john.doe@example.com").await.unwrap();
+    let code = code(&email_sender.messages()[0]);
+
+    // act
+    let result = service.verify_code("john.doe@example.com", &code).await.unwrap();
+    let replay_result = service.verify_code("john.doe@example.com", &code).await;
+
+    // assert
+    assert_eq!(login_result(result).user, credentials.user());
+    assert_eq!(error_message(replay_result), "Invalid or expired code");
+  }
+
+  #[tokio::test]
+  async fn test_verify_requires_mfa_when_enrolled() {
+    // arrange
+    let credentials = credentials();
+    let user_id = credentials.id;
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_find_totp().returning(move |_| Ok(Some(TotpCredential {
+      user_id,
+      secret: Some("JBSWY3DPEHPK3PXP".to_string()),
+      confirmed_at: Some(Utc::now()),
+      pending_secret: None,
+      last_used_step: None,
+    })));
+    let mfa_service = MfaService::new(
+      TokenPairService::new(Box::new(JwtUtil::new("some_key")), Box::new(InMemoryRefreshTokenStore::new())),
+      Box::new(mfa_store),
+      TotpUtil::new("Ferrum"),
+    );
+    let email_sender = InMemoryEmailSender::new();
+    let service = service(credentials.clone(), email_sender.clone()).with_mfa_service(mfa_service);
+    service.send("john.doe@example.com").await.unwrap();
+    service.send("john.doe@example.com").await.unwrap();
+    let messages = email_sender.messages();
+
+    // act
+    let link_result = service.verify_link(&link_token(&messages[0])).await.unwrap();
+    let code_result = service.verify_code("john.doe@example.com", &code(&messages[1])).await.unwrap();
+
+    // assert
+    for result in [link_result, code_result] {
+      match result {
+        MagicLinkLogin::MfaRequired { user, mfa_token } => {
+          assert_eq!(user, credentials.user());
+          assert_eq!(mfa_token.token_type, TokenType::MfaPending);
+        },
+        MagicLinkLogin::Complete(_) => panic!("Expected MFA to be required"),
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn test_verify_without_confirmed_totp_logs_in() {
+    // arrange
+    let credentials = credentials();
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_find_totp().returning(|_| Ok(None));
+    let mfa_service = MfaService::new(
+      TokenPairService::new(Box::new(JwtUtil::new("some_key")), Box::new(InMemoryRefreshTokenStore::new())),
+      Box::new(mfa_store),
+      TotpUtil::new("Ferrum"),
+    );
+    let email_sender = InMemoryEmailSender::new();
+    let service = service(credentials.clone(), email_sender.clone()).with_mfa_service(mfa_service);
+    service.send("john.doe@example.com").await.unwrap();
+    let message = email_sender.last_message_to("john.doe@example.com").unwrap();
+
+    // act
+    let result = service.verify_link(&link_token(&message)).await.unwrap();
+
+    // assert
+    assert_eq!(login_result(result).user, credentials.user());
+  }
+
+  #[tokio::test]
+  async fn test_send_stops_at_max_open_challenges() {
+    // arrange
+    let credentials = credentials();
+    let email_sender = InMemoryEmailSender::new();
+    let service = service(credentials.clone(), email_sender.clone()).with_max_open_challenges(2);
+
+    // act
+    for _ in 0..3 {
+      service.send("john.doe@example.com").await.unwrap();
+    }
+    let sent_before_use = email_sender.messages().len();
+    let code = code(&email_sender.messages()[1]);
+    service.verify_code("john.doe@example.com", &code).await.unwrap();
+    service.send("john.doe@example.com").await.unwrap();
+
+    // assert
+    assert_eq!(sent_before_use, 2);
+    assert_eq!(email_sender.messages().len(), 3);
+  }
+
+  #[tokio::test]
+  async fn test_verify_code_is_throttled_across_challenges() {
+    // arrange
+    let email_sender = InMemoryEmailSender::new();
+    let attempt_tracker = LoginAttemptTracker::new(Box::new(InMemoryLoginAttemptStore::new()))
+      .with_lockout(2, chrono::Duration::minutes(15));
+    let service = service(credentials(), email_sender.clone()).with_attempt_tracker(attempt_tracker);
+    service.send("john.doe@example.com").await.unwrap();
+    service.verify_code_from("john.doe@example.com", "000000", Some("203.0.113.7")).await.unwrap_err();
+    service.verify_code_from("john.doe@example.com", "000001", Some("203.0.113.7")).await.unwrap_err();
+    service.send("john.doe@example.com").await.unwrap();
+    let code = code(&email_sender.messages()[1]);
+
+    // act
+    let result = service.verify_code_from("john.doe@example.com", &code, Some("198.51.100.1")).await;
+
+    // assert
+    match result {
+      Err(Error::TooManyRequests(error)) => assert!(error.retry_after > 0),
+      _ => panic!("Expected TooManyRequests error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_send_to_unknown_email() {
+    // arrange
+    let email_sender = InMemoryEmailSender::new();
+    let service = service(credentials(), email_sender.clone());
+
+    // act
+    let result = service.send("jane.doe@example.com").await;
+
+    // assert
+    assert!(result.is_ok());
+    assert!(email_sender.messages().is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_verify_link_rejects_access_token() {
+    // arrange
+    let credentials = credentials();
+    let service = service(credentials.clone(), InMemoryEmailSender::new());
+    let token_pair = service.token_pair_service.issue(&credentials.user()).await.unwrap();
+
+    // act
+    let result = service.verify_link(&token_pair.access_token).await;
+
+    // assert
+    assert_eq!(error_message(result), "Token is not a magic link token");
+  }
+
+  #[tokio::test]
+  async fn test_verify_link_rejects_deactivated_user() {
+    // arrange
+    let email_sender = InMemoryEmailSender::new();
+    let mut principal_loader = MockIPrincipalLoader::new();
+    principal_loader.expect_load().returning(|_| Ok(None));
+    let token_pair_service = TokenPairService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(InMemoryRefreshTokenStore::new()),
+    ).with_principal_loader(Box::new(principal_loader));
+    let service = MagicLinkService::new(
+      Box::new(credential_store(credentials())),
+      Box::new(challenge_store()),
+      Box::new(email_sender.clone()),
+      token_pair_service,
+      "https://app.example.com/login/magic",
+    );
+    service.send("john.doe@example.com").await.unwrap();
+    let message = email_sender.last_message_to("john.doe@example.com").unwrap();
+
+    // act
+    let result = service.verify_link(&link_token(&message)).await;
+
+    // assert
+    assert_eq!(error_message(result), "User no longer exists or has been deactivated");
+  }
+}
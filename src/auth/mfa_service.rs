@@ -0,0 +1,542 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+  auth::{
+    login_attempt_tracker::LoginAttemptTracker,
+    mfa_store::{IMfaStore, TotpCredential},
+    token_pair_service::TokenPairService,
+  },
+  error::SerializableError,
+  types::{
+    auth::{AuthPayload, AuthUser, IssuedToken, TokenPair, TotpEnrollment},
+    utils::jwt_util::{AuthClaims, TokenType},
+  },
+  utils::totp_util::TotpUtil,
+  Error,
+};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// A code that checked out but hasn't been used up yet.
+enum VerifiedCode {
+  TotpStep(u64),
+  RecoveryCode(String),
+}
+
+/// TOTP second factor on top of a password login.
+///
+/// After the password check, `issue_mfa_token` hands out a short-lived
+/// `MfaPending` token that `Auth` refuses; `complete` exchanges it together
+/// with a TOTP or recovery code for a regular token pair, once.
+pub struct MfaService<T: AuthPayload = AuthUser> {
+  token_pair_service: TokenPairService<T>,
+  mfa_store: Box<dyn IMfaStore>,
+  totp_util: TotpUtil,
+  max_attempts: i32,
+  attempt_tracker: Option<LoginAttemptTracker>,
+}
+
+impl<T: AuthPayload> MfaService<T> {
+  pub fn new(token_pair_service: TokenPairService<T>, mfa_store: Box<dyn IMfaStore>, totp_util: TotpUtil) -> Self {
+    Self { token_pair_service, mfa_store, totp_util, max_attempts: 5, attempt_tracker: None }
+  }
+
+  /// Codes that can be tried with one MFA token before the user has to
+  /// log in again, 5 by default.
+  pub fn with_max_attempts(mut self, max_attempts: i32) -> Self {
+    self.max_attempts = max_attempts;
+
+    self
+  }
+
+  /// Throttles wrong codes per user across MFA tokens, and per client IP
+  /// when completed with `complete_from`.
+  pub fn with_attempt_tracker(mut self, attempt_tracker: LoginAttemptTracker) -> Self {
+    self.attempt_tracker = Some(attempt_tracker);
+
+    self
+  }
+
+  /// Starts enrollment with a fresh secret. It only replaces the current
+  /// secret, if any, after `confirm_enrollment`.
+  pub async fn enroll(&self, user_id: Uuid, account_name: &str) -> Result<TotpEnrollment, Error> {
+    let secret = self.totp_util.generate_secret();
+    self.mfa_store.save_totp(user_id, &secret).await?;
+
+    Ok(TotpEnrollment {
+      provisioning_uri: self.totp_util.provisioning_uri(&secret, account_name),
+      secret,
+    })
+  }
+
+  /// Checks the first code from the authenticator app and returns the
+  /// recovery codes to show the user once.
+  pub async fn confirm_enrollment(&self, user_id: Uuid, code: &str) -> Result<Vec<String>, Error> {
+    let pending_secret = self.mfa_store.find_totp(user_id).await?
+      .and_then(|credential| credential.pending_secret)
+      .ok_or_else(|| unauthorized("There is no enrollment to confirm"))?;
+
+    self.verify_totp(user_id, &pending_secret, code).await?;
+    if !self.mfa_store.confirm_totp(user_id, &pending_secret).await? {
+      return Err(unauthorized("Enrollment was restarted, confirm the newest secret"));
+    }
+
+    self.regenerate_recovery_codes(user_id).await
+  }
+
+  pub async fn is_enrolled(&self, user_id: Uuid) -> Result<bool, Error> {
+    Ok(self.mfa_store.find_totp(user_id).await?.is_some_and(|credential| credential.secret.is_some()))
+  }
+
+  /// Replaces all recovery codes of the user.
+  pub async fn regenerate_recovery_codes(&self, user_id: Uuid) -> Result<Vec<String>, Error> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+    let code_hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+
+    self.mfa_store.replace_recovery_codes(user_id, &code_hashes).await?;
+
+    Ok(codes)
+  }
+
+  pub fn issue_mfa_token(&self, user: &T) -> Result<IssuedToken, Error> {
+    self.token_pair_service.token_issuer().issue(user, TokenType::MfaPending)
+  }
+
+  /// Exchanges an `MfaPending` token and a TOTP or recovery code for a
+  /// token pair. Each MFA token works once and allows `max_attempts`
+  /// codes to be tried.
+  pub async fn complete(&self, mfa_token: &str, code: &str) -> Result<TokenPair, Error> {
+    self.complete_from(mfa_token, code, None).await
+  }
+
+  /// `complete` for a request from `client_ip`, e.g. `auth::client_ip` of
+  /// the request.
+  pub async fn complete_from(&self, mfa_token: &str, code: &str, client_ip: Option<&str>) -> Result<TokenPair, Error> {
+    let claims = self.token_pair_service.token_issuer().jwt_util().extract_claims(mfa_token).await?;
+
+    if claims.token_type != TokenType::MfaPending {
+      return Err(unauthorized("Token is not an MFA token"));
+    }
+
+    let verification = self.verify_completion(&claims, code);
+    match self.attempt_tracker.as_ref() {
+      Some(attempt_tracker) => {
+        attempt_tracker.track_user(claims.user_details.user_id(), client_ip, verification).await?
+      },
+      None => verification.await?,
+    }
+
+    let user = self.token_pair_service.current_principal(&claims.user_details).await?;
+    self.token_pair_service.issue(&user).await
+  }
+
+  async fn verify_completion(&self, claims: &AuthClaims<T>, code: &str) -> Result<(), Error> {
+    let jti = claims.jti.as_deref().ok_or_else(|| unauthorized("MFA token is missing a token id"))?;
+    let expires_at = DateTime::from_timestamp(claims.expires_in as i64, 0).unwrap_or_default();
+
+    // The attempt is counted before the code is checked, so concurrent
+    // guesses can't get past `max_attempts`.
+    if !self.mfa_store.record_mfa_token_attempt(jti, expires_at, self.max_attempts).await? {
+      let attempts = self.mfa_store.find_mfa_token_attempts(jti).await?;
+      if attempts.is_some_and(|attempts| attempts.consumed_at.is_some()) {
+        return Err(already_used());
+      }
+
+      return Err(unauthorized("Too many invalid verification codes, log in again"));
+    }
+
+    let user_id = claims.user_details.user_id();
+    let credential = self.mfa_store.find_totp(user_id).await?
+      .filter(|credential| credential.secret.is_some())
+      .ok_or_else(|| unauthorized("Multi-factor authentication is not set up"))?;
+    let verified_code = self.check_code(&credential, code.trim()).await?;
+
+    // The token is consumed before the code is used up, so losing a race
+    // for the token doesn't burn a recovery code.
+    if !self.mfa_store.consume_mfa_token(jti).await? {
+      return Err(already_used());
+    }
+
+    self.use_code(user_id, verified_code).await
+  }
+
+  /// Checks a TOTP or recovery code without using it up.
+  async fn check_code(&self, credential: &TotpCredential, code: &str) -> Result<VerifiedCode, Error> {
+    if code.len() == self.totp_util.digits() as usize && code.bytes().all(|byte| byte.is_ascii_digit()) {
+      let secret = credential.secret.as_deref().unwrap_or_default();
+      let step = self.totp_util.verify(secret, code, Utc::now().timestamp() as u64)?
+        .ok_or_else(invalid_code)?;
+
+      if credential.last_used_step.is_some_and(|last_used_step| step as i64 <= last_used_step) {
+        return Err(code_already_used());
+      }
+
+      return Ok(VerifiedCode::TotpStep(step));
+    }
+
+    let code_hash = hash_recovery_code(code);
+    if !self.mfa_store.has_recovery_code(credential.user_id, &code_hash).await? {
+      return Err(invalid_code());
+    }
+
+    Ok(VerifiedCode::RecoveryCode(code_hash))
+  }
+
+  async fn use_code(&self, user_id: Uuid, verified_code: VerifiedCode) -> Result<(), Error> {
+    match verified_code {
+      VerifiedCode::TotpStep(step) if !self.mfa_store.use_totp_step(user_id, step as i64).await? => {
+        Err(code_already_used())
+      },
+      VerifiedCode::RecoveryCode(code_hash) if !self.mfa_store.use_recovery_code(user_id, &code_hash).await? => {
+        Err(code_already_used())
+      },
+      _ => Ok(()),
+    }
+  }
+
+  async fn verify_totp(&self, user_id: Uuid, secret: &str, code: &str) -> Result<(), Error> {
+    let step = self.totp_util.verify(secret, code, Utc::now().timestamp() as u64)?
+      .ok_or_else(invalid_code)?;
+
+    if !self.mfa_store.use_totp_step(user_id, step as i64).await? {
+      return Err(code_already_used());
+    }
+
+    Ok(())
+  }
+}
+
+/// Ten hex characters as `xxxxx-xxxxx`.
+fn generate_recovery_code() -> String {
+  let mut bytes = [0u8; 5];
+  OsRng.fill_bytes(&mut bytes);
+  let code: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+  format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Recovery codes are hashed after normalizing case and separators, so
+/// `ABCDE-12345` and `abcde12345` match.
+fn hash_recovery_code(code: &str) -> String {
+  let normalized: String = code.chars()
+    .filter(char::is_ascii_alphanumeric)
+    .map(|character| character.to_ascii_lowercase())
+    .collect();
+
+  Sha256::digest(normalized.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn already_used() -> Error {
+  unauthorized("MFA token has already been used")
+}
+
+fn code_already_used() -> Error {
+  unauthorized("Verification code has already been used")
+}
+
+fn invalid_code() -> Error {
+  unauthorized("Invalid verification code")
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+
+  use crate::{
+    auth::{
+      login_attempt_store::InMemoryLoginAttemptStore,
+      mfa_store::{MfaTokenAttempts, MockIMfaStore, TotpCredential},
+      refresh_token_store::InMemoryRefreshTokenStore,
+    },
+    types::utils::jwt_util::AuthClaims,
+    utils::{jwt_util::IJwtUtil, JwtUtil},
+  };
+
+  fn auth_user() -> AuthUser {
+    AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    }
+  }
+
+  fn service(mfa_store: MockIMfaStore) -> MfaService {
+    let token_pair_service = TokenPairService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(InMemoryRefreshTokenStore::new()),
+    );
+
+    MfaService::new(token_pair_service, Box::new(mfa_store), TotpUtil::new("Ferrum"))
+  }
+
+  fn confirmed_credential(user_id: Uuid, secret: &str) -> TotpCredential {
+    TotpCredential {
+      user_id,
+      secret: Some(secret.to_string()),
+      confirmed_at: Some(Utc::now()),
+      pending_secret: None,
+      last_used_step: None,
+    }
+  }
+
+  fn allow_mfa_token_attempts(mfa_store: &mut MockIMfaStore) {
+    mfa_store.expect_record_mfa_token_attempt().returning(|_, _, max_attempts| Ok(max_attempts == 5));
+  }
+
+  fn current_code(secret: &str) -> String {
+    TotpUtil::new("Ferrum").code_at(secret, Utc::now().timestamp() as u64).unwrap()
+  }
+
+  fn error_message<V: std::fmt::Debug>(result: Result<V, Error>) -> String {
+    match result {
+      Err(Error::Unauthorized(error)) => error.message,
+      result => panic!("Expected Unauthorized error, got {:?}", result),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_complete_with_totp() {
+    // arrange
+    let user = auth_user();
+    let secret = TotpUtil::new("Ferrum").generate_secret();
+    let credential = confirmed_credential(user.id, &secret);
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_find_totp().returning(move |_| Ok(Some(credential.clone())));
+    mfa_store.expect_use_totp_step().times(1).returning(|_, _| Ok(true));
+    allow_mfa_token_attempts(&mut mfa_store);
+    mfa_store.expect_consume_mfa_token().times(1).returning(|_| Ok(true));
+    let service = service(mfa_store);
+    let mfa_token = service.issue_mfa_token(&user).unwrap();
+
+    // act
+    let token_pair = service.complete(&mfa_token.token, &current_code(&secret)).await.unwrap();
+
+    // assert
+    assert_eq!(mfa_token.token_type, TokenType::MfaPending);
+    assert!(mfa_token.expires_in <= 300);
+    let claims: AuthClaims = JwtUtil::new("some_key").extract_claims(&token_pair.access_token).await.unwrap();
+    assert_eq!(claims.token_type, TokenType::AccessToken);
+    assert_eq!(claims.user_details, user);
+  }
+
+  #[tokio::test]
+  async fn test_complete_rejects_replayed_code() {
+    // arrange
+    let user = auth_user();
+    let totp_util = TotpUtil::new("Ferrum");
+    let secret = totp_util.generate_secret();
+    let credential = TotpCredential {
+      last_used_step: Some(totp_util.step(Utc::now().timestamp() as u64) as i64),
+      ..confirmed_credential(user.id, &secret)
+    };
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_find_totp().returning(move |_| Ok(Some(credential.clone())));
+    allow_mfa_token_attempts(&mut mfa_store);
+    mfa_store.expect_consume_mfa_token().never();
+    mfa_store.expect_use_totp_step().never();
+    let service = service(mfa_store);
+    let mfa_token = service.issue_mfa_token(&user).unwrap();
+
+    // act
+    let result = service.complete(&mfa_token.token, &current_code(&secret)).await;
+
+    // assert
+    assert_eq!(error_message(result), "Verification code has already been used");
+  }
+
+  #[tokio::test]
+  async fn test_complete_consumes_mfa_token_before_using_code() {
+    // arrange
+    let user = auth_user();
+    let credential = confirmed_credential(user.id, &TotpUtil::new("Ferrum").generate_secret());
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_find_totp().returning(move |_| Ok(Some(credential.clone())));
+    mfa_store.expect_has_recovery_code().returning(|_, _| Ok(true));
+    allow_mfa_token_attempts(&mut mfa_store);
+    mfa_store.expect_consume_mfa_token().times(1).returning(|_| Ok(false));
+    mfa_store.expect_use_recovery_code().never();
+    let service = service(mfa_store);
+
+    // act
+    let result = service.complete(&service.issue_mfa_token(&user).unwrap().token, "abcde-12345").await;
+
+    // assert
+    assert_eq!(error_message(result), "MFA token has already been used");
+  }
+
+  #[tokio::test]
+  async fn test_complete_with_recovery_code() {
+    // arrange
+    let user = auth_user();
+    let credential = confirmed_credential(user.id, &TotpUtil::new("Ferrum").generate_secret());
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_find_totp().returning(move |_| Ok(Some(credential.clone())));
+    mfa_store.expect_has_recovery_code()
+      .returning(|_, code_hash| Ok(code_hash == hash_recovery_code("abcde-12345")));
+    mfa_store.expect_use_recovery_code().times(1).returning(|_, _| Ok(true));
+    allow_mfa_token_attempts(&mut mfa_store);
+    mfa_store.expect_consume_mfa_token().times(1).returning(|_| Ok(true));
+    let service = service(mfa_store);
+
+    // act
+    let result = service.complete(&service.issue_mfa_token(&user).unwrap().token, "ABCDE12345").await;
+    let invalid_result = service.complete(&service.issue_mfa_token(&user).unwrap().token, "fffff-fffff").await;
+
+    // assert
+    assert!(result.is_ok());
+    assert_eq!(error_message(invalid_result), "Invalid verification code");
+  }
+
+  #[tokio::test]
+  async fn test_complete_rejects_access_token() {
+    // arrange
+    let user = auth_user();
+    let service = service(MockIMfaStore::new());
+    let token_pair = service.token_pair_service.issue(&user).await.unwrap();
+
+    // act
+    let result = service.complete(&token_pair.access_token, "123456").await;
+
+    // assert
+    assert_eq!(error_message(result), "Token is not an MFA token");
+  }
+
+  #[tokio::test]
+  async fn test_enroll_and_confirm() {
+    // arrange
+    let user_id = Uuid::new_v4();
+    let mut mfa_store = MockIMfaStore::new();
+    let saved_secret = Arc::new(Mutex::new(String::new()));
+    let save_target = saved_secret.clone();
+    let find_source = saved_secret.clone();
+    mfa_store.expect_save_totp().times(1).returning(move |_, secret| {
+      *save_target.lock().unwrap() = secret.to_string();
+      Ok(())
+    });
+    mfa_store.expect_find_totp().returning(move |user_id| Ok(Some(TotpCredential {
+      user_id,
+      secret: None,
+      confirmed_at: None,
+      pending_secret: Some(find_source.lock().unwrap().clone()),
+      last_used_step: None,
+    })));
+    mfa_store.expect_use_totp_step().returning(|_, _| Ok(true));
+    let confirm_source = saved_secret.clone();
+    mfa_store.expect_confirm_totp()
+      .withf(move |_, secret| secret == *confirm_source.lock().unwrap())
+      .times(1)
+      .returning(|_, _| Ok(true));
+    mfa_store.expect_replace_recovery_codes()
+      .withf(|_, code_hashes| code_hashes.len() == RECOVERY_CODE_COUNT)
+      .times(1)
+      .returning(|_, _| Ok(()));
+    let service = service(mfa_store);
+
+    // act
+    let enrollment = service.enroll(user_id, "john.doe@example.com").await.unwrap();
+    let recovery_codes = service.confirm_enrollment(user_id, &current_code(&enrollment.secret)).await.unwrap();
+
+    // assert
+    assert!(enrollment.provisioning_uri.starts_with("otpauth://totp/Ferrum:john.doe@example.com?secret="));
+    assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+    assert!(recovery_codes.iter().all(|code| code.len() == 11 && code.as_bytes()[5] == b'-'));
+  }
+
+  #[tokio::test]
+  async fn test_reenrollment_keeps_confirmed_secret_until_confirmed() {
+    // arrange
+    let user = auth_user();
+    let totp_util = TotpUtil::new("Ferrum");
+    let confirmed_secret = totp_util.generate_secret();
+    let pending_secret = totp_util.generate_secret();
+    let credential = TotpCredential {
+      pending_secret: Some(pending_secret.clone()),
+      ..confirmed_credential(user.id, &confirmed_secret)
+    };
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_save_totp().times(1).returning(|_, _| Ok(()));
+    mfa_store.expect_confirm_totp().never();
+    mfa_store.expect_find_totp().returning(move |_| Ok(Some(credential.clone())));
+    mfa_store.expect_use_totp_step().times(1).returning(|_, _| Ok(true));
+    allow_mfa_token_attempts(&mut mfa_store);
+    mfa_store.expect_consume_mfa_token().times(1).returning(|_| Ok(true));
+    let service = service(mfa_store);
+
+    // act
+    service.enroll(user.id, "john.doe@example.com").await.unwrap();
+    let pending_code_result = service.complete(&service.issue_mfa_token(&user).unwrap().token, &current_code(&pending_secret)).await;
+    let confirmed_code_result = service.complete(&service.issue_mfa_token(&user).unwrap().token, &current_code(&confirmed_secret)).await;
+
+    // assert
+    assert!(service.is_enrolled(user.id).await.unwrap());
+    assert_eq!(error_message(pending_code_result), "Invalid verification code");
+    assert!(confirmed_code_result.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_complete_rejects_used_or_exhausted_token() {
+    // arrange
+    let user = auth_user();
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_record_mfa_token_attempt().times(2).returning(|_, _, _| Ok(false));
+    mfa_store.expect_find_mfa_token_attempts().times(2).returning(|jti| Ok(Some(MfaTokenAttempts {
+      jti: jti.to_string(),
+      attempts: if jti == "exhausted" { 5 } else { 1 },
+      consumed_at: (jti == "used").then(Utc::now),
+    })));
+    mfa_store.expect_find_totp().never();
+    let service = service(mfa_store);
+    let token_issuer = service.token_pair_service.token_issuer();
+    let used_token = token_issuer.sign(token_issuer.claims(&user, TokenType::MfaPending).with_jti("used")).unwrap();
+    let exhausted_token = token_issuer.sign(token_issuer.claims(&user, TokenType::MfaPending).with_jti("exhausted")).unwrap();
+
+    // act
+    let used_result = service.complete(&used_token.token, "123456").await;
+    let exhausted_result = service.complete(&exhausted_token.token, "123456").await;
+
+    // assert
+    assert_eq!(error_message(used_result), "MFA token has already been used");
+    assert_eq!(error_message(exhausted_result), "Too many invalid verification codes, log in again");
+  }
+
+  #[tokio::test]
+  async fn test_complete_is_throttled_across_mfa_tokens() {
+    // arrange
+    let user = auth_user();
+    let secret = TotpUtil::new("Ferrum").generate_secret();
+    let credential = confirmed_credential(user.id, &secret);
+    let mut mfa_store = MockIMfaStore::new();
+    mfa_store.expect_find_totp().returning(move |_| Ok(Some(credential.clone())));
+    allow_mfa_token_attempts(&mut mfa_store);
+    mfa_store.expect_has_recovery_code().times(2).returning(|_, _| Ok(false));
+    mfa_store.expect_consume_mfa_token().never();
+    mfa_store.expect_use_totp_step().never();
+    let attempt_tracker = LoginAttemptTracker::new(Box::new(InMemoryLoginAttemptStore::new()))
+      .with_lockout(2, chrono::Duration::minutes(15));
+    let service = service(mfa_store).with_attempt_tracker(attempt_tracker);
+    for _ in 0..2 {
+      let mfa_token = service.issue_mfa_token(&user).unwrap();
+      service.complete_from(&mfa_token.token, "wrong-code", Some("203.0.113.7")).await.unwrap_err();
+    }
+    let mfa_token = service.issue_mfa_token(&user).unwrap();
+
+    // act
+    let result = service.complete_from(&mfa_token.token, &current_code(&secret), Some("198.51.100.1")).await;
+
+    // assert
+    match result {
+      Err(Error::TooManyRequests(error)) => assert!(error.retry_after > 0),
+      _ => panic!("Expected TooManyRequests error"),
+    }
+  }
+}
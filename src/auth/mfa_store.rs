@@ -0,0 +1,206 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct TotpCredential {
+  pub user_id: Uuid,
+  /// The secret in use, set once the user proved the authenticator app
+  /// works.
+  pub secret: Option<String>,
+  pub confirmed_at: Option<DateTime<Utc>>,
+  /// A secret from a newer enrollment that hasn't been confirmed yet.
+  pub pending_secret: Option<String>,
+  pub last_used_step: Option<i64>,
+}
+
+/// Codes entered with one `MfaPending` token, keyed by its `jti`.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct MfaTokenAttempts {
+  pub jti: String,
+  pub attempts: i32,
+  pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IMfaStore: Send + Sync {
+  async fn find_totp(&self, user_id: Uuid) -> Result<Option<TotpCredential>, Error>;
+
+  /// Stores a new secret pending confirmation, replacing any previous
+  /// pending one. A confirmed secret stays in use until `confirm_totp`.
+  async fn save_totp(&self, user_id: Uuid, secret: &str) -> Result<(), Error>;
+
+  /// Swaps the pending secret in if it is still `secret`. Returns `false`
+  /// if it was replaced in the meantime.
+  async fn confirm_totp(&self, user_id: Uuid, secret: &str) -> Result<bool, Error>;
+
+  /// Records `step` as used. Returns `false` if it, or a later step, was
+  /// already used, i.e. the code is being replayed.
+  async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, Error>;
+
+  async fn replace_recovery_codes(&self, user_id: Uuid, code_hashes: &[String]) -> Result<(), Error>;
+
+  /// Marks an unused recovery code as used. Returns `false` if there is no
+  /// such unused code.
+  async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, Error>;
+
+  /// Whether the user has this recovery code and hasn't used it yet.
+  async fn has_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, Error>;
+
+  async fn find_mfa_token_attempts(&self, jti: &str) -> Result<Option<MfaTokenAttempts>, Error>;
+
+  /// Counts an attempt against the MFA token before its code is checked.
+  /// Returns `false`, without counting, if the token was already used or
+  /// has had `max_attempts` attempts.
+  async fn record_mfa_token_attempt(&self, jti: &str, expires_at: DateTime<Utc>, max_attempts: i32) -> Result<bool, Error>;
+
+  /// Marks the MFA token as used. Returns `false` if it already was.
+  async fn consume_mfa_token(&self, jti: &str) -> Result<bool, Error>;
+}
+
+/// `IMfaStore` backed by the `user_totp`, `user_recovery_codes` and
+/// `mfa_token_attempts` tables from `database::MIGRATOR`.
+#[derive(Clone)]
+pub struct PgMfaStore {
+  pool: PgPool,
+}
+
+impl PgMfaStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl IMfaStore for PgMfaStore {
+  async fn find_totp(&self, user_id: Uuid) -> Result<Option<TotpCredential>, Error> {
+    Ok(sqlx::query_as(
+      "SELECT user_id, secret, confirmed_at, pending_secret, last_used_step FROM user_totp WHERE user_id = $1"
+    )
+      .bind(user_id)
+      .fetch_optional(&self.pool)
+      .await?)
+  }
+
+  async fn save_totp(&self, user_id: Uuid, secret: &str) -> Result<(), Error> {
+    sqlx::query(
+      "INSERT INTO user_totp (user_id, pending_secret) VALUES ($1, $2)
+       ON CONFLICT (user_id) DO UPDATE SET pending_secret = EXCLUDED.pending_secret"
+    )
+      .bind(user_id)
+      .bind(secret)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn confirm_totp(&self, user_id: Uuid, secret: &str) -> Result<bool, Error> {
+    let result = sqlx::query(
+      "UPDATE user_totp SET secret = pending_secret, pending_secret = NULL, confirmed_at = NOW()
+       WHERE user_id = $1 AND pending_secret = $2"
+    )
+      .bind(user_id)
+      .bind(secret)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(result.rows_affected() > 0)
+  }
+
+  async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, Error> {
+    let result = sqlx::query(
+      "UPDATE user_totp SET last_used_step = $2
+       WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)"
+    )
+      .bind(user_id)
+      .bind(step)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(result.rows_affected() > 0)
+  }
+
+  async fn replace_recovery_codes(&self, user_id: Uuid, code_hashes: &[String]) -> Result<(), Error> {
+    let mut transaction = self.pool.begin().await?;
+
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+      .bind(user_id)
+      .execute(&mut *transaction)
+      .await?;
+    sqlx::query("INSERT INTO user_recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::TEXT[])")
+      .bind(user_id)
+      .bind(code_hashes)
+      .execute(&mut *transaction)
+      .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+  }
+
+  async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, Error> {
+    let result = sqlx::query(
+      "UPDATE user_recovery_codes SET used_at = NOW()
+       WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL"
+    )
+      .bind(user_id)
+      .bind(code_hash)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(result.rows_affected() > 0)
+  }
+
+  async fn has_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, Error> {
+    Ok(sqlx::query_scalar(
+      "SELECT EXISTS (
+         SELECT 1 FROM user_recovery_codes WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+       )"
+    )
+      .bind(user_id)
+      .bind(code_hash)
+      .fetch_one(&self.pool)
+      .await?)
+  }
+
+  async fn find_mfa_token_attempts(&self, jti: &str) -> Result<Option<MfaTokenAttempts>, Error> {
+    Ok(sqlx::query_as("SELECT jti, attempts, consumed_at FROM mfa_token_attempts WHERE jti = $1")
+      .bind(jti)
+      .fetch_optional(&self.pool)
+      .await?)
+  }
+
+  async fn record_mfa_token_attempt(&self, jti: &str, expires_at: DateTime<Utc>, max_attempts: i32) -> Result<bool, Error> {
+    let recorded: Option<String> = sqlx::query_scalar(
+      "INSERT INTO mfa_token_attempts (jti, attempts, expires_at) SELECT $1, 1, $2 WHERE $3 > 0
+       ON CONFLICT (jti) DO UPDATE SET attempts = mfa_token_attempts.attempts + 1
+       WHERE mfa_token_attempts.consumed_at IS NULL AND mfa_token_attempts.attempts < $3
+       RETURNING jti"
+    )
+      .bind(jti)
+      .bind(expires_at)
+      .bind(max_attempts)
+      .fetch_optional(&self.pool)
+      .await?;
+
+    Ok(recorded.is_some())
+  }
+
+  async fn consume_mfa_token(&self, jti: &str) -> Result<bool, Error> {
+    let result = sqlx::query(
+      "UPDATE mfa_token_attempts SET consumed_at = NOW() WHERE jti = $1 AND consumed_at IS NULL"
+    )
+      .bind(jti)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(result.rows_affected() > 0)
+  }
+}
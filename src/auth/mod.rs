@@ -1,63 +1,352 @@
-use lambda_http::http::HeaderMap;
+pub mod alb_oidc_verifier;
+pub mod api_key_auth;
+pub mod api_key_store;
+pub mod auth_config;
+pub mod authorization;
+pub mod authorizer_context_auth;
+pub mod credential_store;
+pub mod email_sender;
+pub mod http_client;
+pub mod impersonation_service;
+pub mod impersonation_store;
+pub mod lambda_authorizer;
+pub mod login_attempt_store;
+pub mod login_attempt_tracker;
+pub mod login_challenge_store;
+pub mod login_service;
+pub mod magic_link_service;
+pub mod mfa_service;
+pub mod mfa_store;
+pub mod oidc_login_service;
+pub mod principal_loader;
+pub mod refresh_token_store;
+pub mod revocation_store;
+pub mod session_auth;
+pub mod session_store;
+pub mod token_extractor;
+pub mod token_issuer;
+pub mod token_pair_service;
+
+use async_trait::async_trait;
+use lambda_http::{http::HeaderMap, request::RequestContext, Request, RequestExt};
+use uuid::Uuid;
+
+pub use alb_oidc_verifier::AlbOidcVerifier;
+pub use api_key_auth::ApiKeyAuth;
+pub use api_key_store::{ApiKeyRecord, IApiKeyStore, PgApiKeyStore};
+pub use auth_config::AuthConfig;
+pub use authorization::{Grants, IPermissionStore, PgPermissionStore};
+pub use authorizer_context_auth::AuthorizerContextAuth;
+pub use credential_store::{ICredentialStore, PgCredentialStore, UserCredentials};
+pub use email_sender::{EmailMessage, IEmailSender, InMemoryEmailSender};
+pub use http_client::IHttpClient;
+pub use impersonation_service::ImpersonationService;
+pub use impersonation_store::{IImpersonationStore, ImpersonationRecord, PgImpersonationStore};
+pub use lambda_authorizer::LambdaAuthorizer;
+pub use login_attempt_store::{ILoginAttemptStore, InMemoryLoginAttemptStore, LoginAttempts, PgLoginAttemptStore};
+pub use login_attempt_tracker::LoginAttemptTracker;
+pub use login_challenge_store::{ILoginChallengeStore, LoginChallenge, PgLoginChallengeStore};
+pub use login_service::LoginService;
+pub use magic_link_service::MagicLinkService;
+pub use mfa_service::MfaService;
+pub use mfa_store::{IMfaStore, MfaTokenAttempts, PgMfaStore, TotpCredential};
+pub use oidc_login_service::{OidcAuthorization, OidcLoginService, OidcProviderConfig};
+pub use principal_loader::{CachingPrincipalLoader, IPrincipalLoader, PgPrincipalLoader};
+pub use refresh_token_store::{IRefreshTokenStore, InMemoryRefreshTokenStore, PgRefreshTokenStore};
+pub use revocation_store::{IRevocationStore, PgRevocationStore};
+pub use session_auth::SessionAuth;
+pub use session_store::{ISessionStore, PgSessionStore, SessionRecord};
+pub use token_extractor::{
+  AuthorizationHeaderExtractor, CookieExtractor, HeaderExtractor, ITokenExtractor,
+  QueryParameterExtractor, TokenSource,
+};
+pub use token_issuer::TokenIssuer;
+pub use token_pair_service::TokenPairService;
 
 use crate::{
-  error::SerializableError,
-  types::{auth::AuthUser, utils::jwt_util::AuthClaims},
+  error::{ScopeError, SerializableError},
+  types::{auth::{AuthPayload, AuthUser}, utils::jwt_util::{AuthClaims, TokenType}},
   utils::jwt_util::IJwtUtil,
   Error,
 };
 
-pub trait IAuth {
-  fn authenticate(&mut self, headers: &HeaderMap) -> Result<(), Error>;
+#[async_trait]
+pub trait IAuth: Send {
+  async fn authenticate(&mut self, headers: &HeaderMap) -> Result<(), Error>;
+
+  /// Like `authenticate`, for implementations that also read credentials
+  /// outside the headers, e.g. from the query string.
+  async fn authenticate_request(&mut self, request: &Request) -> Result<(), Error> {
+    self.authenticate(request.headers()).await
+  }
 }
 
-pub struct Auth {
-  jwt_util: Box<dyn IJwtUtil>,
-  claims: Option<AuthClaims>,
-  auth_scheme: &'static str,
+pub struct Auth<T: AuthPayload = AuthUser> {
+  jwt_util: Box<dyn IJwtUtil<T>>,
+  revocation_store: Option<Box<dyn IRevocationStore>>,
+  permission_store: Option<Box<dyn IPermissionStore>>,
+  principal_loader: Option<Box<dyn IPrincipalLoader<T>>>,
+  claims: Option<AuthClaims<T>>,
+  principal: Option<T>,
+  grants: Grants,
+  config: AuthConfig,
+  extractors: Vec<Box<dyn ITokenExtractor>>,
+  token_source: Option<String>,
 }
 
 impl Auth {
   pub fn new(jwt_util: Box<dyn IJwtUtil>) -> Self {
-    Self { jwt_util, claims: None, auth_scheme: "Watashiwasta " }
+    Self::for_payload(jwt_util)
   }
+}
 
-  pub fn user(&self) -> Option<AuthUser> {
-    match self.claims.as_ref() {
-      None => None,
-      Some(claims) => Some(claims.user_details.clone()),
+impl<T: AuthPayload> Auth<T> {
+  /// Same as `Auth::new` for a custom payload type, e.g.
+  /// `Auth::<TenantUser>::for_payload(Box::new(jwt_util))`.
+  pub fn for_payload(jwt_util: Box<dyn IJwtUtil<T>>) -> Self {
+    Self {
+      jwt_util,
+      revocation_store: None,
+      permission_store: None,
+      principal_loader: None,
+      claims: None,
+      principal: None,
+      grants: Grants::default(),
+      config: AuthConfig::default(),
+      extractors: Vec::new(),
+      token_source: None,
     }
   }
-}
 
-impl IAuth for Auth {
-  fn authenticate(&mut self, headers: &HeaderMap) -> Result<(), Error> {
-    let auth_header_value = headers
-      .get("Authorization")
-      .ok_or_else(|| Error::Unauthorized(SerializableError {
-        message: "Missing Authorization header".to_string()
-      }))?;
+  /// Replaces the accepted authorization schemes, `Watashiwasta` by default.
+  pub fn with_config(mut self, config: AuthConfig) -> Self {
+    self.config = config;
+
+    self
+  }
+
+  /// Adds a place to look for the token. Extractors are tried in the order
+  /// they were added; once any is added the `Authorization` header is only
+  /// read if an `AuthorizationHeaderExtractor` is part of the chain.
+  pub fn with_extractor(mut self, extractor: Box<dyn ITokenExtractor>) -> Self {
+    self.extractors.push(extractor);
+
+    self
+  }
+
+  pub fn with_revocation_store(mut self, revocation_store: Box<dyn IRevocationStore>) -> Self {
+    self.revocation_store = Some(revocation_store);
+
+    self
+  }
+
+  /// Loads roles and permissions from `permission_store` on top of the
+  /// ones carried in the token's claims.
+  pub fn with_permission_store(mut self, permission_store: Box<dyn IPermissionStore>) -> Self {
+    self.permission_store = Some(permission_store);
+
+    self
+  }
+
+  /// Rejects tokens of users the loader no longer returns, e.g. deleted or
+  /// deactivated ones. `user` then returns the loaded principal instead of
+  /// the one in the token.
+  pub fn with_principal_loader(mut self, principal_loader: Box<dyn IPrincipalLoader<T>>) -> Self {
+    self.principal_loader = Some(principal_loader);
+
+    self
+  }
+
+  pub fn user(&self) -> Option<T> {
+    match (self.principal.as_ref(), self.claims.as_ref()) {
+      (Some(principal), _) => Some(principal.clone()),
+      (None, None) => None,
+      (None, Some(claims)) => Some(claims.user_details.clone()),
+    }
+  }
+
+  pub fn claims(&self) -> Option<&AuthClaims<T>> {
+    self.claims.as_ref()
+  }
+
+  /// The real user behind an impersonation token; `user` is then the
+  /// impersonated user.
+  pub fn actor(&self) -> Option<T> {
+    self.claims.as_ref().and_then(|claims| claims.actor()).map(|actor| actor.user_details)
+  }
+
+  pub fn is_impersonating(&self) -> bool {
+    self.claims.as_ref().is_some_and(|claims| claims.token_type == TokenType::Impersonation)
+  }
+
+  pub fn grants(&self) -> &Grants {
+    &self.grants
+  }
+
+  pub fn require_role(&self, role: &str) -> Result<(), Error> {
+    self.ensure_authenticated()?;
+    self.grants.require_role(role)
+  }
+
+  pub fn require_permission(&self, permission: &str) -> Result<(), Error> {
+    self.ensure_authenticated()?;
+    self.grants.require_permission(permission)
+  }
+
+  pub fn require_any_permission(&self, permissions: &[&str]) -> Result<(), Error> {
+    self.ensure_authenticated()?;
+    self.grants.require_any_permission(permissions)
+  }
+
+  /// Requires every one of `scopes` in the token's `scope`/`scp` claim.
+  pub fn require_scopes(&self, scopes: &[&str]) -> Result<(), Error> {
+    let claims = self.authenticated_claims()?;
+
+    if !scopes.iter().all(|scope| claims.has_scope(scope)) {
+      return Err(insufficient_scope(scopes));
+    }
+
+    Ok(())
+  }
 
-    let auth_header_value_str = auth_header_value.to_str().map_err(|_| {
-      Error::Unauthorized(SerializableError {
-        message: "Missing Authorization header".to_string()
-      })
-    })?;
+  pub fn require_any_scope(&self, scopes: &[&str]) -> Result<(), Error> {
+    let claims = self.authenticated_claims()?;
 
-    if !auth_header_value_str.starts_with(self.auth_scheme) {
-      return Err(Error::Unauthorized(SerializableError {
-        message: "Missing Authorization header".to_string()
-      }));
+    if !scopes.iter().any(|scope| claims.has_scope(scope)) {
+      return Err(insufficient_scope(scopes));
     }
 
-    let token = &auth_header_value_str[self.auth_scheme.len()..];
+    Ok(())
+  }
+
+  fn authenticated_claims(&self) -> Result<&AuthClaims<T>, Error> {
+    self.claims.as_ref().ok_or_else(|| Error::Unauthorized(SerializableError {
+      message: "Not authenticated".to_string()
+    }))
+  }
+
+  fn ensure_authenticated(&self) -> Result<(), Error> {
+    self.authenticated_claims().map(|_| ())
+  }
+
+  /// Name of the extractor that found the token of the authenticated
+  /// request, e.g. `authorization` or `cookie:access_token`.
+  pub fn token_source(&self) -> Option<&str> {
+    self.token_source.as_deref()
+  }
+
+  fn extract_token(&self, source: &TokenSource) -> Result<(String, String), Error> {
+    if self.extractors.is_empty() {
+      let token = self.config.extract_token(source.headers)?;
+      return Ok(("authorization".to_string(), token.to_string()));
+    }
+
+    for extractor in &self.extractors {
+      if let Some(token) = extractor.extract(source)? {
+        return Ok((extractor.name().to_string(), token));
+      }
+    }
+
+    Err(Error::Unauthorized(SerializableError {
+      message: "Missing authentication token".to_string()
+    }))
+  }
+
+  pub(crate) async fn authenticate_source(&mut self, source: TokenSource<'_>) -> Result<(), Error> {
+    // A failed attempt must not leave an earlier identity behind.
+    self.claims = None;
+    self.principal = None;
+    self.grants = Grants::default();
+    self.token_source = None;
+
+    let (token_source, token) = self.extract_token(&source)?;
+
+    let claims = self.jwt_util.extract_claims(&token).await?;
+
+    match claims.token_type {
+      TokenType::AccessToken => {},
+      TokenType::Impersonation if claims.actor().is_some() => {},
+      TokenType::Impersonation => return Err(Error::Unauthorized(SerializableError {
+        message: "Impersonation token has no actor".to_string()
+      })),
+      TokenType::MfaPending => return Err(Error::Unauthorized(SerializableError {
+        message: "Multi-factor authentication required".to_string()
+      })),
+      _ => return Err(Error::Unauthorized(SerializableError {
+        message: "Token is not an access token".to_string()
+      })),
+    }
+
+    if let Some(revocation_store) = self.revocation_store.as_deref() {
+      revocation_store::ensure_not_revoked(revocation_store, &claims).await?;
+    }
+
+    let mut principal = None;
+    if let Some(principal_loader) = self.principal_loader.as_deref() {
+      principal = Some(load_principal(principal_loader, claims.user_details.user_id()).await?);
+
+      if let Some(actor) = claims.actor() {
+        load_principal(principal_loader, actor.user_details.user_id()).await?;
+      }
+    }
+
+    let mut grants = Grants::from_claims(&claims);
+    if let Some(permission_store) = self.permission_store.as_deref() {
+      grants.merge(permission_store.grants_for_user(claims.user_details.user_id()).await?);
+    }
 
-    self.claims = Some(self.jwt_util.extract_claims(token)?);
+    self.claims = Some(claims);
+    self.principal = principal;
+    self.grants = grants;
+    self.token_source = Some(token_source);
 
     Ok(())
   }
 }
 
+#[async_trait]
+impl<T: AuthPayload> IAuth for Auth<T> {
+  async fn authenticate(&mut self, headers: &HeaderMap) -> Result<(), Error> {
+    self.authenticate_source(TokenSource::from_headers(headers)).await
+  }
+
+  async fn authenticate_request(&mut self, request: &Request) -> Result<(), Error> {
+    self.authenticate_source(TokenSource::from_request(request)).await
+  }
+}
+
+/// Address of the client that called API Gateway, from the request
+/// context. Behind an ALB, which has none, the last `X-Forwarded-For`
+/// entry, i.e. the one the load balancer appended; anything left of it was
+/// sent by the client.
+pub fn client_ip(request: &Request) -> Option<String> {
+  let source_ip = match request.request_context_ref() {
+    Some(RequestContext::ApiGatewayV1(context)) => context.identity.source_ip.clone(),
+    Some(RequestContext::ApiGatewayV2(context)) => context.http.source_ip.clone(),
+    Some(RequestContext::WebSocket(context)) => context.identity.source_ip.clone(),
+    Some(RequestContext::Alb(_)) => request.headers().get("X-Forwarded-For")
+      .and_then(|header_value| header_value.to_str().ok())
+      .and_then(|forwarded_for| forwarded_for.rsplit(',').next())
+      .map(|ip| ip.trim().to_string()),
+    _ => None,
+  };
+
+  source_ip.filter(|ip| !ip.is_empty())
+}
+
+pub(crate) async fn load_principal<T: AuthPayload>(principal_loader: &dyn IPrincipalLoader<T>, user_id: Uuid) -> Result<T, Error> {
+  principal_loader.load(user_id).await?.ok_or_else(|| Error::Unauthorized(SerializableError {
+    message: "User no longer exists or has been deactivated".to_string()
+  }))
+}
+
+fn insufficient_scope(scopes: &[&str]) -> Error {
+  Error::InsufficientScope(ScopeError {
+    message: format!("Insufficient scope, requires {}", scopes.join(" ")),
+    scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+  })
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -65,10 +354,13 @@ mod tests {
   use lambda_http::http::HeaderValue;
   use uuid::Uuid;
 
-  use crate::{types::utils::jwt_util::TokenType, utils::jwt_util::MockIJwtUtil};
+  use crate::{
+    auth::{principal_loader::MockIPrincipalLoader, revocation_store::MockIRevocationStore},
+    utils::jwt_util::MockIJwtUtil,
+  };
 
-  #[test]
-  fn test_authenticate_success() {
+  #[tokio::test]
+  async fn test_authenticate_success() {
     // arrange
     let mut jwt_util = MockIJwtUtil::new();
     let mut headers = HeaderMap::new();
@@ -95,24 +387,33 @@ mod tests {
             last_name: "Doe".to_string(),
             email: "johndoe@example.com".to_string(),
           }, token_type: TokenType::AccessToken,
+          jti: None,
+          token_version: None,
+          issuer: None,
+          audience: None,
+          issued_at: None,
+          not_before: None,
+          roles: Vec::new(),
+          permissions: Vec::new(),
+          extra: Default::default(),
         })
       });
 
     // assert
     let mut auth = Auth::new(Box::new(jwt_util));
-    assert!(auth.authenticate(&headers).is_ok());
+    assert!(auth.authenticate(&headers).await.is_ok());
     assert_eq!(auth.user().unwrap().id, user_id);
   }
 
-  #[test]
-  fn test_authenticate_missing_header() {
+  #[tokio::test]
+  async fn test_authenticate_missing_header() {
     // arrange
     let mock_jwt_util = MockIJwtUtil::new();
     let mut auth = Auth::new(Box::new(mock_jwt_util));
     let headers = HeaderMap::new();
 
     // act
-    let authenticate_result = auth.authenticate(&headers);
+    let authenticate_result = auth.authenticate(&headers).await;
 
     // assert
     assert!(authenticate_result.is_err());
@@ -123,8 +424,8 @@ mod tests {
     }
   }
 
-  #[test]
-  fn test_authenticate_invalid_scheme() {
+  #[tokio::test]
+  async fn test_authenticate_invalid_scheme() {
     // arrange
     let jwt_util = MockIJwtUtil::new();
     let mut headers = HeaderMap::new();
@@ -137,15 +438,47 @@ mod tests {
 
     // act
     let mut auth = Auth::new(Box::new(jwt_util));
-    let test_did_fail = auth.authenticate(&headers).is_err();
+    let test_did_fail = auth.authenticate(&headers).await.is_err();
 
     // assert
     assert!(test_did_fail);
     assert_eq!(auth.user(), None);
   }
 
-  #[test]
-  fn test_authenticate_jwt_extraction_error() {
+  #[tokio::test]
+  async fn test_authenticate_bearer_scheme() {
+    // arrange
+    let jwt_util = crate::utils::JwtUtil::new("some_key");
+    let user_id = Uuid::new_v4();
+    let expires_in = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+    let claims = AuthClaims::new(AuthUser {
+      id: user_id,
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    }, TokenType::AccessToken, expires_in);
+    let token = jwt_util.generate_token(&claims).unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", format!("bearer  {}", token).parse().unwrap());
+
+    // act
+    let mut default_auth = Auth::new(Box::new(jwt_util.clone()));
+    let default_result = default_auth.authenticate(&headers).await;
+    let mut bearer_auth = Auth::new(Box::new(jwt_util)).with_config(AuthConfig::bearer());
+    let bearer_result = bearer_auth.authenticate(&headers).await;
+
+    // assert
+    match default_result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Unsupported authorization scheme"),
+      _ => panic!("Unexpected error type"),
+    }
+    assert!(bearer_result.is_ok());
+    assert_eq!(bearer_auth.user().unwrap().id, user_id);
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_jwt_extraction_error() {
     // arrange
     let mut mock_jwt_util = MockIJwtUtil::new();
     let mut headers = HeaderMap::new();
@@ -165,12 +498,448 @@ mod tests {
       });
 
     let mut auth = Auth::new(Box::new(mock_jwt_util));
-    let authenticate_result = auth.authenticate(&headers);
+    let authenticate_result = auth.authenticate(&headers).await;
 
     assert!(authenticate_result.is_err());
     match authenticate_result.unwrap_err() {
-      Error::JwtTokenInvalid(_) => assert!(true),
+      Error::JwtTokenInvalid(_) => {},
+      _ => panic!("Unexpected error type"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_rejects_refresh_token() {
+    // arrange
+    let mut mock_jwt_util = MockIJwtUtil::new();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", "Watashiwasta refresh_token".parse().unwrap());
+    let user_id = Uuid::new_v4();
+
+    mock_jwt_util.expect_extract_claims()
+      .times(1)
+      .returning(move |_| {
+        Ok(AuthClaims {
+          subject: user_id.to_string(),
+          expires_in: Utc::now().timestamp() as usize,
+          user_details: AuthUser {
+            id: user_id,
+            first_name: "John".to_string(),
+            middle_name: None,
+            last_name: "Doe".to_string(),
+            email: "johndoe@example.com".to_string(),
+          }, token_type: TokenType::RefreshToken,
+          jti: None,
+          token_version: None,
+          issuer: None,
+          audience: None,
+          issued_at: None,
+          not_before: None,
+          roles: Vec::new(),
+          permissions: Vec::new(),
+          extra: Default::default(),
+        })
+      });
+
+    // act
+    let mut auth = Auth::new(Box::new(mock_jwt_util));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    match authenticate_result.unwrap_err() {
+      Error::Unauthorized(error) => assert_eq!(error.message, "Token is not an access token"),
       _ => panic!("Unexpected error type"),
     }
+    assert_eq!(auth.user(), None);
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_rejects_mfa_pending_token() {
+    // arrange
+    let mut mock_jwt_util = MockIJwtUtil::new();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", "Watashiwasta mfa_token".parse().unwrap());
+    let user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    };
+
+    mock_jwt_util.expect_extract_claims()
+      .times(1)
+      .returning(move |_| Ok(AuthClaims::new(user.clone(), TokenType::MfaPending, Utc::now().timestamp() as usize + 60)));
+
+    // act
+    let mut auth = Auth::new(Box::new(mock_jwt_util));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    match authenticate_result.unwrap_err() {
+      Error::Unauthorized(error) => assert_eq!(error.message, "Multi-factor authentication required"),
+      _ => panic!("Unexpected error type"),
+    }
+    assert_eq!(auth.user(), None);
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_impersonation_token() {
+    // arrange
+    let mut mock_jwt_util = MockIJwtUtil::new();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", "Watashiwasta impersonation_token".parse().unwrap());
+    let user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    };
+    let actor = AuthUser { id: Uuid::new_v4(), email: "support@example.com".to_string(), ..user.clone() };
+    let claims_user = user.clone();
+    let claims_actor = actor.clone();
+
+    mock_jwt_util.expect_extract_claims()
+      .times(1)
+      .returning(move |_| {
+        let expires_in = Utc::now().timestamp() as usize + 60;
+        Ok(AuthClaims::new(claims_user.clone(), TokenType::Impersonation, expires_in).with_actor(&claims_actor))
+      });
+
+    // act
+    let mut auth = Auth::new(Box::new(mock_jwt_util));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    assert!(authenticate_result.is_ok());
+    assert!(auth.is_impersonating());
+    assert_eq!(auth.user(), Some(user));
+    assert_eq!(auth.actor(), Some(actor));
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_with_principal_loader() {
+    // arrange
+    let mut mock_jwt_util = MockIJwtUtil::new();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", "Watashiwasta valid_token".parse().unwrap());
+    let token_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    };
+    let current_user = AuthUser { email: "john.doe@example.com".to_string(), ..token_user.clone() };
+    let loaded_user = current_user.clone();
+
+    mock_jwt_util.expect_extract_claims()
+      .returning(move |_| Ok(AuthClaims::new(token_user.clone(), TokenType::AccessToken, Utc::now().timestamp() as usize + 60)));
+    let mut principal_loader = MockIPrincipalLoader::new();
+    principal_loader.expect_load()
+      .with(mockall::predicate::eq(current_user.id))
+      .times(1)
+      .returning(move |_| Ok(Some(loaded_user.clone())));
+
+    // act
+    let mut auth = Auth::new(Box::new(mock_jwt_util)).with_principal_loader(Box::new(principal_loader));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    assert!(authenticate_result.is_ok());
+    assert_eq!(auth.user(), Some(current_user));
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_rejects_deactivated_user() {
+    // arrange
+    let mut mock_jwt_util = MockIJwtUtil::new();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", "Watashiwasta valid_token".parse().unwrap());
+    let user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    };
+
+    mock_jwt_util.expect_extract_claims()
+      .returning(move |_| Ok(AuthClaims::new(user.clone(), TokenType::AccessToken, Utc::now().timestamp() as usize + 60)));
+    let mut principal_loader = MockIPrincipalLoader::new();
+    principal_loader.expect_load().returning(|_| Ok(None));
+
+    // act
+    let mut auth = Auth::new(Box::new(mock_jwt_util)).with_principal_loader(Box::new(principal_loader));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    match authenticate_result.unwrap_err() {
+      Error::Unauthorized(error) => assert_eq!(error.message, "User no longer exists or has been deactivated"),
+      _ => panic!("Unexpected error type"),
+    }
+    assert_eq!(auth.user(), None);
+  }
+
+  #[tokio::test]
+  async fn test_failed_authentication_clears_previous_identity() {
+    // arrange
+    let mut mock_jwt_util = MockIJwtUtil::new();
+    let user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    };
+    let mut valid_headers = HeaderMap::new();
+    valid_headers.insert("Authorization", "Watashiwasta valid_token".parse().unwrap());
+    let mut invalid_headers = HeaderMap::new();
+    invalid_headers.insert("Authorization", "Watashiwasta invalid_token".parse().unwrap());
+
+    mock_jwt_util.expect_extract_claims()
+      .returning(move |token| match token {
+        "valid_token" => Ok(AuthClaims::new(user.clone(), TokenType::AccessToken, Utc::now().timestamp() as usize + 60)
+          .with_permissions(&["orders:read"])),
+        _ => Err(Error::JwtTokenInvalid(SerializableError { message: "InvalidToken".to_string() })),
+      });
+
+    // act
+    let mut auth = Auth::new(Box::new(mock_jwt_util));
+    let valid_result = auth.authenticate(&valid_headers).await;
+    let invalid_result = auth.authenticate(&invalid_headers).await;
+
+    // assert
+    assert!(valid_result.is_ok());
+    assert!(invalid_result.is_err());
+    assert_eq!(auth.user(), None);
+    assert!(auth.claims().is_none());
+    assert_eq!(auth.grants(), &Grants::default());
+    assert_eq!(auth.token_source(), None);
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_rejects_revoked_token() {
+    // arrange
+    let mut mock_jwt_util = MockIJwtUtil::new();
+    let mut revocation_store = MockIRevocationStore::new();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", "Watashiwasta revoked_token".parse().unwrap());
+    let user_id = Uuid::new_v4();
+
+    mock_jwt_util.expect_extract_claims()
+      .times(1)
+      .returning(move |_| {
+        Ok(AuthClaims {
+          subject: user_id.to_string(),
+          expires_in: Utc::now().timestamp() as usize,
+          user_details: AuthUser {
+            id: user_id,
+            first_name: "John".to_string(),
+            middle_name: None,
+            last_name: "Doe".to_string(),
+            email: "johndoe@example.com".to_string(),
+          }, token_type: TokenType::AccessToken,
+          jti: Some("revoked-jti".to_string()),
+          token_version: None,
+          issuer: None,
+          audience: None,
+          issued_at: None,
+          not_before: None,
+          roles: Vec::new(),
+          permissions: Vec::new(),
+          extra: Default::default(),
+        })
+      });
+    revocation_store.expect_is_revoked()
+      .with(mockall::predicate::eq("revoked-jti"))
+      .times(1)
+      .returning(|_| Ok(true));
+
+    // act
+    let mut auth = Auth::new(Box::new(mock_jwt_util))
+      .with_revocation_store(Box::new(revocation_store));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    assert!(matches!(authenticate_result, Err(Error::Unauthorized(_))));
+    assert_eq!(auth.user(), None);
+  }
+
+  #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+  struct TenantUser {
+    id: Uuid,
+    tenant_id: String,
+  }
+
+  impl AuthPayload for TenantUser {
+    fn user_id(&self) -> Uuid {
+      self.id
+    }
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_custom_payload() {
+    // arrange
+    let jwt_util = crate::utils::JwtUtil::new("some_key");
+    let user = TenantUser { id: Uuid::new_v4(), tenant_id: "acme".to_string() };
+    let expires_in = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+    let claims = AuthClaims::new(user.clone(), TokenType::AccessToken, expires_in)
+      .with_claim("plan", "enterprise");
+    let token = jwt_util.generate_token(&claims).unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", format!("Watashiwasta {}", token).parse().unwrap());
+
+    // act
+    let mut auth = Auth::<TenantUser>::for_payload(Box::new(jwt_util));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    assert!(authenticate_result.is_ok());
+    assert_eq!(auth.user(), Some(user));
+    assert_eq!(auth.claims().unwrap().claim("plan"), Some(&serde_json::json!("enterprise")));
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_request_tries_extractors_in_order() {
+    // arrange
+    let jwt_util = crate::utils::JwtUtil::new("some_key");
+    let expires_in = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+    let user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    };
+    let token = jwt_util.generate_token(&AuthClaims::new(user.clone(), TokenType::AccessToken, expires_in)).unwrap();
+    let cookie_request = lambda_http::http::Request::builder()
+      .header("Cookie", format!("access_token={}", token))
+      .body(lambda_http::Body::Empty)
+      .unwrap();
+    let query_request = Request::default().with_query_string_parameters(
+      std::collections::HashMap::from([("access_token".to_string(), token.clone())])
+    );
+    let auth = || Auth::new(Box::new(jwt_util.clone()))
+      .with_extractor(Box::new(AuthorizationHeaderExtractor::new(AuthConfig::bearer())))
+      .with_extractor(Box::new(CookieExtractor::new("access_token")))
+      .with_extractor(Box::new(QueryParameterExtractor::new("access_token")));
+
+    // act
+    let mut cookie_auth = auth();
+    let cookie_result = cookie_auth.authenticate_request(&cookie_request).await;
+    let mut query_auth = auth();
+    let query_result = query_auth.authenticate_request(&query_request).await;
+    let mut missing_auth = auth();
+    let missing_result = missing_auth.authenticate_request(&Request::default()).await;
+
+    // assert
+    assert!(cookie_result.is_ok());
+    assert_eq!(cookie_auth.user(), Some(user.clone()));
+    assert_eq!(cookie_auth.token_source(), Some("cookie:access_token"));
+    assert!(query_result.is_ok());
+    assert_eq!(query_auth.token_source(), Some("query:access_token"));
+    match missing_result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Missing authentication token"),
+      _ => panic!("Unexpected error type"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_require_permission() {
+    // arrange
+    let jwt_util = crate::utils::JwtUtil::new("some_key");
+    let mut permission_store = crate::auth::authorization::MockIPermissionStore::new();
+    permission_store.expect_grants_for_user()
+      .times(1)
+      .returning(|_| Ok(Grants::new(&["admin"], &["invoices:*"])));
+    let expires_in = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+    let claims = AuthClaims::new(AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    }, TokenType::AccessToken, expires_in).with_permissions(&["orders:read"]);
+    let token = jwt_util.generate_token(&claims).unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", format!("Watashiwasta {}", token).parse().unwrap());
+    let mut auth = Auth::new(Box::new(jwt_util)).with_permission_store(Box::new(permission_store));
+
+    // act
+    let unauthenticated_result = auth.require_permission("orders:read");
+    auth.authenticate(&headers).await.unwrap();
+
+    // assert
+    assert!(matches!(unauthenticated_result, Err(Error::Unauthorized(_))));
+    assert!(auth.require_permission("orders:read").is_ok());
+    assert!(auth.require_permission("invoices:write").is_ok());
+    assert!(auth.require_role("admin").is_ok());
+    match auth.require_permission("orders:write") {
+      Err(Error::Forbidden(error)) => assert_eq!(error.message, "Missing permission orders:write"),
+      _ => panic!("Unexpected error type"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_require_scopes() {
+    // arrange
+    let jwt_util = crate::utils::JwtUtil::new("some_key");
+    let expires_in = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+    let claims = AuthClaims::new(AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "johndoe@example.com".to_string(),
+    }, TokenType::AccessToken, expires_in).with_scopes(&["orders:read", "orders:write"]);
+    let token = jwt_util.generate_token(&claims).unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", format!("Watashiwasta {}", token).parse().unwrap());
+    let mut auth = Auth::new(Box::new(jwt_util));
+
+    // act
+    auth.authenticate(&headers).await.unwrap();
+
+    // assert
+    assert!(auth.require_scopes(&["orders:read", "orders:write"]).is_ok());
+    assert!(auth.require_any_scope(&["invoices:read", "orders:read"]).is_ok());
+    assert!(matches!(auth.require_any_scope(&["invoices:read"]), Err(Error::InsufficientScope(_))));
+    match auth.require_scopes(&["orders:read", "invoices:read"]) {
+      Err(Error::InsufficientScope(error)) => {
+        assert_eq!(error.message, "Insufficient scope, requires orders:read invoices:read");
+        assert_eq!(error.scopes, vec!["orders:read", "invoices:read"]);
+      },
+      _ => panic!("Unexpected error type"),
+    }
+  }
+
+  #[test]
+  fn test_client_ip() {
+    // arrange
+    let mut rest_request = Request::default().with_request_context(RequestContext::ApiGatewayV1(
+      lambda_http::aws_lambda_events::apigw::ApiGatewayProxyRequestContext {
+        identity: lambda_http::aws_lambda_events::apigw::ApiGatewayRequestIdentity {
+          source_ip: Some("203.0.113.7".to_string()),
+          ..Default::default()
+        },
+        ..Default::default()
+      }
+    ));
+    rest_request.headers_mut().insert("X-Forwarded-For", "198.51.100.1".parse().unwrap());
+    let mut alb_request = Request::default().with_request_context(RequestContext::Alb(Default::default()));
+    alb_request.headers_mut().insert("X-Forwarded-For", "198.51.100.1, 203.0.113.7".parse().unwrap());
+    let mut unknown_request = Request::default();
+    unknown_request.headers_mut().insert("X-Forwarded-For", "198.51.100.1".parse().unwrap());
+
+    // act
+    let rest_ip = client_ip(&rest_request);
+    let alb_ip = client_ip(&alb_request);
+    let unknown_ip = client_ip(&unknown_request);
+
+    // assert
+    assert_eq!(rest_ip.as_deref(), Some("203.0.113.7"));
+    assert_eq!(alb_ip.as_deref(), Some("203.0.113.7"));
+    assert_eq!(unknown_ip, None);
   }
 }
@@ -0,0 +1,678 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use lambda_http::{
+  http::{header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE}, Method, Request as HttpRequest},
+  Request, RequestExt,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use uuid::{Builder, Uuid};
+
+use crate::{
+  auth::{http_client::IHttpClient, token_pair_service::TokenPairService},
+  error::SerializableError,
+  types::auth::{AuthUser, LoginResult},
+  utils::{jwks_verifier::JwksVerifier, jwt_validation_policy::JwtValidationPolicy},
+  Error,
+};
+
+type PrincipalMapper = Box<dyn Fn(&Map<String, Value>) -> Result<AuthUser, Error> + Send + Sync>;
+
+/// The endpoints and client registration of an OpenID Connect provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcProviderConfig {
+  issuer: String,
+  authorization_endpoint: String,
+  token_endpoint: String,
+  userinfo_endpoint: Option<String>,
+  client_id: String,
+  client_secret: Option<String>,
+  redirect_uri: String,
+  scopes: Vec<String>,
+}
+
+impl OidcProviderConfig {
+  pub fn new(
+    issuer: &str,
+    authorization_endpoint: &str,
+    token_endpoint: &str,
+    client_id: &str,
+    redirect_uri: &str,
+  ) -> Self {
+    Self {
+      issuer: issuer.to_string(),
+      authorization_endpoint: authorization_endpoint.to_string(),
+      token_endpoint: token_endpoint.to_string(),
+      userinfo_endpoint: None,
+      client_id: client_id.to_string(),
+      client_secret: None,
+      redirect_uri: redirect_uri.to_string(),
+      scopes: vec!["openid".to_string(), "email".to_string(), "profile".to_string()],
+    }
+  }
+
+  /// Confidential clients authenticate to the token endpoint with
+  /// `client_secret_post`. Public clients rely on PKCE alone.
+  pub fn with_client_secret(mut self, client_secret: &str) -> Self {
+    self.client_secret = Some(client_secret.to_string());
+
+    self
+  }
+
+  /// Claims from the userinfo endpoint fill in what the ID token leaves out.
+  pub fn with_userinfo_endpoint(mut self, userinfo_endpoint: &str) -> Self {
+    self.userinfo_endpoint = Some(userinfo_endpoint.to_string());
+
+    self
+  }
+
+  /// `openid email profile` by default.
+  pub fn with_scopes(mut self, scopes: &[&str]) -> Self {
+    self.scopes = scopes.iter().map(|scope| scope.to_string()).collect();
+
+    self
+  }
+
+  pub fn issuer(&self) -> &str {
+    &self.issuer
+  }
+
+  pub fn client_id(&self) -> &str {
+    &self.client_id
+  }
+}
+
+/// A started login. Redirect the user to `url` and keep the rest, e.g. in a
+/// short-lived encrypted cookie, until the provider calls back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OidcAuthorization {
+  pub url: String,
+  pub state: String,
+  pub nonce: String,
+  pub code_verifier: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+  id_token: Option<String>,
+  access_token: Option<String>,
+}
+
+/// Delegates login to an OpenID Connect provider using the authorization
+/// code flow with PKCE, then issues our own token pair for the user.
+pub struct OidcLoginService {
+  config: OidcProviderConfig,
+  http_client: Box<dyn IHttpClient>,
+  id_token_verifier: JwksVerifier,
+  token_pair_service: TokenPairService,
+  principal_mapper: PrincipalMapper,
+}
+
+impl OidcLoginService {
+  /// `id_token_verifier` holds the provider's JWKS. Its validation policy
+  /// is replaced with one pinning the provider's issuer and our client id.
+  pub fn new(
+    config: OidcProviderConfig,
+    http_client: Box<dyn IHttpClient>,
+    id_token_verifier: JwksVerifier,
+    token_pair_service: TokenPairService,
+  ) -> Self {
+    let id_token_verifier = id_token_verifier.with_validation_policy(
+      JwtValidationPolicy::new().allow_issuer(&config.issuer).allow_audience(&config.client_id)
+    );
+
+    Self {
+      config,
+      http_client,
+      id_token_verifier,
+      token_pair_service,
+      principal_mapper: Box::new(auth_user_from_claims),
+    }
+  }
+
+  /// Replaces the default mapping from the verified ID token and userinfo
+  /// claims, e.g. to link the provider account to an existing user.
+  pub fn with_principal_mapper(
+    mut self,
+    principal_mapper: impl Fn(&Map<String, Value>) -> Result<AuthUser, Error> + Send + Sync + 'static,
+  ) -> Self {
+    self.principal_mapper = Box::new(principal_mapper);
+
+    self
+  }
+
+  pub fn config(&self) -> &OidcProviderConfig {
+    &self.config
+  }
+
+  pub fn authorization_request(&self) -> OidcAuthorization {
+    let state = random_token();
+    let nonce = random_token();
+    let code_verifier = random_token();
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    let query = form_encode(&[
+      ("response_type", "code"),
+      ("client_id", &self.config.client_id),
+      ("redirect_uri", &self.config.redirect_uri),
+      ("scope", &self.config.scopes.join(" ")),
+      ("state", &state),
+      ("nonce", &nonce),
+      ("code_challenge", &code_challenge),
+      ("code_challenge_method", "S256"),
+    ]);
+    let separator = if self.config.authorization_endpoint.contains('?') { '&' } else { '?' };
+
+    OidcAuthorization {
+      url: format!("{}{}{}", self.config.authorization_endpoint, separator, query),
+      state,
+      nonce,
+      code_verifier,
+    }
+  }
+
+  /// Completes the login from the provider's redirect to `redirect_uri`.
+  pub async fn handle_callback(
+    &self,
+    request: &Request,
+    authorization: &OidcAuthorization,
+  ) -> Result<LoginResult, Error> {
+    let query = request.query_string_parameters_ref();
+    let parameter = |name: &str| query.and_then(|query| query.first(name));
+
+    if let Some(error) = parameter("error") {
+      return Err(unauthorized(&format!(
+        "Identity provider returned {}: {}",
+        error,
+        parameter("error_description").unwrap_or("no description"),
+      )));
+    }
+
+    let code = parameter("code").ok_or_else(|| unauthorized("Missing authorization code"))?;
+    let state = parameter("state").ok_or_else(|| unauthorized("Missing OAuth state"))?;
+
+    self.login(authorization, code, state).await
+  }
+
+  pub async fn login(&self, authorization: &OidcAuthorization, code: &str, state: &str) -> Result<LoginResult, Error> {
+    let claims = self.exchange(authorization, code, state).await?;
+    let user = (self.principal_mapper)(&claims)?;
+    let token_pair = self.token_pair_service.issue(&user).await?;
+
+    Ok(LoginResult { user, token_pair })
+  }
+
+  /// Redeems the authorization code and returns the verified ID token
+  /// claims, merged with the userinfo claims if configured.
+  pub async fn exchange(
+    &self,
+    authorization: &OidcAuthorization,
+    code: &str,
+    state: &str,
+  ) -> Result<Map<String, Value>, Error> {
+    if !constant_time_eq(state.as_bytes(), authorization.state.as_bytes()) {
+      return Err(unauthorized("OAuth state does not match"));
+    }
+
+    let token_response = self.redeem_code(authorization, code).await?;
+    let id_token = token_response.id_token
+      .ok_or_else(|| identity_provider_error("Token response has no ID token"))?;
+    let mut claims = self.verify_id_token(&id_token, &authorization.nonce).await?;
+
+    if let Some(userinfo_endpoint) = self.config.userinfo_endpoint.as_deref()
+      && let Some(access_token) = token_response.access_token.as_deref() {
+      let userinfo = self.fetch_userinfo(userinfo_endpoint, access_token).await?;
+
+      if userinfo.get("sub") != claims.get("sub") {
+        return Err(unauthorized("Userinfo subject does not match the ID token"));
+      }
+
+      for (name, value) in userinfo {
+        claims.entry(name).or_insert(value);
+      }
+    }
+
+    Ok(claims)
+  }
+
+  async fn redeem_code(&self, authorization: &OidcAuthorization, code: &str) -> Result<TokenResponse, Error> {
+    let mut form = vec![
+      ("grant_type", "authorization_code"),
+      ("code", code),
+      ("redirect_uri", &self.config.redirect_uri),
+      ("client_id", &self.config.client_id),
+      ("code_verifier", &authorization.code_verifier),
+    ];
+
+    if let Some(client_secret) = self.config.client_secret.as_deref() {
+      form.push(("client_secret", client_secret));
+    }
+
+    let request = HttpRequest::builder()
+      .method(Method::POST)
+      .uri(&self.config.token_endpoint)
+      .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+      .header(ACCEPT, "application/json")
+      .body(form_encode(&form))
+      .map_err(|error| identity_provider_error(&error.to_string()))?;
+    let response = self.http_client.send(request).await?;
+    let body: Value = serde_json::from_str(response.body()).unwrap_or_default();
+
+    if !response.status().is_success() {
+      let error = body.get("error").and_then(Value::as_str).unwrap_or("unknown_error");
+
+      // invalid_grant means the code was forged, replayed or has expired.
+      return Err(match error {
+        "invalid_grant" => unauthorized("Authorization code was rejected"),
+        _ => identity_provider_error(&format!("Token endpoint returned {}: {}", response.status(), error)),
+      });
+    }
+
+    serde_json::from_value(body).map_err(|error| identity_provider_error(&format!("Invalid token response: {}", error)))
+  }
+
+  async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<Map<String, Value>, Error> {
+    let claims = self.id_token_verifier.extract_raw_claims(id_token).await?;
+
+    let token_nonce = claims.get("nonce").and_then(Value::as_str).unwrap_or_default();
+    if !constant_time_eq(token_nonce.as_bytes(), nonce.as_bytes()) {
+      return Err(unauthorized("ID token nonce does not match"));
+    }
+
+    // A token issued to several audiences must name us as the party it was
+    // issued for.
+    if let Some(authorized_party) = claims.get("azp")
+      && authorized_party.as_str() != Some(self.config.client_id.as_str()) {
+      return Err(unauthorized("ID token was issued to another client"));
+    }
+
+    if !claims.get("sub").is_some_and(Value::is_string) {
+      return Err(unauthorized("ID token has no subject"));
+    }
+
+    Ok(claims)
+  }
+
+  async fn fetch_userinfo(&self, userinfo_endpoint: &str, access_token: &str) -> Result<Map<String, Value>, Error> {
+    let request = HttpRequest::builder()
+      .method(Method::GET)
+      .uri(userinfo_endpoint)
+      .header(AUTHORIZATION, format!("Bearer {}", access_token))
+      .header(ACCEPT, "application/json")
+      .body(String::new())
+      .map_err(|error| identity_provider_error(&error.to_string()))?;
+    let response = self.http_client.send(request).await?;
+
+    if !response.status().is_success() {
+      return Err(identity_provider_error(&format!("Userinfo endpoint returned {}", response.status())));
+    }
+
+    serde_json::from_str(response.body())
+      .map_err(|error| identity_provider_error(&format!("Invalid userinfo response: {}", error)))
+  }
+}
+
+/// Maps the standard OIDC claims. The id is a stable name-based UUID derived
+/// from the issuer and subject, even when the subject is a UUID itself, so
+/// one provider can't claim another account's id. Only verified emails are
+/// accepted.
+fn auth_user_from_claims(claims: &Map<String, Value>) -> Result<AuthUser, Error> {
+  let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+
+  let subject = claim("sub").ok_or_else(|| unauthorized("ID token has no subject"))?;
+  let id = subject_id(&claim("iss").unwrap_or_default(), &subject);
+
+  let email = claim("email").ok_or_else(|| unauthorized("Identity provider did not return an email"))?;
+  // Some providers, e.g. Cognito's userinfo endpoint, send the flag as a string.
+  let email_verified = match claims.get("email_verified") {
+    Some(Value::Bool(verified)) => *verified,
+    Some(Value::String(verified)) => verified == "true",
+    _ => false,
+  };
+  if !email_verified {
+    return Err(unauthorized("Email address is not verified"));
+  }
+
+  let name = claim("name").unwrap_or_default();
+  let (name_first, name_rest) = name.trim().split_once(' ').unwrap_or((name.trim(), ""));
+
+  Ok(AuthUser {
+    id,
+    first_name: claim("given_name").unwrap_or_else(|| name_first.to_string()),
+    middle_name: claim("middle_name"),
+    last_name: claim("family_name").unwrap_or_else(|| name_rest.trim().to_string()),
+    email: email.to_lowercase(),
+  })
+}
+
+/// A version 5 UUID in the URL namespace for `{issuer}#{subject}`.
+fn subject_id(issuer: &str, subject: &str) -> Uuid {
+  let mut hasher = Sha1::new();
+  hasher.update(Uuid::NAMESPACE_URL.as_bytes());
+  hasher.update(format!("{}#{}", issuer, subject).as_bytes());
+
+  let mut bytes = [0u8; 16];
+  bytes.copy_from_slice(&hasher.finalize()[..16]);
+
+  Builder::from_sha1_bytes(bytes).into_uuid()
+}
+
+fn random_token() -> String {
+  let mut bytes = [0u8; 32];
+  OsRng.fill_bytes(&mut bytes);
+
+  URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn form_encode(pairs: &[(&str, &str)]) -> String {
+  pairs.iter()
+    .map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value)))
+    .collect::<Vec<_>>()
+    .join("&")
+}
+
+fn percent_encode(value: &str) -> String {
+  value.bytes().map(|byte| match byte {
+    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+    _ => format!("%{:02X}", byte),
+  }).collect()
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+  left.len() == right.len() && left.iter()
+    .zip(right)
+    .fold(0u8, |difference, (left, right)| difference | (left ^ right)) == 0
+}
+
+fn identity_provider_error(message: &str) -> Error {
+  Error::IdentityProvider(SerializableError { message: message.to_string() })
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  use chrono::{Duration, Utc};
+  use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+  use lambda_http::http::Response;
+  use serde_json::json;
+
+  use crate::{
+    auth::{http_client::MockIHttpClient, refresh_token_store::InMemoryRefreshTokenStore},
+    types::utils::jwt_util::AuthClaims,
+    utils::{jwt_util::IJwtUtil, JwtUtil},
+  };
+
+  const ISSUER: &str = "https://id.example.com";
+  const CLIENT_ID: &str = "ferrum-app";
+
+  fn config() -> OidcProviderConfig {
+    OidcProviderConfig::new(
+      ISSUER,
+      "https://id.example.com/authorize",
+      "https://id.example.com/token",
+      CLIENT_ID,
+      "https://app.example.com/login/callback",
+    )
+  }
+
+  fn id_token(claims: Value) -> String {
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some("ed-1".to_string());
+    let key = EncodingKey::from_ed_pem(include_bytes!("../../tests/fixtures/keys/ed_private.pem")).unwrap();
+
+    encode(&header, &claims, &key).unwrap()
+  }
+
+  fn id_token_claims(nonce: &str) -> Value {
+    json!({
+      "iss": ISSUER,
+      "aud": CLIENT_ID,
+      "sub": "248289761001",
+      "exp": (Utc::now() + Duration::minutes(5)).timestamp(),
+      "iat": Utc::now().timestamp(),
+      "nonce": nonce,
+      "email": "Jane.Doe@example.com",
+      "email_verified": true,
+      "given_name": "Jane",
+      "family_name": "Doe",
+    })
+  }
+
+  /// A provider whose token endpoint answers with `token_response`.
+  fn provider(token_response: Value) -> MockIHttpClient {
+    let mut http_client = MockIHttpClient::new();
+    http_client.expect_send()
+      .withf(|request| request.uri() == "https://id.example.com/token")
+      .returning(move |_| Ok(Response::new(token_response.to_string())));
+
+    http_client
+  }
+
+  fn service(http_client: MockIHttpClient, config: OidcProviderConfig) -> OidcLoginService {
+    let signer = JwtUtil::from_ed_pem(
+      include_bytes!("../../tests/fixtures/keys/ed_private.pem"),
+      include_bytes!("../../tests/fixtures/keys/ed_public.pem"),
+    ).unwrap().with_key_id("ed-1");
+
+    OidcLoginService::new(
+      config,
+      Box::new(http_client),
+      JwksVerifier::from_document(&serde_json::to_string(&signer.jwks()).unwrap()),
+      TokenPairService::new(Box::new(JwtUtil::new("some_key")), Box::new(InMemoryRefreshTokenStore::new())),
+    )
+  }
+
+  fn query(url: &str) -> HashMap<String, String> {
+    url.split_once('?').unwrap().1.split('&')
+      .map(|pair| pair.split_once('=').unwrap())
+      .map(|(name, value)| (name.to_string(), value.replace("%20", " ").replace("%3A", ":").replace("%2F", "/")))
+      .collect()
+  }
+
+  fn error_message(result: Result<LoginResult, Error>) -> String {
+    match result {
+      Err(Error::Unauthorized(error)) => error.message,
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_authorization_request() {
+    // arrange
+    let service = service(MockIHttpClient::new(), config());
+
+    // act
+    let authorization = service.authorization_request();
+
+    // assert
+    let query = query(&authorization.url);
+    assert!(authorization.url.starts_with("https://id.example.com/authorize?"));
+    assert_eq!(query["response_type"], "code");
+    assert_eq!(query["client_id"], CLIENT_ID);
+    assert_eq!(query["redirect_uri"], "https://app.example.com/login/callback");
+    assert_eq!(query["scope"], "openid email profile");
+    assert_eq!(query["state"], authorization.state);
+    assert_eq!(query["nonce"], authorization.nonce);
+    assert_eq!(query["code_challenge_method"], "S256");
+    assert_eq!(query["code_challenge"], URL_SAFE_NO_PAD.encode(Sha256::digest(authorization.code_verifier.as_bytes())));
+    assert_ne!(authorization.state, service.authorization_request().state);
+  }
+
+  #[tokio::test]
+  async fn test_login() {
+    // arrange
+    let authorization = service(MockIHttpClient::new(), config()).authorization_request();
+    let code_verifier = format!("code_verifier={}", authorization.code_verifier);
+    let id_token = id_token(id_token_claims(&authorization.nonce));
+    let mut http_client = MockIHttpClient::new();
+    http_client.expect_send()
+      .withf(move |request| {
+        request.method() == Method::POST
+          && request.body().contains("grant_type=authorization_code&code=some_code")
+          && request.body().contains(&code_verifier)
+      })
+      .times(1)
+      .returning(move |_| Ok(Response::new(json!({ "id_token": id_token, "access_token": "at" }).to_string())));
+    let service = service(http_client, config());
+
+    // act
+    let result = service.login(&authorization, "some_code", &authorization.state).await.unwrap();
+
+    // assert
+    let access_claims: AuthClaims = JwtUtil::new("some_key").extract_claims(&result.token_pair.access_token).await.unwrap();
+    assert_eq!(result.user.id, subject_id(ISSUER, "248289761001"));
+    assert_eq!(result.user.first_name, "Jane");
+    assert_eq!(result.user.last_name, "Doe");
+    assert_eq!(result.user.email, "jane.doe@example.com");
+    assert_eq!(access_claims.user_details, result.user);
+  }
+
+  #[tokio::test]
+  async fn test_login_rejects_wrong_state() {
+    // arrange
+    let service = service(MockIHttpClient::new(), config());
+    let authorization = service.authorization_request();
+
+    // act
+    let result = service.login(&authorization, "some_code", "forged_state").await;
+
+    // assert
+    assert_eq!(error_message(result), "OAuth state does not match");
+  }
+
+  #[tokio::test]
+  async fn test_login_rejects_wrong_nonce() {
+    // arrange
+    let service = service(provider(json!({ "id_token": id_token(id_token_claims("other_nonce")) })), config());
+    let authorization = service.authorization_request();
+
+    // act
+    let result = service.login(&authorization, "some_code", &authorization.state).await;
+
+    // assert
+    assert_eq!(error_message(result), "ID token nonce does not match");
+  }
+
+  #[tokio::test]
+  async fn test_login_rejects_id_token_for_other_client() {
+    // arrange
+    let authorization = service(MockIHttpClient::new(), config()).authorization_request();
+    let mut claims = id_token_claims(&authorization.nonce);
+    claims["aud"] = json!("other-app");
+    let service = service(provider(json!({ "id_token": id_token(claims) })), config());
+
+    // act
+    let result = service.login(&authorization, "some_code", &authorization.state).await;
+
+    // assert
+    assert!(matches!(result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_login_rejected_code() {
+    // arrange
+    let mut http_client = MockIHttpClient::new();
+    http_client.expect_send().returning(|_| {
+      Ok(Response::builder().status(400).body(json!({ "error": "invalid_grant" }).to_string()).unwrap())
+    });
+    let service = service(http_client, config());
+    let authorization = service.authorization_request();
+
+    // act
+    let result = service.login(&authorization, "some_code", &authorization.state).await;
+
+    // assert
+    assert_eq!(error_message(result), "Authorization code was rejected");
+  }
+
+  #[tokio::test]
+  async fn test_login_merges_userinfo() {
+    // arrange
+    let authorization = service(MockIHttpClient::new(), config()).authorization_request();
+    let mut claims = id_token_claims(&authorization.nonce);
+    claims.as_object_mut().unwrap().retain(|name, _| !["email", "given_name", "family_name"].contains(&name.as_str()));
+    let mut http_client = provider(json!({ "id_token": id_token(claims), "access_token": "some_access_token" }));
+    http_client.expect_send()
+      .withf(|request| {
+        request.uri() == "https://id.example.com/userinfo"
+          && request.headers()[AUTHORIZATION] == "Bearer some_access_token"
+      })
+      .returning(|_| Ok(Response::new(json!({
+        "sub": "248289761001",
+        "email": "jane.doe@example.com",
+        "name": "Jane Q. Doe",
+      }).to_string())));
+    let service = service(http_client, config().with_userinfo_endpoint("https://id.example.com/userinfo"));
+
+    // act
+    let result = service.login(&authorization, "some_code", &authorization.state).await.unwrap();
+
+    // assert
+    assert_eq!(result.user.email, "jane.doe@example.com");
+    assert_eq!(result.user.first_name, "Jane");
+    assert_eq!(result.user.last_name, "Q. Doe");
+  }
+
+  #[test]
+  fn test_auth_user_from_claims_ignores_uuid_subjects_and_requires_verified_email() {
+    // arrange
+    let subject = Uuid::new_v4().to_string();
+    let mut claims = id_token_claims("some_nonce");
+    claims["sub"] = json!(subject);
+    let mut unverified_claims = claims.clone();
+    unverified_claims["email_verified"] = json!(false);
+    let mut unflagged_claims = claims.clone();
+    unflagged_claims.as_object_mut().unwrap().remove("email_verified");
+
+    // act
+    let user = auth_user_from_claims(claims.as_object().unwrap()).unwrap();
+    let unverified_result = auth_user_from_claims(unverified_claims.as_object().unwrap());
+    let unflagged_result = auth_user_from_claims(unflagged_claims.as_object().unwrap());
+
+    // assert
+    assert_eq!(user.id, subject_id(ISSUER, &subject));
+    assert_ne!(user.id.to_string(), subject);
+    for result in [unverified_result, unflagged_result] {
+      match result {
+        Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Email address is not verified"),
+        _ => panic!("Expected Unauthorized error"),
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn test_handle_callback_with_provider_error() {
+    // arrange
+    let service = service(MockIHttpClient::new(), config());
+    let authorization = service.authorization_request();
+    let request = Request::default().with_query_string_parameters(HashMap::from([
+      ("error".to_string(), "access_denied".to_string()),
+      ("state".to_string(), authorization.state.clone()),
+    ]));
+
+    // act
+    let result = service.handle_callback(&request, &authorization).await;
+
+    // assert
+    assert_eq!(error_message(result), "Identity provider returned access_denied: no description");
+  }
+
+  #[test]
+  fn test_subject_ids_are_stable() {
+    // act
+    let id = subject_id(ISSUER, "248289761001");
+
+    // assert
+    assert_eq!(id, subject_id(ISSUER, "248289761001"));
+    assert_ne!(id, subject_id("https://other.example.com", "248289761001"));
+    assert_eq!(id.get_version_num(), 5);
+  }
+}
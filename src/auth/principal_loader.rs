@@ -0,0 +1,117 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use async_trait::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  types::auth::{AuthPayload, AuthUser},
+  Error,
+};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IPrincipalLoader<T: AuthPayload = AuthUser>: Send + Sync {
+  /// The current principal for `user_id`, or `None` if the user no longer
+  /// exists or has been deactivated.
+  async fn load(&self, user_id: Uuid) -> Result<Option<T>, Error>;
+}
+
+/// Remembers what `loader` returned so each user is loaded at most once.
+/// Nothing is evicted, so create one per invocation; clones share the cache.
+pub struct CachingPrincipalLoader<T: AuthPayload = AuthUser> {
+  loader: Arc<dyn IPrincipalLoader<T>>,
+  cache: Arc<Mutex<HashMap<Uuid, Option<T>>>>,
+}
+
+impl<T: AuthPayload> CachingPrincipalLoader<T> {
+  pub fn new(loader: Box<dyn IPrincipalLoader<T>>) -> Self {
+    Self { loader: Arc::from(loader), cache: Arc::default() }
+  }
+}
+
+impl<T: AuthPayload> Clone for CachingPrincipalLoader<T> {
+  fn clone(&self) -> Self {
+    Self { loader: self.loader.clone(), cache: self.cache.clone() }
+  }
+}
+
+#[async_trait]
+impl<T: AuthPayload> IPrincipalLoader<T> for CachingPrincipalLoader<T> {
+  async fn load(&self, user_id: Uuid) -> Result<Option<T>, Error> {
+    let cached = self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&user_id).cloned();
+    if let Some(principal) = cached {
+      return Ok(principal);
+    }
+
+    let principal = self.loader.load(user_id).await?;
+    self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(user_id, principal.clone());
+
+    Ok(principal)
+  }
+}
+
+/// Loads `AuthUser`s from the application's `users` table, skipping
+/// deactivated users. See `PgCredentialStore` for the columns it needs.
+#[derive(Clone)]
+pub struct PgPrincipalLoader {
+  pool: PgPool,
+}
+
+impl PgPrincipalLoader {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl IPrincipalLoader for PgPrincipalLoader {
+  async fn load(&self, user_id: Uuid) -> Result<Option<AuthUser>, Error> {
+    Ok(sqlx::query_as(
+      "SELECT id, first_name, middle_name, last_name, email
+       FROM users WHERE id = $1 AND deactivated_at IS NULL"
+    )
+      .bind(user_id)
+      .fetch_optional(&self.pool)
+      .await?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_caching_loader_loads_each_user_once() {
+    // arrange
+    let user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+    let deactivated_id = Uuid::new_v4();
+    let loaded_user = user.clone();
+    let mut loader = MockIPrincipalLoader::new();
+    loader.expect_load()
+      .times(2)
+      .returning(move |user_id| Ok((user_id == loaded_user.id).then(|| loaded_user.clone())));
+    let caching_loader = CachingPrincipalLoader::new(Box::new(loader));
+    let shared_loader = caching_loader.clone();
+
+    // act
+    let first = caching_loader.load(user.id).await.unwrap();
+    let second = shared_loader.load(user.id).await.unwrap();
+    let deactivated = caching_loader.load(deactivated_id).await.unwrap();
+    let deactivated_again = shared_loader.load(deactivated_id).await.unwrap();
+
+    // assert
+    assert_eq!(first, Some(user.clone()));
+    assert_eq!(second, Some(user));
+    assert_eq!(deactivated, None);
+    assert_eq!(deactivated_again, None);
+  }
+}
@@ -0,0 +1,243 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::SerializableError, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RefreshTokenState {
+  Active { family_id: Uuid },
+  Used { family_id: Uuid },
+  Revoked,
+  Unknown,
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IRefreshTokenStore: Send + Sync {
+  async fn save(&self, token_id: &str, family_id: Uuid, expires_in: usize) -> Result<(), Error>;
+
+  /// Marks the token as used and returns the state it had before, so a
+  /// second exchange of the same token is reported as `Used`.
+  async fn consume(&self, token_id: &str) -> Result<RefreshTokenState, Error>;
+
+  async fn revoke_family(&self, family_id: Uuid) -> Result<(), Error>;
+
+  /// Deletes tokens that have expired anyway. Returns the number of tokens
+  /// removed.
+  async fn delete_expired(&self) -> Result<u64, Error>;
+}
+
+#[derive(Clone, Copy)]
+struct StoredRefreshToken {
+  family_id: Uuid,
+  expires_in: usize,
+  used: bool,
+  revoked: bool,
+}
+
+#[derive(Default)]
+pub struct InMemoryRefreshTokenStore {
+  tokens: Mutex<HashMap<String, StoredRefreshToken>>,
+}
+
+impl InMemoryRefreshTokenStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+#[async_trait]
+impl IRefreshTokenStore for InMemoryRefreshTokenStore {
+  async fn save(&self, token_id: &str, family_id: Uuid, expires_in: usize) -> Result<(), Error> {
+    let mut tokens = self.tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    prune_expired(&mut tokens);
+    tokens.insert(token_id.to_string(), StoredRefreshToken {
+      family_id, expires_in, used: false, revoked: false,
+    });
+
+    Ok(())
+  }
+
+  async fn consume(&self, token_id: &str) -> Result<RefreshTokenState, Error> {
+    let mut tokens = self.tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    Ok(match tokens.get_mut(token_id) {
+      None => RefreshTokenState::Unknown,
+      Some(token) if token.revoked => RefreshTokenState::Revoked,
+      Some(token) if token.used => RefreshTokenState::Used { family_id: token.family_id },
+      Some(token) => {
+        token.used = true;
+        RefreshTokenState::Active { family_id: token.family_id }
+      },
+    })
+  }
+
+  async fn revoke_family(&self, family_id: Uuid) -> Result<(), Error> {
+    let mut tokens = self.tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    tokens.values_mut()
+      .filter(|token| token.family_id == family_id)
+      .for_each(|token| token.revoked = true);
+
+    Ok(())
+  }
+
+  async fn delete_expired(&self) -> Result<u64, Error> {
+    let mut tokens = self.tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    Ok(prune_expired(&mut tokens) as u64)
+  }
+}
+
+fn prune_expired(tokens: &mut HashMap<String, StoredRefreshToken>) -> usize {
+  let now = Utc::now().timestamp().max(0) as usize;
+  let count = tokens.len();
+  tokens.retain(|_, token| token.expires_in > now);
+
+  count - tokens.len()
+}
+
+/// `IRefreshTokenStore` backed by the `refresh_tokens` table from
+/// `database::MIGRATOR`. Run `delete_expired` periodically to keep it small.
+#[derive(Clone)]
+pub struct PgRefreshTokenStore {
+  pool: PgPool,
+}
+
+impl PgRefreshTokenStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl IRefreshTokenStore for PgRefreshTokenStore {
+  async fn save(&self, token_id: &str, family_id: Uuid, expires_in: usize) -> Result<(), Error> {
+    let expires_at = DateTime::<Utc>::from_timestamp(expires_in as i64, 0)
+      .ok_or_else(|| Error::Unhandled(SerializableError {
+        message: format!("Invalid expiry timestamp {}", expires_in)
+      }))?;
+
+    sqlx::query("INSERT INTO refresh_tokens (jti, family_id, expires_at) VALUES ($1, $2, $3)")
+      .bind(token_id)
+      .bind(family_id)
+      .bind(expires_at)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn consume(&self, token_id: &str) -> Result<RefreshTokenState, Error> {
+    // The row lock makes a concurrent exchange of the same token wait and
+    // then see it as used.
+    let token: Option<(Uuid, bool, bool)> = sqlx::query_as(
+      "WITH token AS (
+         SELECT jti, family_id, used_at, revoked_at FROM refresh_tokens WHERE jti = $1
+         FOR UPDATE
+       ), consumed AS (
+         UPDATE refresh_tokens SET used_at = NOW()
+         FROM token
+         WHERE refresh_tokens.jti = token.jti AND token.used_at IS NULL AND token.revoked_at IS NULL
+       )
+       SELECT family_id, used_at IS NOT NULL, revoked_at IS NOT NULL FROM token"
+    )
+      .bind(token_id)
+      .fetch_optional(&self.pool)
+      .await?;
+
+    Ok(match token {
+      None => RefreshTokenState::Unknown,
+      Some((_, _, true)) => RefreshTokenState::Revoked,
+      Some((family_id, true, _)) => RefreshTokenState::Used { family_id },
+      Some((family_id, false, false)) => RefreshTokenState::Active { family_id },
+    })
+  }
+
+  async fn revoke_family(&self, family_id: Uuid) -> Result<(), Error> {
+    sqlx::query("UPDATE refresh_tokens SET revoked_at = NOW() WHERE family_id = $1 AND revoked_at IS NULL")
+      .bind(family_id)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn delete_expired(&self) -> Result<u64, Error> {
+    Ok(sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < NOW()")
+      .execute(&self.pool)
+      .await?
+      .rows_affected())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn expires_in() -> usize {
+    (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize
+  }
+
+  #[tokio::test]
+  async fn test_consume_is_one_time() {
+    // arrange
+    let store = InMemoryRefreshTokenStore::new();
+    let family_id = Uuid::new_v4();
+    store.save("token-1", family_id, expires_in()).await.unwrap();
+
+    // act
+    let first_state = store.consume("token-1").await.unwrap();
+    let second_state = store.consume("token-1").await.unwrap();
+
+    // assert
+    assert_eq!(first_state, RefreshTokenState::Active { family_id });
+    assert_eq!(second_state, RefreshTokenState::Used { family_id });
+  }
+
+  #[tokio::test]
+  async fn test_revoke_family() {
+    // arrange
+    let store = InMemoryRefreshTokenStore::new();
+    let family_id = Uuid::new_v4();
+    let other_family_id = Uuid::new_v4();
+    store.save("token-1", family_id, expires_in()).await.unwrap();
+    store.save("token-2", family_id, expires_in()).await.unwrap();
+    store.save("token-3", other_family_id, expires_in()).await.unwrap();
+
+    // act
+    store.revoke_family(family_id).await.unwrap();
+
+    // assert
+    assert_eq!(store.consume("token-1").await.unwrap(), RefreshTokenState::Revoked);
+    assert_eq!(store.consume("token-2").await.unwrap(), RefreshTokenState::Revoked);
+    assert_eq!(
+      store.consume("token-3").await.unwrap(),
+      RefreshTokenState::Active { family_id: other_family_id }
+    );
+    assert_eq!(store.consume("token-4").await.unwrap(), RefreshTokenState::Unknown);
+  }
+
+  #[tokio::test]
+  async fn test_delete_expired() {
+    // arrange
+    let store = InMemoryRefreshTokenStore::new();
+    let family_id = Uuid::new_v4();
+    let expired = (Utc::now() - chrono::Duration::minutes(1)).timestamp() as usize;
+    store.save("token-1", family_id, expires_in()).await.unwrap();
+    store.save("token-2", family_id, expired).await.unwrap();
+
+    // act
+    let deleted = store.delete_expired().await.unwrap();
+
+    // assert
+    assert_eq!(deleted, 1);
+    assert_eq!(store.consume("token-1").await.unwrap(), RefreshTokenState::Active { family_id });
+    assert_eq!(store.consume("token-2").await.unwrap(), RefreshTokenState::Unknown);
+  }
+}
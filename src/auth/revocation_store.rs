@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  error::SerializableError,
+  types::{auth::AuthPayload, utils::jwt_util::AuthClaims},
+  Error,
+};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IRevocationStore: Send + Sync {
+  async fn revoke(&self, jti: &str, user_id: Uuid, expires_in: usize) -> Result<(), Error>;
+
+  async fn is_revoked(&self, jti: &str) -> Result<bool, Error>;
+
+  async fn token_version(&self, user_id: Uuid) -> Result<i32, Error>;
+
+  /// Bumps the user's token version so every token issued before the call
+  /// is rejected. Returns the new version.
+  async fn revoke_all_for_user(&self, user_id: Uuid) -> Result<i32, Error>;
+
+  /// Deletes denylist entries for tokens that have expired anyway. Returns
+  /// the number of rows removed.
+  async fn delete_expired(&self) -> Result<u64, Error>;
+}
+
+/// Rejects `claims` if its `jti` was revoked or it was issued before the
+/// user's tokens were revoked wholesale.
+pub async fn ensure_not_revoked<T: AuthPayload>(
+  revocation_store: &dyn IRevocationStore,
+  claims: &AuthClaims<T>,
+) -> Result<(), Error> {
+  if let Some(jti) = claims.jti.as_deref()
+    && revocation_store.is_revoked(jti).await? {
+    return Err(Error::Unauthorized(SerializableError {
+      message: "Token has been revoked".to_string()
+    }));
+  }
+
+  let token_version = revocation_store.token_version(claims.user_details.user_id()).await?;
+  if claims.token_version.unwrap_or(0) < token_version {
+    return Err(Error::Unauthorized(SerializableError {
+      message: "Token has been revoked".to_string()
+    }));
+  }
+
+  Ok(())
+}
+
+/// `IRevocationStore` backed by the `revoked_tokens` and
+/// `user_token_versions` tables from `database::MIGRATOR`.
+#[derive(Clone)]
+pub struct PgRevocationStore {
+  pool: PgPool,
+}
+
+impl PgRevocationStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl IRevocationStore for PgRevocationStore {
+  async fn revoke(&self, jti: &str, user_id: Uuid, expires_in: usize) -> Result<(), Error> {
+    let expires_at = DateTime::<Utc>::from_timestamp(expires_in as i64, 0)
+      .ok_or_else(|| Error::Unhandled(SerializableError {
+        message: format!("Invalid expiry timestamp {}", expires_in)
+      }))?;
+
+    sqlx::query(
+      "INSERT INTO revoked_tokens (jti, user_id, expires_at) VALUES ($1, $2, $3)
+       ON CONFLICT (jti) DO NOTHING"
+    )
+      .bind(jti)
+      .bind(user_id)
+      .bind(expires_at)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn is_revoked(&self, jti: &str) -> Result<bool, Error> {
+    Ok(sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)")
+      .bind(jti)
+      .fetch_one(&self.pool)
+      .await?)
+  }
+
+  async fn token_version(&self, user_id: Uuid) -> Result<i32, Error> {
+    let token_version: Option<i32> = sqlx::query_scalar(
+      "SELECT token_version FROM user_token_versions WHERE user_id = $1"
+    )
+      .bind(user_id)
+      .fetch_optional(&self.pool)
+      .await?;
+
+    Ok(token_version.unwrap_or(0))
+  }
+
+  async fn revoke_all_for_user(&self, user_id: Uuid) -> Result<i32, Error> {
+    Ok(sqlx::query_scalar(
+      "INSERT INTO user_token_versions (user_id, token_version) VALUES ($1, 1)
+       ON CONFLICT (user_id) DO UPDATE
+         SET token_version = user_token_versions.token_version + 1, updated_at = NOW()
+       RETURNING token_version"
+    )
+      .bind(user_id)
+      .fetch_one(&self.pool)
+      .await?)
+  }
+
+  async fn delete_expired(&self) -> Result<u64, Error> {
+    Ok(sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+      .execute(&self.pool)
+      .await?
+      .rows_affected())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::types::{auth::AuthUser, utils::jwt_util::TokenType};
+
+  fn claims(jti: Option<&str>, token_version: Option<i32>) -> AuthClaims {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+
+    AuthClaims {
+      subject: auth_user.id.to_string(),
+      expires_in: Utc::now().timestamp() as usize,
+      user_details: auth_user,
+      token_type: TokenType::AccessToken,
+      jti: jti.map(str::to_string),
+      token_version,
+      issuer: None,
+      audience: None,
+      issued_at: None,
+      not_before: None,
+      roles: Vec::new(),
+      permissions: Vec::new(),
+      extra: Default::default(),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_ensure_not_revoked_accepts_valid_token() {
+    // arrange
+    let mut revocation_store = MockIRevocationStore::new();
+    revocation_store.expect_is_revoked().with(eq("token-1")).returning(|_| Ok(false));
+    revocation_store.expect_token_version().returning(|_| Ok(2));
+
+    // act
+    let result = ensure_not_revoked(&revocation_store, &claims(Some("token-1"), Some(2))).await;
+
+    // assert
+    assert!(result.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_ensure_not_revoked_rejects_revoked_jti() {
+    // arrange
+    let mut revocation_store = MockIRevocationStore::new();
+    revocation_store.expect_is_revoked().returning(|_| Ok(true));
+    revocation_store.expect_token_version().never();
+
+    // act
+    let result = ensure_not_revoked(&revocation_store, &claims(Some("token-1"), None)).await;
+
+    // assert
+    match result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Token has been revoked"),
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_ensure_not_revoked_rejects_outdated_token_version() {
+    // arrange
+    let mut revocation_store = MockIRevocationStore::new();
+    revocation_store.expect_is_revoked().never();
+    revocation_store.expect_token_version().returning(|_| Ok(1));
+
+    // act
+    let result = ensure_not_revoked(&revocation_store, &claims(None, None)).await;
+
+    // assert
+    assert!(matches!(result, Err(Error::Unauthorized(_))));
+  }
+}
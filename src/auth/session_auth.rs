@@ -0,0 +1,411 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use lambda_http::{http::HeaderMap, Request};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+  auth::{
+    load_principal,
+    principal_loader::IPrincipalLoader,
+    session_store::{ISessionStore, SessionRecord},
+    token_extractor::{CookieExtractor, ITokenExtractor, TokenSource},
+    client_ip, IAuth,
+  },
+  error::SerializableError,
+  types::auth::{AuthPayload, AuthUser},
+  Error,
+};
+
+/// Cookie sessions stored server side. The cookie only carries an opaque,
+/// random session id; sessions end after `idle_timeout` without requests
+/// or `absolute_timeout` after login, whichever comes first.
+pub struct SessionAuth<T: AuthPayload = AuthUser> {
+  session_store: Box<dyn ISessionStore>,
+  cookie_name: String,
+  idle_timeout: Duration,
+  absolute_timeout: Duration,
+  principal_loader: Option<Box<dyn IPrincipalLoader<T>>>,
+  session: Option<SessionRecord>,
+  user: Option<T>,
+}
+
+impl SessionAuth {
+  pub fn new(session_store: Box<dyn ISessionStore>) -> Self {
+    Self::for_payload(session_store)
+  }
+}
+
+impl<T: AuthPayload> SessionAuth<T> {
+  pub fn for_payload(session_store: Box<dyn ISessionStore>) -> Self {
+    Self {
+      session_store,
+      cookie_name: "session_id".to_string(),
+      idle_timeout: Duration::minutes(30),
+      absolute_timeout: Duration::hours(12),
+      principal_loader: None,
+      session: None,
+      user: None,
+    }
+  }
+
+  pub fn with_cookie_name(mut self, cookie_name: &str) -> Self {
+    self.cookie_name = cookie_name.to_string();
+
+    self
+  }
+
+  /// 30 minutes idle and 12 hours absolute by default.
+  pub fn with_timeouts(mut self, idle_timeout: Duration, absolute_timeout: Duration) -> Self {
+    self.idle_timeout = idle_timeout;
+    self.absolute_timeout = absolute_timeout;
+
+    self
+  }
+
+  /// Rejects sessions of users the loader no longer returns, e.g. deleted
+  /// or deactivated ones. `user` then returns the loaded principal instead
+  /// of the one stored with the session.
+  pub fn with_principal_loader(mut self, principal_loader: Box<dyn IPrincipalLoader<T>>) -> Self {
+    self.principal_loader = Some(principal_loader);
+
+    self
+  }
+
+  pub fn user(&self) -> Option<T> {
+    self.user.clone()
+  }
+
+  pub fn session(&self) -> Option<&SessionRecord> {
+    self.session.as_ref()
+  }
+
+  /// Starts a session for `user`, recording the client IP and user agent
+  /// of the login request. Returns the `Set-Cookie` value to send back and
+  /// the stored session.
+  pub async fn create_session(&self, user: &T, request: &Request) -> Result<(String, SessionRecord), Error> {
+    let mut session_id = [0u8; 32];
+    OsRng.fill_bytes(&mut session_id);
+    let session_id = URL_SAFE_NO_PAD.encode(session_id);
+
+    let principal = serde_json::to_value(user).map_err(|error| Error::Unhandled(SerializableError {
+      message: error.to_string()
+    }))?;
+    let now = Utc::now();
+    let session = SessionRecord {
+      id: Uuid::new_v4(),
+      user_id: user.user_id(),
+      token_hash: hash_session_id(&session_id),
+      principal,
+      ip_address: client_ip(request),
+      user_agent: request.headers().get("User-Agent")
+        .and_then(|header_value| header_value.to_str().ok())
+        .map(str::to_string),
+      created_at: now,
+      last_seen_at: now,
+      expires_at: now + self.absolute_timeout,
+      revoked_at: None,
+    };
+
+    self.session_store.create(&session).await?;
+
+    Ok((self.session_cookie(&session_id), session))
+  }
+
+  pub fn session_cookie(&self, session_id: &str) -> String {
+    format!(
+      "{}={}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+      self.cookie_name,
+      session_id,
+      self.absolute_timeout.num_seconds(),
+    )
+  }
+
+  /// `Set-Cookie` value that removes the session cookie, e.g. on logout.
+  pub fn clear_cookie(&self) -> String {
+    format!("{}=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax", self.cookie_name)
+  }
+
+  /// Revokes the authenticated session.
+  pub async fn logout(&mut self) -> Result<(), Error> {
+    if let Some(session) = self.session.take() {
+      self.session_store.revoke(session.id).await?;
+    }
+    self.user = None;
+
+    Ok(())
+  }
+
+  pub async fn sessions(&self, user_id: Uuid) -> Result<Vec<SessionRecord>, Error> {
+    self.session_store.list_for_user(user_id).await
+  }
+
+  /// Revokes one of the authenticated user's sessions, e.g. from a "signed
+  /// in devices" page.
+  pub async fn revoke_session(&self, session_id: Uuid) -> Result<(), Error> {
+    let user_id = self.user.as_ref()
+      .map(|user| user.user_id())
+      .ok_or_else(|| unauthorized("Not authenticated"))?;
+
+    let owns_session = self.session_store.list_for_user(user_id).await?
+      .iter()
+      .any(|session| session.id == session_id);
+    if !owns_session {
+      return Err(Error::DatabaseRowNotFound(SerializableError { message: "Session not found".to_string() }));
+    }
+
+    self.session_store.revoke(session_id).await
+  }
+
+  pub async fn revoke_all_sessions(&self, user_id: Uuid) -> Result<(), Error> {
+    self.session_store.revoke_all_for_user(user_id).await
+  }
+}
+
+#[async_trait]
+impl<T: AuthPayload> IAuth for SessionAuth<T> {
+  async fn authenticate(&mut self, headers: &HeaderMap) -> Result<(), Error> {
+    let session_id = CookieExtractor::new(&self.cookie_name)
+      .extract(&TokenSource::from_headers(headers))?
+      .ok_or_else(|| unauthorized("Missing session cookie"))?;
+
+    let session = self.session_store
+      .find_by_token_hash(&hash_session_id(&session_id))
+      .await?
+      .ok_or_else(|| unauthorized("Invalid session"))?;
+
+    let now = Utc::now();
+    if session.revoked_at.is_some() {
+      return Err(unauthorized("Session has been revoked"));
+    }
+
+    if session.expires_at <= now || session.last_seen_at + self.idle_timeout <= now {
+      return Err(unauthorized("Session has expired"));
+    }
+
+    let user: T = match self.principal_loader.as_deref() {
+      Some(principal_loader) => load_principal(principal_loader, session.user_id).await?,
+      None => serde_json::from_value(session.principal.clone()).map_err(|error| {
+        Error::DatabaseRowMapping(SerializableError { message: error.to_string() })
+      })?,
+    };
+
+    self.session_store.touch(session.id).await?;
+
+    self.user = Some(user);
+    self.session = Some(session);
+
+    Ok(())
+  }
+}
+
+fn hash_session_id(session_id: &str) -> String {
+  Sha256::digest(session_id.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+
+  use lambda_http::{
+    aws_lambda_events::apigw::{ApiGatewayV2httpRequestContext, ApiGatewayV2httpRequestContextHttpDescription},
+    request::RequestContext,
+    RequestExt,
+  };
+
+  use crate::auth::{principal_loader::MockIPrincipalLoader, session_store::MockISessionStore};
+
+  fn auth_user() -> AuthUser {
+    AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    }
+  }
+
+  fn cookie_headers(set_cookie: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("Cookie", set_cookie.split(';').next().unwrap().parse().unwrap());
+
+    headers
+  }
+
+  /// Creates a session through a mock store and returns the store holding
+  /// it together with the cookie.
+  async fn created_session(user: &AuthUser, adjust: impl FnOnce(&mut SessionRecord)) -> (MockISessionStore, String) {
+    let stored: Arc<Mutex<Option<SessionRecord>>> = Arc::default();
+    let mut session_store = MockISessionStore::new();
+    let create_target = stored.clone();
+    session_store.expect_create().times(1).returning(move |session| {
+      *create_target.lock().unwrap() = Some(session.clone());
+      Ok(())
+    });
+
+    let mut login_request = Request::default().with_request_context(RequestContext::ApiGatewayV2(
+      ApiGatewayV2httpRequestContext {
+        http: ApiGatewayV2httpRequestContextHttpDescription {
+          source_ip: Some("203.0.113.7".to_string()),
+          ..Default::default()
+        },
+        ..Default::default()
+      }
+    ));
+    login_request.headers_mut().insert("User-Agent", "Mozilla/5.0".parse().unwrap());
+    login_request.headers_mut().insert("X-Forwarded-For", "198.51.100.1".parse().unwrap());
+    let (set_cookie, _) = SessionAuth::new(Box::new(session_store)).create_session(user, &login_request).await.unwrap();
+
+    let mut session = stored.lock().unwrap().clone().unwrap();
+    adjust(&mut session);
+    let mut session_store = MockISessionStore::new();
+    session_store.expect_find_by_token_hash()
+      .returning(move |token_hash| Ok((token_hash == session.token_hash).then(|| session.clone())));
+
+    (session_store, set_cookie)
+  }
+
+  fn error_message(result: Result<(), Error>) -> String {
+    match result {
+      Err(Error::Unauthorized(error)) => error.message,
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_create_session_and_authenticate() {
+    // arrange
+    let user = auth_user();
+    let (mut session_store, set_cookie) = created_session(&user, |_| {}).await;
+    session_store.expect_touch().times(1).returning(|_| Ok(()));
+
+    // act
+    let mut auth = SessionAuth::new(Box::new(session_store));
+    let result = auth.authenticate(&cookie_headers(&set_cookie)).await;
+
+    // assert
+    assert!(result.is_ok());
+    assert_eq!(auth.user(), Some(user));
+    let session = auth.session().unwrap();
+    assert_eq!(session.ip_address.as_deref(), Some("203.0.113.7"));
+    assert_eq!(session.user_agent.as_deref(), Some("Mozilla/5.0"));
+    assert!(set_cookie.starts_with("session_id="));
+    assert!(set_cookie.ends_with("; Path=/; Max-Age=43200; HttpOnly; Secure; SameSite=Lax"));
+    assert!(!set_cookie.contains(&session.token_hash));
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_idle_and_absolute_timeouts() {
+    // arrange
+    let user = auth_user();
+    let (idle_store, idle_cookie) = created_session(&user, |session| {
+      session.last_seen_at = Utc::now() - Duration::minutes(31);
+    }).await;
+    let (absolute_store, absolute_cookie) = created_session(&user, |session| {
+      session.expires_at = Utc::now() - Duration::seconds(1);
+    }).await;
+
+    // act
+    let idle_result = SessionAuth::new(Box::new(idle_store)).authenticate(&cookie_headers(&idle_cookie)).await;
+    let absolute_result = SessionAuth::new(Box::new(absolute_store))
+      .authenticate(&cookie_headers(&absolute_cookie))
+      .await;
+
+    // assert
+    assert_eq!(error_message(idle_result), "Session has expired");
+    assert_eq!(error_message(absolute_result), "Session has expired");
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_revoked_and_missing() {
+    // arrange
+    let user = auth_user();
+    let (session_store, set_cookie) = created_session(&user, |session| session.revoked_at = Some(Utc::now())).await;
+    let mut auth = SessionAuth::new(Box::new(session_store));
+
+    // act
+    let revoked_result = auth.authenticate(&cookie_headers(&set_cookie)).await;
+    let missing_result = auth.authenticate(&HeaderMap::new()).await;
+    let unknown_result = auth.authenticate(&cookie_headers("session_id=unknown")).await;
+
+    // assert
+    assert_eq!(error_message(revoked_result), "Session has been revoked");
+    assert_eq!(error_message(missing_result), "Missing session cookie");
+    assert_eq!(error_message(unknown_result), "Invalid session");
+  }
+
+  #[tokio::test]
+  async fn test_revoke_session_and_logout() {
+    // arrange
+    let user = auth_user();
+    let (mut session_store, set_cookie) = created_session(&user, |_| {}).await;
+    let other_session_id = Uuid::new_v4();
+    session_store.expect_touch().returning(|_| Ok(()));
+    session_store.expect_list_for_user().returning(move |user_id| Ok(vec![SessionRecord {
+      id: other_session_id,
+      user_id,
+      token_hash: "other".to_string(),
+      principal: serde_json::Value::Null,
+      ip_address: None,
+      user_agent: None,
+      created_at: Utc::now(),
+      last_seen_at: Utc::now(),
+      expires_at: Utc::now() + Duration::hours(1),
+      revoked_at: None,
+    }]));
+    session_store.expect_revoke().times(2).returning(|_| Ok(()));
+    let mut auth = SessionAuth::new(Box::new(session_store));
+    auth.authenticate(&cookie_headers(&set_cookie)).await.unwrap();
+
+    // act
+    let revoke_result = auth.revoke_session(other_session_id).await;
+    let foreign_result = auth.revoke_session(Uuid::new_v4()).await;
+    let logout_result = auth.logout().await;
+
+    // assert
+    assert!(revoke_result.is_ok());
+    assert!(matches!(foreign_result, Err(Error::DatabaseRowNotFound(_))));
+    assert!(logout_result.is_ok());
+    assert_eq!(auth.user(), None);
+    assert_eq!(auth.clear_cookie(), "session_id=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax");
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_with_principal_loader() {
+    // arrange
+    let user = auth_user();
+    let renamed_user = AuthUser { last_name: "Smith".to_string(), ..user.clone() };
+    let (mut session_store, set_cookie) = created_session(&user, |_| {}).await;
+    session_store.expect_touch().times(1).returning(|_| Ok(()));
+    let (deactivated_store, deactivated_cookie) = created_session(&auth_user(), |_| {}).await;
+    let loaded_user = renamed_user.clone();
+    let principal_loader = move || {
+      let loaded_user = loaded_user.clone();
+      let mut principal_loader = MockIPrincipalLoader::new();
+      principal_loader.expect_load()
+        .returning(move |user_id| Ok((user_id == loaded_user.id).then(|| loaded_user.clone())));
+
+      Box::new(principal_loader)
+    };
+    let mut auth = SessionAuth::new(Box::new(session_store)).with_principal_loader(principal_loader());
+
+    // act
+    let result = auth.authenticate(&cookie_headers(&set_cookie)).await;
+    let deactivated_result = SessionAuth::new(Box::new(deactivated_store))
+      .with_principal_loader(principal_loader())
+      .authenticate(&cookie_headers(&deactivated_cookie))
+      .await;
+
+    // assert
+    assert!(result.is_ok());
+    assert_eq!(auth.user(), Some(renamed_user));
+    assert_eq!(error_message(deactivated_result), "User no longer exists or has been deactivated");
+  }
+}
@@ -0,0 +1,130 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::Error;
+
+/// A server-side session. Only a SHA-256 hash of the session id sent in the
+/// cookie is kept; `id` identifies the session when listing or revoking.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct SessionRecord {
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub token_hash: String,
+  /// The principal `SessionAuth::user` returns, serialized at login.
+  pub principal: Value,
+  pub ip_address: Option<String>,
+  pub user_agent: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub last_seen_at: DateTime<Utc>,
+  /// End of the absolute timeout.
+  pub expires_at: DateTime<Utc>,
+  pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait ISessionStore: Send + Sync {
+  async fn create(&self, session: &SessionRecord) -> Result<(), Error>;
+
+  async fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<SessionRecord>, Error>;
+
+  /// Records activity on the session for the idle timeout.
+  async fn touch(&self, id: Uuid) -> Result<(), Error>;
+
+  /// Active sessions of the user, newest first.
+  async fn list_for_user(&self, user_id: Uuid) -> Result<Vec<SessionRecord>, Error>;
+
+  async fn revoke(&self, id: Uuid) -> Result<(), Error>;
+
+  async fn revoke_all_for_user(&self, user_id: Uuid) -> Result<(), Error>;
+}
+
+const SESSION_COLUMNS: &str =
+  "id, user_id, token_hash, principal, ip_address, user_agent, created_at, last_seen_at, expires_at, revoked_at";
+
+/// `ISessionStore` backed by the `sessions` table from `database::MIGRATOR`.
+#[derive(Clone)]
+pub struct PgSessionStore {
+  pool: PgPool,
+}
+
+impl PgSessionStore {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+#[async_trait]
+impl ISessionStore for PgSessionStore {
+  async fn create(&self, session: &SessionRecord) -> Result<(), Error> {
+    sqlx::query(&format!(
+      "INSERT INTO sessions ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+      SESSION_COLUMNS
+    ))
+      .bind(session.id)
+      .bind(session.user_id)
+      .bind(&session.token_hash)
+      .bind(&session.principal)
+      .bind(&session.ip_address)
+      .bind(&session.user_agent)
+      .bind(session.created_at)
+      .bind(session.last_seen_at)
+      .bind(session.expires_at)
+      .bind(session.revoked_at)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<SessionRecord>, Error> {
+    Ok(sqlx::query_as(&format!("SELECT {} FROM sessions WHERE token_hash = $1", SESSION_COLUMNS))
+      .bind(token_hash)
+      .fetch_optional(&self.pool)
+      .await?)
+  }
+
+  async fn touch(&self, id: Uuid) -> Result<(), Error> {
+    sqlx::query("UPDATE sessions SET last_seen_at = NOW() WHERE id = $1")
+      .bind(id)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn list_for_user(&self, user_id: Uuid) -> Result<Vec<SessionRecord>, Error> {
+    Ok(sqlx::query_as(&format!(
+      "SELECT {} FROM sessions
+       WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+       ORDER BY created_at DESC",
+      SESSION_COLUMNS
+    ))
+      .bind(user_id)
+      .fetch_all(&self.pool)
+      .await?)
+  }
+
+  async fn revoke(&self, id: Uuid) -> Result<(), Error> {
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+      .bind(id)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+
+  async fn revoke_all_for_user(&self, user_id: Uuid) -> Result<(), Error> {
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+      .bind(user_id)
+      .execute(&self.pool)
+      .await?;
+
+    Ok(())
+  }
+}
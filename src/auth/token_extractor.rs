@@ -0,0 +1,217 @@
+use lambda_http::{aws_lambda_events::query_map::QueryMap, http::HeaderMap, Request, RequestExt};
+
+use crate::{auth::auth_config::AuthConfig, error::SerializableError, Error};
+
+/// The parts of a request credentials can be read from.
+#[derive(Clone, Copy)]
+pub struct TokenSource<'a> {
+  pub headers: &'a HeaderMap,
+  pub query_parameters: Option<&'a QueryMap>,
+}
+
+impl<'a> TokenSource<'a> {
+  pub fn from_headers(headers: &'a HeaderMap) -> Self {
+    Self { headers, query_parameters: None }
+  }
+
+  pub fn from_request(request: &'a Request) -> Self {
+    Self { headers: request.headers(), query_parameters: request.query_string_parameters_ref() }
+  }
+}
+
+/// Finds a token in one place of the request. `Ok(None)` means the token
+/// isn't there and the next extractor should be tried; an error means it
+/// is there but unusable.
+pub trait ITokenExtractor: Send + Sync {
+  /// Identifies the extractor in `Auth::token_source`, e.g.
+  /// `cookie:access_token`.
+  fn name(&self) -> &str;
+
+  fn extract(&self, source: &TokenSource) -> Result<Option<String>, Error>;
+}
+
+/// Reads `Authorization: <scheme> <token>` using the schemes of `config`.
+pub struct AuthorizationHeaderExtractor {
+  config: AuthConfig,
+}
+
+impl AuthorizationHeaderExtractor {
+  pub fn new(config: AuthConfig) -> Self {
+    Self { config }
+  }
+}
+
+impl ITokenExtractor for AuthorizationHeaderExtractor {
+  fn name(&self) -> &str {
+    "authorization"
+  }
+
+  fn extract(&self, source: &TokenSource) -> Result<Option<String>, Error> {
+    if !source.headers.contains_key("Authorization") {
+      return Ok(None);
+    }
+
+    Ok(Some(self.config.extract_token(source.headers)?.to_string()))
+  }
+}
+
+pub struct CookieExtractor {
+  cookie_name: String,
+  name: String,
+}
+
+impl CookieExtractor {
+  pub fn new(cookie_name: &str) -> Self {
+    Self { cookie_name: cookie_name.to_string(), name: format!("cookie:{}", cookie_name) }
+  }
+}
+
+impl ITokenExtractor for CookieExtractor {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn extract(&self, source: &TokenSource) -> Result<Option<String>, Error> {
+    let cookie = source.headers.get_all("Cookie")
+      .iter()
+      .filter_map(|header_value| header_value.to_str().ok())
+      .flat_map(|header_value| header_value.split(';'))
+      .filter_map(|cookie| cookie.trim().split_once('='))
+      .find(|(name, _)| *name == self.cookie_name);
+
+    match cookie {
+      Some((_, value)) => non_empty(value.trim().trim_matches('"')),
+      None => Ok(None),
+    }
+  }
+}
+
+pub struct QueryParameterExtractor {
+  parameter_name: String,
+  name: String,
+}
+
+impl QueryParameterExtractor {
+  pub fn new(parameter_name: &str) -> Self {
+    Self { parameter_name: parameter_name.to_string(), name: format!("query:{}", parameter_name) }
+  }
+}
+
+impl ITokenExtractor for QueryParameterExtractor {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn extract(&self, source: &TokenSource) -> Result<Option<String>, Error> {
+    match source.query_parameters.and_then(|parameters| parameters.first(&self.parameter_name)) {
+      Some(value) => non_empty(value.trim()),
+      None => Ok(None),
+    }
+  }
+}
+
+/// Reads the raw token from a custom header such as `X-Auth-Token`.
+pub struct HeaderExtractor {
+  header_name: String,
+  name: String,
+}
+
+impl HeaderExtractor {
+  pub fn new(header_name: &str) -> Self {
+    Self { header_name: header_name.to_string(), name: format!("header:{}", header_name) }
+  }
+}
+
+impl ITokenExtractor for HeaderExtractor {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn extract(&self, source: &TokenSource) -> Result<Option<String>, Error> {
+    match source.headers.get(self.header_name.as_str()) {
+      Some(header_value) => {
+        let value = header_value.to_str().map_err(|_| Error::Unauthorized(SerializableError {
+          message: format!("Malformed {} header", self.header_name)
+        }))?;
+
+        non_empty(value.trim())
+      },
+      None => Ok(None),
+    }
+  }
+}
+
+fn non_empty(value: &str) -> Result<Option<String>, Error> {
+  Ok((!value.is_empty()).then(|| value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  #[test]
+  fn test_authorization_header_extractor() {
+    // arrange
+    let extractor = AuthorizationHeaderExtractor::new(AuthConfig::bearer());
+    let mut headers = HeaderMap::new();
+    let empty_headers = HeaderMap::new();
+    headers.insert("Authorization", "Bearer token-1".parse().unwrap());
+
+    // act
+    let token = extractor.extract(&TokenSource::from_headers(&headers)).unwrap();
+    let missing = extractor.extract(&TokenSource::from_headers(&empty_headers)).unwrap();
+
+    // assert
+    assert_eq!(token, Some("token-1".to_string()));
+    assert_eq!(missing, None);
+  }
+
+  #[test]
+  fn test_cookie_extractor() {
+    // arrange
+    let extractor = CookieExtractor::new("access_token");
+    let mut headers = HeaderMap::new();
+    headers.append("Cookie", "theme=dark; access_token=token-1".parse().unwrap());
+
+    // act
+    let token = extractor.extract(&TokenSource::from_headers(&headers)).unwrap();
+    let missing = CookieExtractor::new("session").extract(&TokenSource::from_headers(&headers)).unwrap();
+
+    // assert
+    assert_eq!(token, Some("token-1".to_string()));
+    assert_eq!(missing, None);
+    assert_eq!(extractor.name(), "cookie:access_token");
+  }
+
+  #[test]
+  fn test_query_parameter_extractor() {
+    // arrange
+    let extractor = QueryParameterExtractor::new("access_token");
+    let request = Request::default().with_query_string_parameters(HashMap::from([
+      ("access_token".to_string(), "token-1".to_string()),
+    ]));
+
+    // act
+    let token = extractor.extract(&TokenSource::from_request(&request)).unwrap();
+    let missing = extractor.extract(&TokenSource::from_headers(request.headers())).unwrap();
+
+    // assert
+    assert_eq!(token, Some("token-1".to_string()));
+    assert_eq!(missing, None);
+  }
+
+  #[test]
+  fn test_header_extractor() {
+    // arrange
+    let extractor = HeaderExtractor::new("X-Auth-Token");
+    let mut headers = HeaderMap::new();
+    headers.insert("x-auth-token", " token-1 ".parse().unwrap());
+
+    // act
+    let token = extractor.extract(&TokenSource::from_headers(&headers)).unwrap();
+
+    // assert
+    assert_eq!(token, Some("token-1".to_string()));
+  }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+  types::{
+    auth::{AuthPayload, AuthUser, IssuedToken},
+    utils::jwt_util::{AuthClaims, TokenType},
+  },
+  utils::jwt_util::IJwtUtil,
+  Error,
+};
+
+/// Builds and signs claims for a user so callers don't have to compute
+/// `sub`/`exp`/`iat`/`jti` by hand. Lifetimes are configured per token type.
+pub struct TokenIssuer<T: AuthPayload = AuthUser> {
+  jwt_util: Box<dyn IJwtUtil<T>>,
+  lifetimes: HashMap<TokenType, Duration>,
+  issuer: Option<String>,
+  audience: Option<String>,
+}
+
+impl TokenIssuer {
+  pub fn new(jwt_util: Box<dyn IJwtUtil>) -> Self {
+    Self::for_payload(jwt_util)
+  }
+}
+
+impl<T: AuthPayload> TokenIssuer<T> {
+  pub fn for_payload(jwt_util: Box<dyn IJwtUtil<T>>) -> Self {
+    Self { jwt_util, lifetimes: HashMap::new(), issuer: None, audience: None }
+  }
+
+  pub fn with_lifetime(mut self, token_type: TokenType, lifetime: Duration) -> Self {
+    self.lifetimes.insert(token_type, lifetime);
+
+    self
+  }
+
+  pub fn with_issuer(mut self, issuer: &str) -> Self {
+    self.issuer = Some(issuer.to_string());
+
+    self
+  }
+
+  pub fn with_audience(mut self, audience: &str) -> Self {
+    self.audience = Some(audience.to_string());
+
+    self
+  }
+
+  /// Configured lifetime for `token_type`, 15 minutes for access and magic
+  /// link tokens, 30 days for refresh tokens, 10 minutes for impersonation
+  /// tokens and 5 minutes for MFA tokens unless overridden.
+  pub fn lifetime(&self, token_type: TokenType) -> Duration {
+    self.lifetimes.get(&token_type).copied().unwrap_or_else(|| match token_type {
+      TokenType::AccessToken => Duration::minutes(15),
+      TokenType::RefreshToken => Duration::days(30),
+      TokenType::MfaPending => Duration::minutes(5),
+      TokenType::MagicLink => Duration::minutes(15),
+      TokenType::Impersonation => Duration::minutes(10),
+    })
+  }
+
+  pub fn jwt_util(&self) -> &dyn IJwtUtil<T> {
+    self.jwt_util.as_ref()
+  }
+
+  /// Claims `issue` would sign, for callers that need to add their own
+  /// claims before passing them to `sign`.
+  pub fn claims(&self, user: &T, token_type: TokenType) -> AuthClaims<T> {
+    let now = Utc::now();
+    let expires_in = (now + self.lifetime(token_type)).timestamp() as usize;
+    let mut claims = AuthClaims::new(user.clone(), token_type, expires_in)
+      .with_not_before(now.timestamp() as usize)
+      .with_random_jti();
+    claims.issued_at = Some(now.timestamp() as usize);
+
+    if let Some(issuer) = self.issuer.as_deref() {
+      claims = claims.with_issuer(issuer);
+    }
+
+    if let Some(audience) = self.audience.as_deref() {
+      claims = claims.with_audience(audience);
+    }
+
+    claims
+  }
+
+  pub fn issue(&self, user: &T, token_type: TokenType) -> Result<IssuedToken, Error> {
+    self.sign(self.claims(user, token_type))
+  }
+
+  /// Signs `claims`, giving them a `jti` and `iat` if they have none.
+  pub fn sign(&self, mut claims: AuthClaims<T>) -> Result<IssuedToken, Error> {
+    let issued_at = *claims.issued_at.get_or_insert_with(|| Utc::now().timestamp() as usize);
+    let jti = claims.jti.get_or_insert_with(|| Uuid::new_v4().to_string()).clone();
+    let token = self.jwt_util.generate_token(&claims)?;
+
+    Ok(IssuedToken {
+      token,
+      token_type: claims.token_type,
+      jti,
+      issued_at,
+      expires_at: claims.expires_in,
+      expires_in: claims.expires_in as i64 - issued_at as i64,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::utils::JwtUtil;
+
+  fn auth_user() -> AuthUser {
+    AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_issue() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key");
+    let token_issuer = TokenIssuer::new(Box::new(jwt_util.clone()))
+      .with_lifetime(TokenType::AccessToken, Duration::minutes(5));
+    let user = auth_user();
+
+    // act
+    let issued_token = token_issuer.issue(&user, TokenType::AccessToken).unwrap();
+
+    // assert
+    let claims: AuthClaims = jwt_util.extract_claims(&issued_token.token).await.unwrap();
+    assert_eq!(claims.subject, user.id.to_string());
+    assert_eq!(claims.user_details, user);
+    assert_eq!(claims.token_type, TokenType::AccessToken);
+    assert_eq!(claims.jti, Some(issued_token.jti.clone()));
+    assert_eq!(claims.issued_at, Some(issued_token.issued_at));
+    assert_eq!(claims.expires_in, issued_token.expires_at);
+    assert_eq!(issued_token.expires_in, 300);
+  }
+
+  #[test]
+  fn test_default_lifetimes() {
+    // arrange
+    let token_issuer = TokenIssuer::new(Box::new(JwtUtil::new("some_key")))
+      .with_lifetime(TokenType::RefreshToken, Duration::days(7));
+
+    // act
+    let access_token = token_issuer.issue(&auth_user(), TokenType::AccessToken).unwrap();
+    let refresh_token = token_issuer.issue(&auth_user(), TokenType::RefreshToken).unwrap();
+
+    // assert
+    assert_eq!(access_token.expires_in, Duration::minutes(15).num_seconds());
+    assert_eq!(refresh_token.expires_in, Duration::days(7).num_seconds());
+    assert_ne!(access_token.jti, refresh_token.jti);
+  }
+
+  #[tokio::test]
+  async fn test_issuer_and_audience() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key").with_validation_policy(
+      crate::utils::JwtValidationPolicy::new()
+        .allow_issuer("https://auth.example.com")
+        .allow_audience("orders")
+    );
+    let token_issuer = TokenIssuer::new(Box::new(jwt_util.clone()))
+      .with_issuer("https://auth.example.com")
+      .with_audience("orders");
+
+    // act
+    let issued_token = token_issuer.issue(&auth_user(), TokenType::AccessToken).unwrap();
+
+    // assert
+    let claims: Result<AuthClaims, Error> = jwt_util.extract_claims(&issued_token.token).await;
+    assert_eq!(claims.unwrap().issuer.as_deref(), Some("https://auth.example.com"));
+  }
+
+  #[tokio::test]
+  async fn test_sign_keeps_custom_claims() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key");
+    let token_issuer = TokenIssuer::new(Box::new(jwt_util.clone()));
+    let claims = token_issuer.claims(&auth_user(), TokenType::RefreshToken)
+      .with_jti("refresh-1")
+      .with_token_version(Some(2));
+
+    // act
+    let issued_token = token_issuer.sign(claims).unwrap();
+
+    // assert
+    let claims: AuthClaims = jwt_util.extract_claims(&issued_token.token).await.unwrap();
+    assert_eq!(issued_token.jti, "refresh-1");
+    assert_eq!(issued_token.token_type, TokenType::RefreshToken);
+    assert_eq!(claims.token_version, Some(2));
+  }
+
+  #[test]
+  fn test_issued_token_serializes_for_login_response() {
+    // arrange
+    let token_issuer = TokenIssuer::new(Box::new(JwtUtil::new("some_key")));
+    let issued_token = token_issuer.issue(&auth_user(), TokenType::AccessToken).unwrap();
+
+    // act
+    let value = serde_json::to_value(&issued_token).unwrap();
+
+    // assert
+    assert_eq!(value["token"], serde_json::json!(issued_token.token));
+    assert_eq!(value["token_type"], serde_json::json!("AccessToken"));
+    assert_eq!(value["expires_in"], serde_json::json!(900));
+  }
+}
@@ -0,0 +1,352 @@
+use chrono::Duration;
+use uuid::Uuid;
+
+use crate::{
+  auth::{
+    load_principal,
+    principal_loader::IPrincipalLoader,
+    refresh_token_store::{IRefreshTokenStore, RefreshTokenState},
+    revocation_store::{self, IRevocationStore},
+    token_issuer::TokenIssuer,
+  },
+  error::SerializableError,
+  types::{
+    auth::{AuthPayload, AuthUser, TokenPair},
+    utils::jwt_util::TokenType,
+  },
+  utils::jwt_util::IJwtUtil,
+  Error,
+};
+
+/// Issues access/refresh token pairs and rotates refresh tokens. Every
+/// refresh token can be exchanged once; presenting an already used token
+/// revokes every token descended from the same login.
+pub struct TokenPairService<T: AuthPayload = AuthUser> {
+  token_issuer: TokenIssuer<T>,
+  refresh_token_store: Box<dyn IRefreshTokenStore>,
+  revocation_store: Option<Box<dyn IRevocationStore>>,
+  principal_loader: Option<Box<dyn IPrincipalLoader<T>>>,
+}
+
+impl TokenPairService {
+  pub fn new(jwt_util: Box<dyn IJwtUtil>, refresh_token_store: Box<dyn IRefreshTokenStore>) -> Self {
+    Self::for_payload(jwt_util, refresh_token_store)
+  }
+}
+
+impl<T: AuthPayload> TokenPairService<T> {
+  pub fn for_payload(
+    jwt_util: Box<dyn IJwtUtil<T>>,
+    refresh_token_store: Box<dyn IRefreshTokenStore>,
+  ) -> Self {
+    Self::from_issuer(TokenIssuer::for_payload(jwt_util), refresh_token_store)
+  }
+
+  /// Uses an already configured issuer, e.g. one with `iss`/`aud` set.
+  pub fn from_issuer(token_issuer: TokenIssuer<T>, refresh_token_store: Box<dyn IRefreshTokenStore>) -> Self {
+    Self { token_issuer, refresh_token_store, revocation_store: None, principal_loader: None }
+  }
+
+  pub fn with_lifetimes(mut self, access_token_lifetime: Duration, refresh_token_lifetime: Duration) -> Self {
+    self.token_issuer = self.token_issuer
+      .with_lifetime(TokenType::AccessToken, access_token_lifetime)
+      .with_lifetime(TokenType::RefreshToken, refresh_token_lifetime);
+
+    self
+  }
+
+  /// Stamps the user's current token version into issued tokens and refuses
+  /// to refresh tokens that have been revoked.
+  pub fn with_revocation_store(mut self, revocation_store: Box<dyn IRevocationStore>) -> Self {
+    self.revocation_store = Some(revocation_store);
+
+    self
+  }
+
+  /// Refuses to refresh tokens of users the loader no longer returns, e.g.
+  /// deactivated ones, and issues the new pair for the loaded principal so
+  /// profile changes reach it. Services that exchange their own tokens for
+  /// a pair, like `MagicLinkService::verify_link`, use it as well.
+  pub fn with_principal_loader(mut self, principal_loader: Box<dyn IPrincipalLoader<T>>) -> Self {
+    self.principal_loader = Some(principal_loader);
+
+    self
+  }
+
+  pub fn token_issuer(&self) -> &TokenIssuer<T> {
+    &self.token_issuer
+  }
+
+  pub async fn issue(&self, user: &T) -> Result<TokenPair, Error> {
+    self.issue_in_family(user, Uuid::new_v4()).await
+  }
+
+  /// `user` as the principal loader currently returns it, or `user` itself
+  /// without a loader. Fails if the user no longer exists or is deactivated.
+  pub async fn current_principal(&self, user: &T) -> Result<T, Error> {
+    match self.principal_loader.as_deref() {
+      Some(principal_loader) => load_principal(principal_loader, user.user_id()).await,
+      None => Ok(user.clone()),
+    }
+  }
+
+  pub async fn refresh(&self, refresh_token: &str) -> Result<TokenPair, Error> {
+    let claims = self.token_issuer.jwt_util().extract_claims(refresh_token).await?;
+
+    if claims.token_type != TokenType::RefreshToken {
+      return Err(unauthorized("Token is not a refresh token"));
+    }
+
+    if let Some(revocation_store) = self.revocation_store.as_deref() {
+      revocation_store::ensure_not_revoked(revocation_store, &claims).await?;
+    }
+
+    let token_id = claims.jti.as_deref()
+      .ok_or_else(|| unauthorized("Refresh token is missing a token id"))?;
+    let user = self.current_principal(&claims.user_details).await?;
+
+    match self.refresh_token_store.consume(token_id).await? {
+      RefreshTokenState::Active { family_id } => {
+        self.issue_in_family(&user, family_id).await
+      },
+      RefreshTokenState::Used { family_id } => {
+        self.refresh_token_store.revoke_family(family_id).await?;
+        Err(unauthorized("Refresh token reuse detected"))
+      },
+      RefreshTokenState::Revoked => Err(unauthorized("Refresh token has been revoked")),
+      RefreshTokenState::Unknown => Err(unauthorized("Unknown refresh token")),
+    }
+  }
+
+  async fn issue_in_family(&self, user: &T, family_id: Uuid) -> Result<TokenPair, Error> {
+    let token_version = match self.revocation_store.as_deref() {
+      Some(revocation_store) => Some(revocation_store.token_version(user.user_id()).await?),
+      None => None,
+    };
+
+    let access_token = self.token_issuer.sign(
+      self.token_issuer.claims(user, TokenType::AccessToken).with_token_version(token_version)
+    )?;
+    let refresh_token = self.token_issuer.sign(
+      self.token_issuer.claims(user, TokenType::RefreshToken).with_token_version(token_version)
+    )?;
+
+    self.refresh_token_store
+      .save(&refresh_token.jti, family_id, refresh_token.expires_at)
+      .await?;
+
+    Ok(TokenPair {
+      access_token: access_token.token,
+      access_token_expires_in: access_token.expires_at,
+      refresh_token: refresh_token.token,
+      refresh_token_expires_in: refresh_token.expires_at,
+    })
+  }
+}
+
+fn unauthorized(message: &str) -> Error {
+  Error::Unauthorized(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::{
+    auth::{
+      principal_loader::MockIPrincipalLoader,
+      refresh_token_store::{InMemoryRefreshTokenStore, MockIRefreshTokenStore},
+      revocation_store::MockIRevocationStore,
+    },
+    types::utils::jwt_util::AuthClaims,
+    utils::JwtUtil,
+  };
+
+  fn auth_user() -> AuthUser {
+    AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    }
+  }
+
+  fn service() -> TokenPairService {
+    TokenPairService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(InMemoryRefreshTokenStore::new()),
+    )
+  }
+
+  #[tokio::test]
+  async fn test_issue() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key");
+    let service = service();
+    let user = auth_user();
+
+    // act
+    let token_pair = service.issue(&user).await.unwrap();
+
+    // assert
+    let access_claims: AuthClaims = jwt_util.extract_claims(&token_pair.access_token).await.unwrap();
+    let refresh_claims: AuthClaims = jwt_util.extract_claims(&token_pair.refresh_token).await.unwrap();
+    assert_eq!(access_claims.token_type, TokenType::AccessToken);
+    assert_eq!(access_claims.user_details, user);
+    assert_eq!(access_claims.expires_in, token_pair.access_token_expires_in);
+    assert_eq!(refresh_claims.token_type, TokenType::RefreshToken);
+    assert_eq!(refresh_claims.expires_in, token_pair.refresh_token_expires_in);
+    assert!(token_pair.refresh_token_expires_in > token_pair.access_token_expires_in);
+  }
+
+  #[tokio::test]
+  async fn test_refresh_rotates_tokens() {
+    // arrange
+    let service = service();
+    let user = auth_user();
+    let token_pair = service.issue(&user).await.unwrap();
+
+    // act
+    let refreshed_pair = service.refresh(&token_pair.refresh_token).await.unwrap();
+
+    // assert
+    assert_ne!(refreshed_pair.refresh_token, token_pair.refresh_token);
+    assert!(service.refresh(&refreshed_pair.refresh_token).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_refresh_reuse_revokes_family() {
+    // arrange
+    let service = service();
+    let token_pair = service.issue(&auth_user()).await.unwrap();
+    let refreshed_pair = service.refresh(&token_pair.refresh_token).await.unwrap();
+
+    // act
+    let reuse_result = service.refresh(&token_pair.refresh_token).await;
+    let descendant_result = service.refresh(&refreshed_pair.refresh_token).await;
+
+    // assert
+    match reuse_result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Refresh token reuse detected"),
+      _ => panic!("Expected Unauthorized error"),
+    }
+    assert!(matches!(descendant_result, Err(Error::Unauthorized(_))));
+  }
+
+  #[tokio::test]
+  async fn test_refresh_rejects_access_token() {
+    // arrange
+    let mut refresh_token_store = MockIRefreshTokenStore::new();
+    refresh_token_store.expect_save().returning(|_, _, _| Ok(()));
+    refresh_token_store.expect_consume().never();
+    let service = TokenPairService::new(
+      Box::new(JwtUtil::new("some_key")),
+      Box::new(refresh_token_store),
+    );
+    let token_pair = service.issue(&auth_user()).await.unwrap();
+
+    // act
+    let refresh_result = service.refresh(&token_pair.access_token).await;
+
+    // assert
+    match refresh_result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Token is not a refresh token"),
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_refresh_unknown_token() {
+    // arrange
+    let issuing_service = service();
+    let token_pair = issuing_service.issue(&auth_user()).await.unwrap();
+
+    // act
+    let refresh_result = service().refresh(&token_pair.refresh_token).await;
+
+    // assert
+    assert!(matches!(refresh_result, Err(Error::Unauthorized(_))));
+  }
+
+  #[tokio::test]
+  async fn test_issue_stamps_token_version() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key");
+    let mut revocation_store = MockIRevocationStore::new();
+    revocation_store.expect_token_version().returning(|_| Ok(3));
+    let service = service().with_revocation_store(Box::new(revocation_store));
+
+    // act
+    let token_pair = service.issue(&auth_user()).await.unwrap();
+
+    // assert
+    let access_claims: AuthClaims = jwt_util.extract_claims(&token_pair.access_token).await.unwrap();
+    let refresh_claims: AuthClaims = jwt_util.extract_claims(&token_pair.refresh_token).await.unwrap();
+    assert_eq!(access_claims.token_version, Some(3));
+    assert_eq!(refresh_claims.token_version, Some(3));
+  }
+
+  #[tokio::test]
+  async fn test_refresh_rejects_revoked_user_tokens() {
+    // arrange
+    let token_pair = service().issue(&auth_user()).await.unwrap();
+    let mut revocation_store = MockIRevocationStore::new();
+    revocation_store.expect_is_revoked().returning(|_| Ok(false));
+    revocation_store.expect_token_version().returning(|_| Ok(1));
+    let service = service().with_revocation_store(Box::new(revocation_store));
+
+    // act
+    let refresh_result = service.refresh(&token_pair.refresh_token).await;
+
+    // assert
+    match refresh_result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "Token has been revoked"),
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_issue_from_configured_issuer() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key");
+    let token_issuer = TokenIssuer::new(Box::new(jwt_util.clone()))
+      .with_issuer("https://auth.example.com");
+    let service = TokenPairService::from_issuer(token_issuer, Box::new(InMemoryRefreshTokenStore::new()));
+
+    // act
+    let token_pair = service.issue(&auth_user()).await.unwrap();
+
+    // assert
+    let refresh_claims: AuthClaims = jwt_util.extract_claims(&token_pair.refresh_token).await.unwrap();
+    assert_eq!(refresh_claims.issuer.as_deref(), Some("https://auth.example.com"));
+    assert!(service.refresh(&token_pair.refresh_token).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_refresh_with_principal_loader() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key");
+    let (user, deactivated_user) = (auth_user(), auth_user());
+    let renamed_user = AuthUser { last_name: "Smith".to_string(), ..user.clone() };
+    let loaded_user = renamed_user.clone();
+    let mut principal_loader = MockIPrincipalLoader::new();
+    principal_loader.expect_load()
+      .returning(move |user_id| Ok((user_id == loaded_user.id).then(|| loaded_user.clone())));
+    let service = service().with_principal_loader(Box::new(principal_loader));
+    let token_pair = service.issue(&user).await.unwrap();
+    let deactivated_pair = service.issue(&deactivated_user).await.unwrap();
+
+    // act
+    let refreshed_pair = service.refresh(&token_pair.refresh_token).await.unwrap();
+    let deactivated_result = service.refresh(&deactivated_pair.refresh_token).await;
+
+    // assert
+    let access_claims: AuthClaims = jwt_util.extract_claims(&refreshed_pair.access_token).await.unwrap();
+    assert_eq!(access_claims.user_details, renamed_user);
+    match deactivated_result {
+      Err(Error::Unauthorized(error)) => assert_eq!(error.message, "User no longer exists or has been deactivated"),
+      _ => panic!("Expected Unauthorized error"),
+    }
+  }
+}
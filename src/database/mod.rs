@@ -0,0 +1,6 @@
+use sqlx::migrate::Migrator;
+
+/// Migrations for the tables used by the Postgres-backed stores in this
+/// crate. Run with `MIGRATOR.run(&pool).await`. The `users` table belongs to
+/// the application; see `PgCredentialStore` for the columns it needs.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
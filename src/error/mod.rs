@@ -5,6 +5,20 @@ pub struct SerializableError {
   pub message: String,
 }
 
+/// A rejected request that may be retried after `retry_after` seconds.
+#[derive(Debug, Serialize)]
+pub struct RetryAfterError {
+  pub message: String,
+  pub retry_after: u64,
+}
+
+/// A token lacking the OAuth2 `scopes` a request requires.
+#[derive(Debug, Serialize)]
+pub struct ScopeError {
+  pub message: String,
+  pub scopes: Vec<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub enum Error {
   DatabaseConnection(SerializableError),
@@ -13,7 +27,13 @@ pub enum Error {
   DatabaseRowMapping(SerializableError),
   JwtGenerate(SerializableError),
   JwtTokenInvalid(SerializableError),
+  JwtKeyInvalid(SerializableError),
+  JwksFetch(SerializableError),
+  IdentityProvider(SerializableError),
   Unauthorized(SerializableError),
+  Forbidden(SerializableError),
+  InsufficientScope(ScopeError),
+  TooManyRequests(RetryAfterError),
   ToStr(SerializableError),
   Unhandled(SerializableError),
 }
@@ -49,10 +69,24 @@ impl From<jsonwebtoken::errors::Error> for Error {
     };
 
     match error.kind() {
-      jsonwebtoken::errors::ErrorKind::InvalidToken { .. } => {
+      jsonwebtoken::errors::ErrorKind::InvalidToken
+        | jsonwebtoken::errors::ErrorKind::InvalidAlgorithm
+        | jsonwebtoken::errors::ErrorKind::ExpiredSignature
+        | jsonwebtoken::errors::ErrorKind::ImmatureSignature
+        | jsonwebtoken::errors::ErrorKind::InvalidIssuer
+        | jsonwebtoken::errors::ErrorKind::InvalidAudience
+        | jsonwebtoken::errors::ErrorKind::InvalidSubject
+        | jsonwebtoken::errors::ErrorKind::MissingRequiredClaim(_)
+        | jsonwebtoken::errors::ErrorKind::InvalidSignature
+        | jsonwebtoken::errors::ErrorKind::Base64(_)
+        | jsonwebtoken::errors::ErrorKind::Json(_)
+        | jsonwebtoken::errors::ErrorKind::Utf8(_) => {
         Error::JwtTokenInvalid(serializable_error)
-      }, jsonwebtoken::errors::ErrorKind::InvalidSignature => {
-        Error::JwtGenerate(serializable_error)
+      }, jsonwebtoken::errors::ErrorKind::InvalidKeyFormat
+        | jsonwebtoken::errors::ErrorKind::InvalidAlgorithmName
+        | jsonwebtoken::errors::ErrorKind::InvalidRsaKey(_)
+        | jsonwebtoken::errors::ErrorKind::InvalidEcdsaKey => {
+        Error::JwtKeyInvalid(serializable_error)
       }, _ => Error::Unhandled(serializable_error),
     }
   }
@@ -129,7 +163,7 @@ use super::*;
   }
 
   #[test]
-  fn test_from_jwt_generate_error() {
+  fn test_from_jwt_invalid_signature_error() {
     // arrange
     let jwt_error = jsonwebtoken::errors::Error::from(
       jsonwebtoken::errors::ErrorKind::InvalidSignature
@@ -140,11 +174,56 @@ use super::*;
     let error = Error::from(jwt_error);
 
     // assert
-    if let Error::JwtGenerate(jwt_generate_error) = error {
-      assert!(matches!(jwt_generate_error, SerializableError { .. }));
-      assert_eq!(jwt_generate_error.message, jwt_error_string);
+    if let Error::JwtTokenInvalid(jwt_token_invalid_error) = error {
+      assert!(matches!(jwt_token_invalid_error, SerializableError { .. }));
+      assert_eq!(jwt_token_invalid_error.message, jwt_error_string);
+    } else {
+      panic!("Expected JwtTokenInvalid error");
+    }
+  }
+
+  #[test]
+  fn test_from_jwt_expired_signature_error() {
+    // arrange
+    let jwt_error = jsonwebtoken::errors::Error::from(
+      jsonwebtoken::errors::ErrorKind::ExpiredSignature
+    );
+
+    // act
+    let error = Error::from(jwt_error);
+
+    // assert
+    assert!(matches!(error, Error::JwtTokenInvalid(_)));
+  }
+
+  #[test]
+  fn test_from_jwt_malformed_token_error() {
+    // arrange
+    let jwt_error = jsonwebtoken::decode_header("x.y.z").unwrap_err();
+
+    // act
+    let error = Error::from(jwt_error);
+
+    // assert
+    assert!(matches!(error, Error::JwtTokenInvalid(_)));
+  }
+
+  #[test]
+  fn test_from_jwt_key_error() {
+    // arrange
+    let jwt_error = jsonwebtoken::errors::Error::from(
+      jsonwebtoken::errors::ErrorKind::InvalidKeyFormat
+    );
+    let jwt_error_string = jwt_error.to_string();
+
+    // act
+    let error = Error::from(jwt_error);
+
+    // assert
+    if let Error::JwtKeyInvalid(jwt_key_error) = error {
+      assert_eq!(jwt_key_error.message, jwt_error_string);
     } else {
-      panic!("Expected JWTGenerate error");
+      panic!("Expected JwtKeyInvalid error");
     }
   }
 
@@ -1,4 +1,7 @@
-use lambda_http::{http::StatusCode, Response};
+use lambda_http::{
+  http::{header::{RETRY_AFTER, WWW_AUTHENTICATE}, HeaderValue, StatusCode},
+  Response,
+};
 use serde::Serialize;
 use serde_json::json;
 
@@ -11,8 +14,20 @@ pub trait IApiResponse {
 
   fn unauthorized() -> Response<String>;
 
+  fn forbidden() -> Response<String>;
+
+  /// 403 for a token lacking `scopes`, e.g. for `Error::InsufficientScope`,
+  /// with the RFC 6750 challenge naming the scopes required. Scopes that
+  /// aren't valid RFC 6750 scope tokens, e.g. containing `"` or `\`, are
+  /// left out of the challenge.
+  fn insufficient_scope(scopes: &[&str]) -> Response<String>;
+
   fn not_found<T: Serialize>(data: T) -> Response<String>;
 
+  /// 429 telling the client to wait `retry_after` seconds, e.g. for
+  /// `Error::TooManyRequests`.
+  fn too_many_requests(retry_after: u64) -> Response<String>;
+
   fn unprocessable_entity<T: Serialize>(data: T) -> Response<String>;
 
   fn server_error<T: Serialize>(data: T) -> Response<String>;
@@ -33,6 +48,14 @@ impl ApiResponse {
       .body(serde_json::to_string(&data).unwrap_or_default())
       .unwrap()
   }
+
+  fn with_challenge(mut response: Response<String>, challenge: &str) -> Response<String> {
+    if let Ok(challenge) = HeaderValue::from_str(challenge) {
+      response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+    }
+
+    response
+  }
 }
 
 impl IApiResponse for ApiResponse {
@@ -49,15 +72,43 @@ impl IApiResponse for ApiResponse {
   }
 
   fn unauthorized() -> Response<String> {
-    Self::json_response(json!({
+    Self::with_challenge(Self::json_response(json!({
       "message": "Unauthorized."
-    }), StatusCode::UNAUTHORIZED)
+    }), StatusCode::UNAUTHORIZED), "Bearer")
+  }
+
+  fn forbidden() -> Response<String> {
+    Self::json_response(json!({
+      "message": "Forbidden."
+    }), StatusCode::FORBIDDEN)
+  }
+
+  fn insufficient_scope(scopes: &[&str]) -> Response<String> {
+    let scopes: Vec<&str> = scopes.iter().copied().filter(|scope| is_scope_token(scope)).collect();
+    let challenge = if scopes.is_empty() {
+      r#"Bearer error="insufficient_scope""#.to_string()
+    } else {
+      format!(r#"Bearer error="insufficient_scope", scope="{}""#, scopes.join(" "))
+    };
+
+    Self::with_challenge(Self::json_response(json!({
+      "message": "Forbidden."
+    }), StatusCode::FORBIDDEN), &challenge)
   }
 
   fn not_found<T: Serialize>(data: T) -> Response<String> {
     Self::json_response(data, StatusCode::NOT_FOUND)
   }
 
+  fn too_many_requests(retry_after: u64) -> Response<String> {
+    let mut response = Self::json_response(json!({
+      "message": "Too many requests."
+    }), StatusCode::TOO_MANY_REQUESTS);
+    response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+
+    response
+  }
+
   fn unprocessable_entity<T: Serialize>(data: T) -> Response<String> {
     Self::json_response(data, StatusCode::UNPROCESSABLE_ENTITY)
   }
@@ -71,6 +122,11 @@ impl IApiResponse for ApiResponse {
   }
 }
 
+/// RFC 6750 scope-token: printable ASCII except space, `"` and `\`.
+fn is_scope_token(scope: &str) -> bool {
+  !scope.is_empty() && scope.bytes().all(|byte| matches!(byte, 0x21 | 0x23..=0x5B | 0x5D..=0x7E))
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -135,6 +191,57 @@ mod tests {
     assert_eq!(deserialized_body, data);
   }
 
+  #[test]
+  fn test_forbidden() {
+    let response = ApiResponse::forbidden();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+      response.headers().get("Content-Type").unwrap(),
+      "application/json"
+    );
+    assert!(response.headers().get("WWW-Authenticate").is_none());
+    let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+    assert_eq!(body["message"], "Forbidden.");
+  }
+
+  #[test]
+  fn test_unauthorized() {
+    let response = ApiResponse::unauthorized();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers().get("WWW-Authenticate").unwrap(), "Bearer");
+  }
+
+  #[test]
+  fn test_insufficient_scope() {
+    let response = ApiResponse::insufficient_scope(&["orders:read", "orders:write"]);
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+      response.headers().get("WWW-Authenticate").unwrap(),
+      r#"Bearer error="insufficient_scope", scope="orders:read orders:write""#
+    );
+  }
+
+  #[test]
+  fn test_insufficient_scope_leaves_out_invalid_scopes() {
+    let response = ApiResponse::insufficient_scope(&[r#"orders:read", error="invalid_token"#, r"orders\write", "orders:list"]);
+    assert_eq!(
+      response.headers().get("WWW-Authenticate").unwrap(),
+      r#"Bearer error="insufficient_scope", scope="orders:list""#
+    );
+
+    let response = ApiResponse::insufficient_scope(&["orders read"]);
+    assert_eq!(response.headers().get("WWW-Authenticate").unwrap(), r#"Bearer error="insufficient_scope""#);
+  }
+
+  #[test]
+  fn test_too_many_requests() {
+    let response = ApiResponse::too_many_requests(120);
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers().get("Retry-After").unwrap(), "120");
+    let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+    assert_eq!(body["message"], "Too many requests.");
+  }
+
   #[test]
   fn test_unprocessable_entity() {
     let data = SampleData {
@@ -0,0 +1,19 @@
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+
+use crate::types::auth::AuthUser;
+
+/// The principal carried in `AuthClaims::user_details`. `AuthUser` is the
+/// default; services that need tenant ids, roles or other fields in the token
+/// implement this for their own type.
+pub trait AuthPayload: Serialize + DeserializeOwned + Clone + Send + Sync + 'static {
+  /// Stable user id, used as the token subject and to key per-user state
+  /// such as revocation versions.
+  fn user_id(&self) -> Uuid;
+}
+
+impl AuthPayload for AuthUser {
+  fn user_id(&self) -> Uuid {
+    self.id
+  }
+}
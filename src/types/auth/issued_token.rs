@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::utils::jwt_util::TokenType;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IssuedToken {
+  pub token: String,
+  pub token_type: TokenType,
+  pub jti: String,
+  pub issued_at: usize,
+  pub expires_at: usize,
+  /// Lifetime in seconds, as in an OAuth2 token response.
+  pub expires_in: i64,
+}
@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::auth::{AuthUser, TokenPair};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LoginResult {
+  pub user: AuthUser,
+  pub token_pair: TokenPair,
+}
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::auth::{AuthUser, IssuedToken, LoginResult};
+
+/// Outcome of a magic link or one-time code login. Users enrolled in TOTP
+/// get an `MfaPending` token to exchange with `MfaService::complete`
+/// instead of a token pair.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum MagicLinkLogin {
+  Complete(LoginResult),
+  MfaRequired { user: AuthUser, mfa_token: IssuedToken },
+}
@@ -1,3 +1,15 @@
+pub mod auth_payload;
 pub mod auth_user;
+pub mod issued_token;
+pub mod login_result;
+pub mod magic_link_login;
+pub mod token_pair;
+pub mod totp_enrollment;
 
+pub use auth_payload::AuthPayload;
 pub use auth_user::AuthUser;
+pub use issued_token::IssuedToken;
+pub use login_result::LoginResult;
+pub use magic_link_login::MagicLinkLogin;
+pub use token_pair::TokenPair;
+pub use totp_enrollment::TotpEnrollment;
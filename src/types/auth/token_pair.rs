@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenPair {
+  pub access_token: String,
+  pub access_token_expires_in: usize,
+  pub refresh_token: String,
+  pub refresh_token_expires_in: usize,
+}
@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TotpEnrollment {
+  pub secret: String,
+  pub provisioning_uri: String,
+}
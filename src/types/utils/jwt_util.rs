@@ -1,21 +1,210 @@
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
 
-use crate::types::auth::AuthUser;
+use crate::types::auth::{AuthPayload, AuthUser};
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TokenType {
   AccessToken,
   RefreshToken,
+  /// Proves the password step of a login; only exchangeable for a token
+  /// pair together with a second factor.
+  MfaPending,
+  /// Single-use login token sent by email, exchangeable for a token pair.
+  MagicLink,
+  /// Short-lived access token for acting as another user. The real user is
+  /// named in the `act` claim.
+  Impersonation,
+}
+
+/// RFC 8693 `act` claim: the party acting on behalf of the token's subject.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Actor<T = AuthUser> {
+  #[serde(rename = "sub")]
+  pub subject: String,
+  pub user_details: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+  Single(String),
+  Multiple(Vec<String>),
+}
+
+impl Audience {
+  pub fn contains(&self, audience: &str) -> bool {
+    match self {
+      Audience::Single(value) => value == audience,
+      Audience::Multiple(values) => values.iter().any(|value| value == audience),
+    }
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
-pub struct AuthClaims {
+pub struct AuthClaims<T = AuthUser> {
   #[serde(rename = "sub")]
   pub subject: String,
   #[serde(rename = "exp")]
   pub expires_in: usize,
-  pub user_details: AuthUser,
+  pub user_details: T,
   pub token_type: TokenType,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub jti: Option<String>,
+  #[serde(rename = "ver", default, skip_serializing_if = "Option::is_none")]
+  pub token_version: Option<i32>,
+  #[serde(rename = "iss", default, skip_serializing_if = "Option::is_none")]
+  pub issuer: Option<String>,
+  #[serde(rename = "aud", default, skip_serializing_if = "Option::is_none")]
+  pub audience: Option<Audience>,
+  #[serde(rename = "iat", default, skip_serializing_if = "Option::is_none")]
+  pub issued_at: Option<usize>,
+  #[serde(rename = "nbf", default, skip_serializing_if = "Option::is_none")]
+  pub not_before: Option<usize>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub roles: Vec<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub permissions: Vec<String>,
+  /// Any other claims in the token, e.g. `scope` from an external issuer.
+  #[serde(flatten)]
+  pub extra: Map<String, Value>,
+}
+
+impl<T: AuthPayload> AuthClaims<T> {
+  pub fn new(user_details: T, token_type: TokenType, expires_in: usize) -> Self {
+    Self {
+      subject: user_details.user_id().to_string(),
+      expires_in,
+      user_details,
+      token_type,
+      jti: None,
+      token_version: None,
+      issuer: None,
+      audience: None,
+      issued_at: None,
+      not_before: None,
+      roles: Vec::new(),
+      permissions: Vec::new(),
+      extra: Map::new(),
+    }
+  }
+
+  pub fn with_issuer(mut self, issuer: &str) -> Self {
+    self.issuer = Some(issuer.to_string());
+
+    self
+  }
+
+  pub fn with_audience(mut self, audience: &str) -> Self {
+    self.audience = Some(Audience::Single(audience.to_string()));
+
+    self
+  }
+
+  pub fn with_audiences(mut self, audiences: &[&str]) -> Self {
+    self.audience = Some(Audience::Multiple(
+      audiences.iter().map(|audience| audience.to_string()).collect()
+    ));
+
+    self
+  }
+
+  /// Sets `iat` and `nbf` to the current time.
+  pub fn issued_now(mut self) -> Self {
+    let now = Utc::now().timestamp() as usize;
+    self.issued_at = Some(now);
+    self.not_before = Some(now);
+
+    self
+  }
+
+  pub fn with_not_before(mut self, not_before: usize) -> Self {
+    self.not_before = Some(not_before);
+
+    self
+  }
+
+  pub fn with_jti(mut self, jti: &str) -> Self {
+    self.jti = Some(jti.to_string());
+
+    self
+  }
+
+  pub fn with_random_jti(self) -> Self {
+    self.with_jti(&Uuid::new_v4().to_string())
+  }
+
+  pub fn with_token_version(mut self, token_version: Option<i32>) -> Self {
+    self.token_version = token_version;
+
+    self
+  }
+
+  pub fn with_roles(mut self, roles: &[&str]) -> Self {
+    self.roles = roles.iter().map(|role| role.to_string()).collect();
+
+    self
+  }
+
+  pub fn with_permissions(mut self, permissions: &[&str]) -> Self {
+    self.permissions = permissions.iter().map(|permission| permission.to_string()).collect();
+
+    self
+  }
+
+  /// Stores `scopes` as the space-delimited OAuth2 `scope` claim.
+  pub fn with_scopes(self, scopes: &[&str]) -> Self {
+    self.with_claim("scope", scopes.join(" "))
+  }
+
+  /// Scopes from the `scope` and `scp` claims, either space-delimited
+  /// strings or arrays of strings.
+  pub fn scopes(&self) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+
+    for claim in ["scope", "scp"] {
+      let values: Vec<&str> = match self.extra.get(claim) {
+        Some(Value::String(value)) => value.split_whitespace().collect(),
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+      };
+
+      for value in values {
+        if !scopes.iter().any(|scope| scope == value) {
+          scopes.push(value.to_string());
+        }
+      }
+    }
+
+    scopes
+  }
+
+  pub fn has_scope(&self, scope: &str) -> bool {
+    self.scopes().iter().any(|granted| granted == scope)
+  }
+
+  pub fn with_claim(mut self, name: &str, value: impl Into<Value>) -> Self {
+    self.extra.insert(name.to_string(), value.into());
+
+    self
+  }
+
+  pub fn claim(&self, name: &str) -> Option<&Value> {
+    self.extra.get(name)
+  }
+
+  /// Names `actor` in the `act` claim as acting on behalf of the subject.
+  pub fn with_actor(self, actor: &T) -> Self {
+    let actor = Actor { subject: actor.user_id().to_string(), user_details: actor.clone() };
+
+    self.with_claim("act", serde_json::to_value(actor).unwrap_or_default())
+  }
+
+  pub fn actor(&self) -> Option<Actor<T>> {
+    serde_json::from_value(self.claim("act")?.clone()).ok()
+  }
 }
 
 #[cfg(test)]
@@ -41,7 +230,9 @@ mod tests {
     let token_type = TokenType::AccessToken;
 
     let claims = AuthClaims {
-      subject, expires_in, user_details: auth_user, token_type,
+      subject, expires_in, user_details: auth_user, token_type, jti: None, token_version: None,
+      issuer: None, audience: None, issued_at: None, not_before: None, roles: Vec::new(),
+      permissions: Vec::new(), extra: Default::default(),
     };
 
     assert_eq!(claims.subject, user_id.to_string());
@@ -67,7 +258,9 @@ mod tests {
     let token_type = TokenType::RefreshToken;
 
     let claims = AuthClaims {
-      subject, expires_in, user_details: auth_user, token_type,
+      subject, expires_in, user_details: auth_user, token_type, jti: None, token_version: None,
+      issuer: None, audience: None, issued_at: None, not_before: None, roles: Vec::new(),
+      permissions: Vec::new(), extra: Default::default(),
     };
 
     assert_eq!(claims.subject, user_id.to_string());
@@ -76,4 +269,131 @@ mod tests {
     assert_eq!(claims.user_details.first_name, "Jane");
     assert_eq!(claims.token_type, TokenType::RefreshToken);
   }
+
+  #[test]
+  fn can_build_auth_claims_with_registered_claims() {
+    let user_id = Uuid::new_v4();
+    let auth_user = AuthUser {
+      id: user_id,
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+
+    let claims = AuthClaims::new(auth_user, TokenType::AccessToken, 3600)
+      .with_issuer("https://auth.example.com")
+      .with_audiences(&["orders", "billing"])
+      .issued_now()
+      .with_jti("token-1");
+
+    assert_eq!(claims.subject, user_id.to_string());
+    assert_eq!(claims.issuer, Some("https://auth.example.com".to_string()));
+    assert!(claims.audience.as_ref().unwrap().contains("billing"));
+    assert!(claims.issued_at.is_some());
+    assert_eq!(claims.not_before, claims.issued_at);
+    assert_eq!(claims.jti, Some("token-1".to_string()));
+  }
+
+  #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+  struct TenantUser {
+    id: Uuid,
+    tenant_id: String,
+    roles: Vec<String>,
+  }
+
+  impl AuthPayload for TenantUser {
+    fn user_id(&self) -> Uuid {
+      self.id
+    }
+  }
+
+  #[test]
+  fn can_use_custom_payload_with_extra_claims() {
+    let user = TenantUser {
+      id: Uuid::new_v4(),
+      tenant_id: "acme".to_string(),
+      roles: vec!["admin".to_string()],
+    };
+
+    let claims = AuthClaims::new(user.clone(), TokenType::AccessToken, 3600)
+      .with_claim("feature_flags", serde_json::json!(["beta"]));
+    let value = serde_json::to_value(&claims).unwrap();
+    let deserialized: AuthClaims<TenantUser> = serde_json::from_value(value.clone()).unwrap();
+
+    assert_eq!(value["sub"], user.id.to_string());
+    assert_eq!(value["user_details"]["tenant_id"], "acme");
+    assert_eq!(value["feature_flags"][0], "beta");
+    assert_eq!(deserialized, claims);
+    assert_eq!(deserialized.claim("feature_flags"), Some(&serde_json::json!(["beta"])));
+  }
+
+  #[test]
+  fn can_deserialize_single_and_multiple_audiences() {
+    let single: Audience = serde_json::from_str(r#""orders""#).unwrap();
+    let multiple: Audience = serde_json::from_str(r#"["orders", "billing"]"#).unwrap();
+
+    assert_eq!(single, Audience::Single("orders".to_string()));
+    assert!(multiple.contains("billing"));
+    assert!(!multiple.contains("shipping"));
+  }
+
+  #[test]
+  fn can_parse_scopes_from_scope_and_scp_claims() {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+    let value = serde_json::json!({
+      "sub": auth_user.id.to_string(),
+      "exp": 3600,
+      "user_details": auth_user,
+      "token_type": "AccessToken",
+      "scope": "orders:read  orders:write",
+      "scp": ["orders:write", "invoices:read"],
+    });
+
+    let claims: AuthClaims = serde_json::from_value(value).unwrap();
+
+    assert_eq!(claims.scopes(), vec!["orders:read", "orders:write", "invoices:read"]);
+    assert!(claims.has_scope("invoices:read"));
+    assert!(!claims.has_scope("invoices:write"));
+  }
+
+  #[test]
+  fn can_build_auth_claims_with_scopes() {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+
+    let claims = AuthClaims::new(auth_user, TokenType::AccessToken, 3600)
+      .with_scopes(&["orders:read", "orders:write"]);
+
+    assert_eq!(claims.claim("scope"), Some(&serde_json::json!("orders:read orders:write")));
+    assert_eq!(claims.scopes(), vec!["orders:read", "orders:write"]);
+  }
+
+  #[test]
+  fn can_build_auth_claims_with_actor() {
+    let user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+    let actor = AuthUser { id: Uuid::new_v4(), email: "support@example.com".to_string(), ..user.clone() };
+
+    let claims = AuthClaims::new(user, TokenType::Impersonation, 3600).with_actor(&actor);
+
+    assert_eq!(claims.claim("act").and_then(|act| act.get("sub")), Some(&serde_json::json!(actor.id.to_string())));
+    assert_eq!(claims.actor(), Some(Actor { subject: actor.id.to_string(), user_details: actor }));
+  }
 }
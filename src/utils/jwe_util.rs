@@ -0,0 +1,575 @@
+use aes_gcm::{
+  aead::{rand_core::RngCore, Aead, OsRng, Payload},
+  Aes128Gcm, Aes256Gcm, KeyInit, Nonce,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rsa::{
+  pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+  pkcs8::{DecodePrivateKey, DecodePublicKey},
+  Oaep, RsaPrivateKey, RsaPublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  error::SerializableError,
+  types::{auth::AuthPayload, utils::jwt_util::AuthClaims},
+  utils::{jwt_util::{IJwtUtil, JwtUtil}, jwt_validation_policy::JwtValidationPolicy},
+  Error,
+};
+
+/// JWE `alg`: how the content encryption key reaches the recipient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JweAlgorithm {
+  Direct,
+  RsaOaep,
+  RsaOaep256,
+}
+
+impl JweAlgorithm {
+  fn name(self) -> &'static str {
+    match self {
+      JweAlgorithm::Direct => "dir",
+      JweAlgorithm::RsaOaep => "RSA-OAEP",
+      JweAlgorithm::RsaOaep256 => "RSA-OAEP-256",
+    }
+  }
+}
+
+/// JWE `enc`: the content encryption algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JweEncryption {
+  A128Gcm,
+  A256Gcm,
+}
+
+impl JweEncryption {
+  fn name(self) -> &'static str {
+    match self {
+      JweEncryption::A128Gcm => "A128GCM",
+      JweEncryption::A256Gcm => "A256GCM",
+    }
+  }
+
+  fn key_length(self) -> usize {
+    match self {
+      JweEncryption::A128Gcm => 16,
+      JweEncryption::A256Gcm => 32,
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JweHeader {
+  alg: String,
+  enc: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  kid: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  cty: Option<String>,
+}
+
+#[derive(Clone)]
+enum JweKey {
+  Direct(Vec<u8>),
+  Rsa { public_key: Box<RsaPublicKey>, private_key: Option<Box<RsaPrivateKey>> },
+}
+
+/// `IJwtUtil` producing compact JWE (RFC 7516), so claims can't be read by
+/// whoever holds the token.
+///
+/// With RSA key wrapping anyone holding the public key can encrypt, so RSA
+/// keys need `with_signer` to nest a signed JWT inside the encryption. Only
+/// `dir` keys accept unsigned claims.
+#[derive(Clone)]
+pub struct JweUtil {
+  algorithm: JweAlgorithm,
+  encryption: JweEncryption,
+  key: JweKey,
+  key_id: Option<String>,
+  signer: Option<JwtUtil>,
+  validation_policy: JwtValidationPolicy,
+}
+
+impl JweUtil {
+  /// `dir` key agreement. A 16 byte key selects A128GCM, a 32 byte key
+  /// A256GCM.
+  pub fn direct(key: &[u8]) -> Result<Self, Error> {
+    let encryption = match key.len() {
+      16 => JweEncryption::A128Gcm,
+      32 => JweEncryption::A256Gcm,
+      length => return Err(invalid_key_error(format!(
+        "Direct encryption keys must be 16 or 32 bytes, got {}", length
+      ))),
+    };
+
+    Ok(Self::with_key(JweAlgorithm::Direct, encryption, JweKey::Direct(key.to_vec())))
+  }
+
+  /// RSA-OAEP-256 + A256GCM with a PKCS#8 or PKCS#1 private key.
+  pub fn from_rsa_pem(private_key: &[u8]) -> Result<Self, Error> {
+    let pem = std::str::from_utf8(private_key).map_err(|error| invalid_key_error(error.to_string()))?;
+    let private_key = RsaPrivateKey::from_pkcs8_pem(pem)
+      .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+      .map_err(|error| invalid_key_error(error.to_string()))?;
+
+    Ok(Self::with_key(JweAlgorithm::RsaOaep256, JweEncryption::A256Gcm, JweKey::Rsa {
+      public_key: Box::new(RsaPublicKey::from(&private_key)),
+      private_key: Some(Box::new(private_key)),
+    }))
+  }
+
+  /// Encrypt-only instance for services that issue tokens they can't read.
+  pub fn from_rsa_public_pem(public_key: &[u8]) -> Result<Self, Error> {
+    let pem = std::str::from_utf8(public_key).map_err(|error| invalid_key_error(error.to_string()))?;
+    let public_key = RsaPublicKey::from_public_key_pem(pem)
+      .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+      .map_err(|error| invalid_key_error(error.to_string()))?;
+
+    Ok(Self::with_key(JweAlgorithm::RsaOaep256, JweEncryption::A256Gcm, JweKey::Rsa {
+      public_key: Box::new(public_key),
+      private_key: None,
+    }))
+  }
+
+  fn with_key(algorithm: JweAlgorithm, encryption: JweEncryption, key: JweKey) -> Self {
+    Self {
+      algorithm,
+      encryption,
+      key,
+      key_id: None,
+      signer: None,
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  /// Switches between RSA-OAEP and RSA-OAEP-256. `dir` keys can't change
+  /// algorithm.
+  pub fn with_algorithm(mut self, algorithm: JweAlgorithm) -> Result<Self, Error> {
+    let compatible = matches!(
+      (&self.key, algorithm),
+      (JweKey::Direct(_), JweAlgorithm::Direct)
+        | (JweKey::Rsa { .. }, JweAlgorithm::RsaOaep | JweAlgorithm::RsaOaep256)
+    );
+
+    if !compatible {
+      return Err(invalid_key_error(format!(
+        "Algorithm {} doesn't match the configured key", algorithm.name()
+      )));
+    }
+
+    self.algorithm = algorithm;
+
+    Ok(self)
+  }
+
+  /// Content encryption for RSA key wrapping. With `dir` it is fixed by the
+  /// key length.
+  pub fn with_encryption(mut self, encryption: JweEncryption) -> Result<Self, Error> {
+    if let JweKey::Direct(key) = &self.key
+      && key.len() != encryption.key_length() {
+      return Err(invalid_key_error(format!(
+        "Encryption {} doesn't match the configured key", encryption.name()
+      )));
+    }
+
+    self.encryption = encryption;
+
+    Ok(self)
+  }
+
+  pub fn with_key_id(mut self, key_id: &str) -> Self {
+    self.key_id = Some(key_id.to_string());
+
+    self
+  }
+
+  /// Signs claims with `signer` and encrypts the resulting JWT (`cty: JWT`).
+  /// Tokens without a nested JWT are then rejected. Required for RSA keys.
+  pub fn with_signer(mut self, signer: JwtUtil) -> Self {
+    self.signer = Some(signer);
+
+    self
+  }
+
+  /// Policy for tokens without a nested JWT; nested tokens are checked by
+  /// the signer's own policy.
+  pub fn with_validation_policy(mut self, validation_policy: JwtValidationPolicy) -> Self {
+    self.validation_policy = validation_policy;
+
+    self
+  }
+
+  pub fn key_id(&self) -> Option<&str> {
+    self.key_id.as_deref()
+  }
+
+  pub fn can_decrypt(&self) -> bool {
+    match &self.key {
+      JweKey::Direct(_) => true,
+      JweKey::Rsa { private_key, .. } => private_key.is_some(),
+    }
+  }
+
+  fn ensure_signer_for_rsa(&self) -> Result<(), Error> {
+    if matches!(self.key, JweKey::Rsa { .. }) && self.signer.is_none() {
+      return Err(invalid_key_error("RSA encrypted tokens must be signed, see with_signer".to_string()));
+    }
+
+    Ok(())
+  }
+
+  fn encrypt(&self, plaintext: &[u8], content_type: Option<&str>) -> Result<String, Error> {
+    let header = JweHeader {
+      alg: self.algorithm.name().to_string(),
+      enc: self.encryption.name().to_string(),
+      kid: self.key_id.clone(),
+      cty: content_type.map(str::to_string),
+    };
+    let encoded_header = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).map_err(generate_error)?);
+
+    let (content_key, encrypted_key) = match &self.key {
+      JweKey::Direct(key) => (key.clone(), Vec::new()),
+      JweKey::Rsa { public_key, .. } => {
+        let mut content_key = vec![0u8; self.encryption.key_length()];
+        OsRng.fill_bytes(&mut content_key);
+        let encrypted_key = match self.algorithm {
+          JweAlgorithm::RsaOaep => public_key.encrypt(&mut OsRng, Oaep::new::<sha1::Sha1>(), &content_key),
+          _ => public_key.encrypt(&mut OsRng, Oaep::new::<sha2::Sha256>(), &content_key),
+        }.map_err(generate_error)?;
+
+        (content_key, encrypted_key)
+      },
+    };
+
+    let mut iv = [0u8; 12];
+    OsRng.fill_bytes(&mut iv);
+    let payload = Payload { msg: plaintext, aad: encoded_header.as_bytes() };
+    let mut ciphertext = match self.encryption {
+      JweEncryption::A128Gcm => Aes128Gcm::new_from_slice(&content_key)
+        .map_err(generate_error)?
+        .encrypt(Nonce::from_slice(&iv), payload),
+      JweEncryption::A256Gcm => Aes256Gcm::new_from_slice(&content_key)
+        .map_err(generate_error)?
+        .encrypt(Nonce::from_slice(&iv), payload),
+    }.map_err(generate_error)?;
+    let tag = ciphertext.split_off(ciphertext.len() - 16);
+
+    Ok([
+      encoded_header,
+      URL_SAFE_NO_PAD.encode(encrypted_key),
+      URL_SAFE_NO_PAD.encode(iv),
+      URL_SAFE_NO_PAD.encode(ciphertext),
+      URL_SAFE_NO_PAD.encode(tag),
+    ].join("."))
+  }
+
+  fn decrypt(&self, token: &str) -> Result<(JweHeader, Vec<u8>), Error> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let [encoded_header, encrypted_key, iv, ciphertext, tag] = parts[..] else {
+      return Err(invalid_token("Invalid token"));
+    };
+
+    let header: JweHeader = serde_json::from_slice(&decode_part(encoded_header)?)
+      .map_err(|_| invalid_token("Invalid token"))?;
+
+    if header.alg != self.algorithm.name() || header.enc != self.encryption.name() {
+      return Err(invalid_token("InvalidAlgorithm"));
+    }
+
+    let encrypted_key = decode_part(encrypted_key)?;
+    let iv = decode_part(iv)?;
+    let mut ciphertext = decode_part(ciphertext)?;
+    ciphertext.extend(decode_part(tag)?);
+
+    if iv.len() != 12 {
+      return Err(invalid_token("Invalid token"));
+    }
+
+    let content_key = match &self.key {
+      JweKey::Direct(key) if encrypted_key.is_empty() => key.clone(),
+      JweKey::Direct(_) => return Err(invalid_token("Invalid token")),
+      JweKey::Rsa { private_key: None, .. } => {
+        return Err(invalid_key_error("JweUtil was created without a decryption key".to_string()));
+      },
+      JweKey::Rsa { private_key: Some(private_key), .. } => match self.algorithm {
+        JweAlgorithm::RsaOaep => private_key.decrypt(Oaep::new::<sha1::Sha1>(), &encrypted_key),
+        _ => private_key.decrypt(Oaep::new::<sha2::Sha256>(), &encrypted_key),
+      }.map_err(|_| invalid_token("Token could not be decrypted"))?,
+    };
+
+    let payload = Payload { msg: &ciphertext, aad: encoded_header.as_bytes() };
+    let plaintext = match self.encryption {
+      JweEncryption::A128Gcm => Aes128Gcm::new_from_slice(&content_key)
+        .map_err(|_| invalid_token("Token could not be decrypted"))?
+        .decrypt(Nonce::from_slice(&iv), payload),
+      JweEncryption::A256Gcm => Aes256Gcm::new_from_slice(&content_key)
+        .map_err(|_| invalid_token("Token could not be decrypted"))?
+        .decrypt(Nonce::from_slice(&iv), payload),
+    }.map_err(|_| invalid_token("Token could not be decrypted"))?;
+
+    Ok((header, plaintext))
+  }
+}
+
+#[async_trait]
+impl<T: AuthPayload> IJwtUtil<T> for JweUtil {
+  fn generate_token(&self, claims: &AuthClaims<T>) -> Result<String, Error> {
+    self.ensure_signer_for_rsa()?;
+
+    match &self.signer {
+      Some(signer) => {
+        let signed_token = signer.generate_token(claims)?;
+        self.encrypt(signed_token.as_bytes(), Some("JWT"))
+      },
+      None => self.encrypt(&serde_json::to_vec(claims).map_err(generate_error)?, None),
+    }
+  }
+
+  async fn extract_claims(&self, token: &str) -> Result<AuthClaims<T>, Error> {
+    self.ensure_signer_for_rsa()?;
+
+    let (header, plaintext) = self.decrypt(token)?;
+    let nested = header.cty.as_deref().is_some_and(|content_type| content_type.eq_ignore_ascii_case("JWT"));
+
+    match (&self.key, &self.signer, nested) {
+      (_, Some(signer), true) => {
+        let signed_token = String::from_utf8(plaintext).map_err(|_| invalid_token("Invalid token"))?;
+        signer.extract_claims(&signed_token).await
+      },
+      (JweKey::Direct(_), None, false) => {
+        let claims: AuthClaims<T> = serde_json::from_slice(&plaintext)
+          .map_err(|_| invalid_token("Invalid token"))?;
+        self.validation_policy.validate_claims(&claims)?;
+
+        Ok(claims)
+      },
+      _ => Err(invalid_token("Token is not signed as expected")),
+    }
+  }
+}
+
+fn decode_part(part: &str) -> Result<Vec<u8>, Error> {
+  URL_SAFE_NO_PAD.decode(part).map_err(|_| invalid_token("Invalid token"))
+}
+
+fn invalid_token(message: &str) -> Error {
+  Error::JwtTokenInvalid(SerializableError { message: message.to_string() })
+}
+
+fn invalid_key_error(message: String) -> Error {
+  Error::JwtKeyInvalid(SerializableError { message })
+}
+
+fn generate_error(error: impl ToString) -> Error {
+  Error::JwtGenerate(SerializableError { message: error.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::{Duration, Utc};
+  use uuid::Uuid;
+
+  use crate::{
+    auth::{Auth, IAuth},
+    types::{auth::AuthUser, utils::jwt_util::TokenType},
+  };
+
+  const RSA_PRIVATE_PEM: &[u8] = include_bytes!("../../tests/fixtures/keys/rsa_private.pem");
+  const RSA_PUBLIC_PEM: &[u8] = include_bytes!("../../tests/fixtures/keys/rsa_public.pem");
+
+  fn sample_claims() -> AuthClaims {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+    let expires_in = (Utc::now() + Duration::hours(1)).timestamp() as usize;
+
+    AuthClaims::new(auth_user, TokenType::AccessToken, expires_in)
+  }
+
+  #[tokio::test]
+  async fn test_direct_round_trip() {
+    // arrange
+    let jwe_util = JweUtil::direct(&[7u8; 32]).unwrap();
+    let claims = sample_claims();
+
+    // act
+    let token = jwe_util.generate_token(&claims).unwrap();
+    let extracted_claims: AuthClaims = jwe_util.extract_claims(&token).await.unwrap();
+
+    // assert
+    assert_eq!(token.split('.').count(), 5);
+    assert!(!token.contains(&URL_SAFE_NO_PAD.encode("john.doe@example.com")));
+    assert_eq!(extracted_claims.user_details, claims.user_details);
+    let header: JweHeader = serde_json::from_slice(&decode_part(token.split('.').next().unwrap()).unwrap()).unwrap();
+    assert_eq!(header.alg, "dir");
+    assert_eq!(header.enc, "A256GCM");
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_from_tokens_of_another_implementation() {
+    // arrange
+    // The fixtures were encrypted with Python's `cryptography` package: the
+    // RSA ones for rsa_public.pem around an HS256 JWT signed with some_key,
+    // the direct one with the key 00 01 .. 0f.
+    let rsa_oaep_256 = JweUtil::from_rsa_pem(RSA_PRIVATE_PEM).unwrap()
+      .with_signer(JwtUtil::new("some_key"));
+    let rsa_oaep = JweUtil::from_rsa_pem(RSA_PRIVATE_PEM).unwrap()
+      .with_algorithm(JweAlgorithm::RsaOaep).unwrap()
+      .with_signer(JwtUtil::new("some_key"));
+    let direct = JweUtil::direct(&(0..16).collect::<Vec<u8>>()).unwrap();
+
+    // act
+    let results: Vec<Result<AuthClaims, Error>> = vec![
+      rsa_oaep_256.extract_claims(include_str!("../../tests/fixtures/jwe/rsa_oaep_256_a256gcm.jwe")).await,
+      rsa_oaep.extract_claims(include_str!("../../tests/fixtures/jwe/rsa_oaep_a256gcm.jwe")).await,
+      direct.extract_claims(include_str!("../../tests/fixtures/jwe/dir_a128gcm.jwe")).await,
+    ];
+
+    // assert
+    for result in results {
+      let claims = result.unwrap();
+      assert_eq!(claims.subject, "8f5b3e3a-4c2d-4f0e-9b7a-2d6c1e0f9a11");
+      assert_eq!(claims.user_details.email, "john.doe@example.com");
+    }
+  }
+
+  #[tokio::test]
+  async fn test_direct_rejects_wrong_key() {
+    // arrange
+    let token = JweUtil::direct(&[7u8; 32]).unwrap().generate_token(&sample_claims()).unwrap();
+
+    // act
+    let result: Result<AuthClaims, Error> = JweUtil::direct(&[8u8; 32]).unwrap().extract_claims(&token).await;
+
+    // assert
+    match result {
+      Err(Error::JwtTokenInvalid(error)) => assert_eq!(error.message, "Token could not be decrypted"),
+      _ => panic!("Expected JwtTokenInvalid error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_direct_rejects_invalid_key_length() {
+    // act
+    let result = JweUtil::direct(b"short");
+
+    // assert
+    assert!(matches!(result, Err(Error::JwtKeyInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_rsa_oaep_round_trip() {
+    // arrange
+    let issuer = JweUtil::from_rsa_public_pem(RSA_PUBLIC_PEM).unwrap()
+      .with_algorithm(JweAlgorithm::RsaOaep).unwrap()
+      .with_signer(JwtUtil::new("some_key"));
+    let recipient = JweUtil::from_rsa_pem(RSA_PRIVATE_PEM).unwrap()
+      .with_algorithm(JweAlgorithm::RsaOaep).unwrap()
+      .with_signer(JwtUtil::new("some_key"));
+    let claims = sample_claims();
+
+    // act
+    let token = issuer.generate_token(&claims).unwrap();
+    let extracted_claims: AuthClaims = recipient.extract_claims(&token).await.unwrap();
+    let issuer_result: Result<AuthClaims, Error> = issuer.extract_claims(&token).await;
+
+    // assert
+    assert_eq!(extracted_claims.user_details, claims.user_details);
+    assert!(matches!(issuer_result, Err(Error::JwtKeyInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_rejects_unexpected_algorithm() {
+    // arrange
+    let token = JweUtil::from_rsa_pem(RSA_PRIVATE_PEM).unwrap()
+      .with_algorithm(JweAlgorithm::RsaOaep).unwrap()
+      .with_signer(JwtUtil::new("some_key"))
+      .generate_token(&sample_claims()).unwrap();
+
+    // act
+    let result: Result<AuthClaims, Error> = JweUtil::from_rsa_pem(RSA_PRIVATE_PEM).unwrap()
+      .with_signer(JwtUtil::new("some_key"))
+      .extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_rejects_claims_forged_with_public_key() {
+    // arrange
+    let jwe_util = JweUtil::from_rsa_pem(RSA_PRIVATE_PEM).unwrap().with_signer(JwtUtil::new("some_key"));
+    let forger = JweUtil::from_rsa_public_pem(RSA_PUBLIC_PEM).unwrap();
+    let claims = serde_json::to_vec(&sample_claims()).unwrap();
+    let forged_token = forger.encrypt(&claims, None).unwrap();
+    let forged_nested_token = forger.encrypt(&claims, Some("JWT")).unwrap();
+
+    // act
+    let forged_result: Result<AuthClaims, Error> = jwe_util.extract_claims(&forged_token).await;
+    let forged_nested_result: Result<AuthClaims, Error> = jwe_util.extract_claims(&forged_nested_token).await;
+
+    // assert
+    match forged_result {
+      Err(Error::JwtTokenInvalid(error)) => assert_eq!(error.message, "Token is not signed as expected"),
+      _ => panic!("Expected JwtTokenInvalid error"),
+    }
+    assert!(matches!(forged_nested_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_rsa_requires_signer() {
+    // arrange
+    let token = JweUtil::from_rsa_public_pem(RSA_PUBLIC_PEM).unwrap().encrypt(b"{}", None).unwrap();
+
+    // act
+    let generate_result = JweUtil::from_rsa_public_pem(RSA_PUBLIC_PEM).unwrap().generate_token(&sample_claims());
+    let extract_result: Result<AuthClaims, Error> = JweUtil::from_rsa_pem(RSA_PRIVATE_PEM).unwrap()
+      .extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(generate_result, Err(Error::JwtKeyInvalid(_))));
+    assert!(matches!(extract_result, Err(Error::JwtKeyInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_applies_validation_policy() {
+    // arrange
+    let jwe_util = JweUtil::direct(&[7u8; 16]).unwrap();
+    let mut claims = sample_claims();
+    claims.expires_in = (Utc::now() - Duration::hours(1)).timestamp() as usize;
+    let token = jwe_util.generate_token(&claims).unwrap();
+
+    // act
+    let result: Result<AuthClaims, Error> = jwe_util.extract_claims(&token).await;
+
+    // assert
+    match result {
+      Err(Error::JwtTokenInvalid(error)) => assert_eq!(error.message, "Token has expired"),
+      _ => panic!("Expected JwtTokenInvalid error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_interchangeable_with_jwt_util_in_auth() {
+    // arrange
+    let jwe_util = JweUtil::direct(&[7u8; 32]).unwrap();
+    let claims = sample_claims();
+    let token = jwe_util.generate_token(&claims).unwrap();
+    let mut headers = lambda_http::http::HeaderMap::new();
+    headers.insert("Authorization", format!("Watashiwasta {}", token).parse().unwrap());
+
+    // act
+    let mut auth = Auth::new(Box::new(jwe_util));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    assert!(authenticate_result.is_ok());
+    assert_eq!(auth.user(), Some(claims.user_details));
+  }
+}
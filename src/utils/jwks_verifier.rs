@@ -0,0 +1,581 @@
+use std::{collections::HashMap, path::PathBuf, sync::RwLock};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode_header, jwk::JwkSet};
+use lambda_http::http::{Method, Request};
+use serde_json::{Map, Value};
+
+use crate::{
+  auth::http_client::IHttpClient,
+  error::SerializableError,
+  types::{auth::AuthPayload, utils::jwt_util::{AuthClaims, TokenType}},
+  utils::{jwt_util::{IJwtUtil, JwtUtil}, jwt_validation_policy::JwtValidationPolicy},
+  Error,
+};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IJwksFetcher: Send + Sync {
+  async fn fetch(&self) -> Result<String, Error>;
+}
+
+pub struct FileJwksFetcher {
+  path: PathBuf,
+}
+
+impl FileJwksFetcher {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self { path: path.into() }
+  }
+}
+
+#[async_trait]
+impl IJwksFetcher for FileJwksFetcher {
+  async fn fetch(&self) -> Result<String, Error> {
+    std::fs::read_to_string(&self.path).map_err(|error| Error::JwksFetch(SerializableError {
+      message: format!("Unable to read {}: {}", self.path.display(), error)
+    }))
+  }
+}
+
+pub struct StaticJwksFetcher {
+  document: String,
+}
+
+impl StaticJwksFetcher {
+  pub fn new(document: &str) -> Self {
+    Self { document: document.to_string() }
+  }
+}
+
+#[async_trait]
+impl IJwksFetcher for StaticJwksFetcher {
+  async fn fetch(&self) -> Result<String, Error> {
+    Ok(self.document.clone())
+  }
+}
+
+/// Fetches the key set from the issuer's `jwks_uri`, e.g.
+/// `https://cognito-idp.<region>.amazonaws.com/<pool id>/.well-known/jwks.json`.
+pub struct HttpJwksFetcher {
+  http_client: Box<dyn IHttpClient>,
+  url: String,
+}
+
+impl HttpJwksFetcher {
+  pub fn new(http_client: Box<dyn IHttpClient>, url: &str) -> Self {
+    Self { http_client, url: url.to_string() }
+  }
+}
+
+#[async_trait]
+impl IJwksFetcher for HttpJwksFetcher {
+  async fn fetch(&self) -> Result<String, Error> {
+    let request = Request::builder()
+      .method(Method::GET)
+      .uri(&self.url)
+      .body(String::new())
+      .map_err(|error| Error::JwksFetch(SerializableError {
+        message: format!("Invalid JWKS url {}: {}", self.url, error)
+      }))?;
+    let response = self.http_client.send(request).await?;
+
+    if !response.status().is_success() {
+      return Err(Error::JwksFetch(SerializableError {
+        message: format!("JWKS endpoint {} returned {}", self.url, response.status())
+      }));
+    }
+
+    Ok(response.into_body())
+  }
+}
+
+/// Maps the claims of a token from an external issuer such as Cognito or
+/// Auth0, which has no `user_details`, to the principal.
+#[cfg_attr(test, automock)]
+pub trait IClaimsMapper<T: AuthPayload>: Send + Sync {
+  fn map(&self, claims: &Map<String, Value>) -> Result<T, Error>;
+}
+
+#[derive(Default)]
+struct JwksCache {
+  keys: HashMap<String, JwtUtil>,
+  fetched_at: Option<DateTime<Utc>>,
+}
+
+/// Verifies tokens against a JSON Web Key Set published by another issuer.
+/// Keys are cached by `kid` for `ttl`; a token with an unknown `kid` forces a
+/// refetch at most once per `refresh_cooldown`.
+pub struct JwksVerifier {
+  fetcher: Box<dyn IJwksFetcher>,
+  ttl: Duration,
+  refresh_cooldown: Duration,
+  validation_policy: JwtValidationPolicy,
+  cache: RwLock<JwksCache>,
+}
+
+impl JwksVerifier {
+  pub fn new(fetcher: Box<dyn IJwksFetcher>, ttl: Duration) -> Self {
+    Self {
+      fetcher,
+      ttl,
+      refresh_cooldown: Duration::seconds(30),
+      validation_policy: JwtValidationPolicy::default(),
+      cache: RwLock::new(JwksCache::default()),
+    }
+  }
+
+  pub fn from_file(path: impl Into<PathBuf>, ttl: Duration) -> Self {
+    Self::new(Box::new(FileJwksFetcher::new(path)), ttl)
+  }
+
+  pub fn from_url(http_client: Box<dyn IHttpClient>, url: &str, ttl: Duration) -> Self {
+    Self::new(Box::new(HttpJwksFetcher::new(http_client, url)), ttl)
+  }
+
+  pub fn from_document(document: &str) -> Self {
+    Self::new(Box::new(StaticJwksFetcher::new(document)), Duration::MAX)
+  }
+
+  pub fn with_refresh_cooldown(mut self, refresh_cooldown: Duration) -> Self {
+    self.refresh_cooldown = refresh_cooldown;
+
+    self
+  }
+
+  /// Remote issuers should at least pin `iss` and `aud` here.
+  pub fn with_validation_policy(mut self, validation_policy: JwtValidationPolicy) -> Self {
+    self.validation_policy = validation_policy;
+    self.cache = RwLock::new(JwksCache::default());
+
+    self
+  }
+
+  /// Verifies external tokens, whose principal comes from `claims_mapper`
+  /// instead of the `user_details` claim. They are treated as access tokens.
+  pub fn with_claims_mapper<T: AuthPayload>(self, claims_mapper: Box<dyn IClaimsMapper<T>>) -> MappedJwksVerifier<T> {
+    MappedJwksVerifier { verifier: self, claims_mapper }
+  }
+
+  pub async fn refresh(&self) -> Result<(), Error> {
+    let document = self.fetcher.fetch().await?;
+    let jwks: JwkSet = serde_json::from_str(&document).map_err(|error| {
+      Error::JwksFetch(SerializableError {
+        message: format!("Invalid JWKS document: {}", error)
+      })
+    })?;
+
+    // Keys without a kid or with an unsupported type are skipped rather than
+    // failing the whole set.
+    let keys = jwks.keys.iter()
+      .filter_map(|jwk| {
+        let key_id = jwk.common.key_id.clone()?;
+        let jwt_util = JwtUtil::from_jwk(jwk).ok()?
+          .with_validation_policy(self.validation_policy.clone());
+
+        Some((key_id, jwt_util))
+      })
+      .collect();
+
+    let mut cache = self.cache.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *cache = JwksCache { keys, fetched_at: Some(Utc::now()) };
+
+    Ok(())
+  }
+
+  fn find_key(&self, key_id: &str) -> (Option<JwtUtil>, Option<DateTime<Utc>>) {
+    let cache = self.cache.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    (cache.keys.get(key_id).cloned(), cache.fetched_at)
+  }
+
+  /// Verifies the token like `extract_claims` but returns the claims as
+  /// JSON, for tokens that aren't `AuthClaims` such as OIDC ID tokens.
+  pub async fn extract_raw_claims(&self, token: &str) -> Result<Map<String, Value>, Error> {
+    self.key_for(token).await?.extract_raw_claims(token)
+  }
+
+  async fn key_for(&self, token: &str) -> Result<JwtUtil, Error> {
+    let key_id = decode_header(token)?.kid.ok_or_else(|| {
+      Error::JwtTokenInvalid(SerializableError {
+        message: "Token is missing a key id".to_string()
+      })
+    })?;
+
+    let (mut jwt_util, fetched_at) = self.find_key(&key_id);
+    let should_refresh = match jwt_util {
+      Some(_) => Self::is_older_than(fetched_at, self.ttl),
+      None => Self::is_older_than(fetched_at, self.refresh_cooldown),
+    };
+
+    if should_refresh {
+      self.refresh().await?;
+      jwt_util = self.find_key(&key_id).0;
+    }
+
+    jwt_util.ok_or_else(|| Error::JwtTokenInvalid(SerializableError {
+      message: "Token was signed with an unknown key".to_string()
+    }))
+  }
+
+  fn is_older_than(fetched_at: Option<DateTime<Utc>>, age: Duration) -> bool {
+    match fetched_at {
+      None => true,
+      Some(fetched_at) => Utc::now().signed_duration_since(fetched_at) >= age,
+    }
+  }
+}
+
+#[async_trait]
+impl<T: AuthPayload> IJwtUtil<T> for JwksVerifier {
+  fn generate_token(&self, _claims: &AuthClaims<T>) -> Result<String, Error> {
+    Err(Error::JwtGenerate(SerializableError {
+      message: "JwksVerifier can only verify tokens".to_string()
+    }))
+  }
+
+  async fn extract_claims(&self, token: &str) -> Result<AuthClaims<T>, Error> {
+    self.key_for(token).await?.extract_claims(token).await
+  }
+}
+
+/// A `JwksVerifier` for tokens that aren't `AuthClaims`, see
+/// `JwksVerifier::with_claims_mapper`.
+pub struct MappedJwksVerifier<T: AuthPayload> {
+  verifier: JwksVerifier,
+  claims_mapper: Box<dyn IClaimsMapper<T>>,
+}
+
+#[async_trait]
+impl<T: AuthPayload> IJwtUtil<T> for MappedJwksVerifier<T> {
+  fn generate_token(&self, _claims: &AuthClaims<T>) -> Result<String, Error> {
+    Err(Error::JwtGenerate(SerializableError {
+      message: "JwksVerifier can only verify tokens".to_string()
+    }))
+  }
+
+  async fn extract_claims(&self, token: &str) -> Result<AuthClaims<T>, Error> {
+    let mut claims = self.verifier.extract_raw_claims(token).await?;
+    let user_details = self.claims_mapper.map(&claims)?;
+
+    claims.insert("user_details".to_string(), serde_json::to_value(user_details).map_err(invalid_claims)?);
+    claims.insert("token_type".to_string(), serde_json::to_value(TokenType::AccessToken).map_err(invalid_claims)?);
+
+    serde_json::from_value(Value::Object(claims)).map_err(invalid_claims)
+  }
+}
+
+fn invalid_claims(error: serde_json::Error) -> Error {
+  Error::JwtTokenInvalid(SerializableError {
+    message: format!("Invalid external token claims: {}", error)
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+  use lambda_http::http::{Response, StatusCode};
+  use uuid::Uuid;
+
+  use crate::{
+    auth::http_client::MockIHttpClient,
+    types::{auth::AuthUser, utils::jwt_util::TokenType},
+  };
+
+  fn sample_claims() -> AuthClaims {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "Jane".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "jane.doe@example.com".to_string(),
+    };
+
+    AuthClaims {
+      subject: auth_user.id.to_string(),
+      expires_in: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+      user_details: auth_user,
+      token_type: TokenType::AccessToken,
+      jti: None,
+      token_version: None,
+      issuer: None,
+      audience: None,
+      issued_at: None,
+      not_before: None,
+      roles: Vec::new(),
+      permissions: Vec::new(),
+      extra: Default::default(),
+    }
+  }
+
+  fn ed_signer(key_id: &str) -> JwtUtil {
+    JwtUtil::from_ed_pem(
+      include_bytes!("../../tests/fixtures/keys/ed_private.pem"),
+      include_bytes!("../../tests/fixtures/keys/ed_public.pem"),
+    ).unwrap().with_key_id(key_id)
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_from_document() {
+    // arrange
+    let signer = ed_signer("ed-1");
+    let document = serde_json::to_string(&signer.jwks()).unwrap();
+    let verifier = JwksVerifier::from_document(&document);
+    let claims = sample_claims();
+    let token = signer.generate_token(&claims).unwrap();
+
+    // act
+    let extract_claims_result = verifier.extract_claims(&token).await;
+
+    // assert
+    assert_eq!(extract_claims_result.unwrap(), claims);
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_from_file() {
+    // arrange
+    let signer = ed_signer("ed-1");
+    let path = std::env::temp_dir().join(format!("jwks-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, serde_json::to_string(&signer.jwks()).unwrap()).unwrap();
+    let verifier = JwksVerifier::from_file(&path, Duration::minutes(5));
+    let claims = sample_claims();
+    let token = signer.generate_token(&claims).unwrap();
+
+    // act
+    let extract_claims_result = verifier.extract_claims(&token).await;
+
+    // assert
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(extract_claims_result.unwrap(), claims);
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_from_url() {
+    // arrange
+    let signer = ed_signer("ed-1");
+    let document = serde_json::to_string(&signer.jwks()).unwrap();
+    let mut http_client = MockIHttpClient::new();
+    http_client.expect_send()
+      .withf(|request| request.uri() == "https://idp.example.com/.well-known/jwks.json")
+      .times(1)
+      .returning(move |_| Ok(Response::builder().status(StatusCode::OK).body(document.clone()).unwrap()));
+    let verifier = JwksVerifier::from_url(Box::new(http_client), "https://idp.example.com/.well-known/jwks.json", Duration::minutes(5));
+    let claims = sample_claims();
+    let token = signer.generate_token(&claims).unwrap();
+
+    // act
+    let extract_claims_result = verifier.extract_claims(&token).await;
+
+    // assert
+    assert_eq!(extract_claims_result.unwrap(), claims);
+  }
+
+  #[tokio::test]
+  async fn test_jwks_endpoint_error() {
+    // arrange
+    let mut http_client = MockIHttpClient::new();
+    http_client.expect_send()
+      .times(1)
+      .returning(|_| Ok(Response::builder().status(StatusCode::SERVICE_UNAVAILABLE).body(String::new()).unwrap()));
+    let verifier = JwksVerifier::from_url(Box::new(http_client), "https://idp.example.com/.well-known/jwks.json", Duration::minutes(5));
+
+    // act
+    let refresh_result = verifier.refresh().await;
+
+    // assert
+    assert!(matches!(refresh_result, Err(Error::JwksFetch(_))));
+  }
+
+  #[tokio::test]
+  async fn test_keys_are_cached_within_ttl() {
+    // arrange
+    let signer = ed_signer("ed-1");
+    let document = serde_json::to_string(&signer.jwks()).unwrap();
+    let mut fetcher = MockIJwksFetcher::new();
+    fetcher.expect_fetch().times(1).returning(move || Ok(document.clone()));
+    let verifier = JwksVerifier::new(Box::new(fetcher), Duration::minutes(5));
+    let token = signer.generate_token(&sample_claims()).unwrap();
+
+    // act
+    let first_result: Result<AuthClaims, Error> = verifier.extract_claims(&token).await;
+    let second_result: Result<AuthClaims, Error> = verifier.extract_claims(&token).await;
+
+    // assert
+    assert!(first_result.is_ok());
+    assert!(second_result.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_keys_are_refetched_after_ttl() {
+    // arrange
+    let signer = ed_signer("ed-1");
+    let document = serde_json::to_string(&signer.jwks()).unwrap();
+    let mut fetcher = MockIJwksFetcher::new();
+    fetcher.expect_fetch().times(2).returning(move || Ok(document.clone()));
+    let verifier = JwksVerifier::new(Box::new(fetcher), Duration::zero());
+    let token = signer.generate_token(&sample_claims()).unwrap();
+
+    // act
+    let _: AuthClaims = verifier.extract_claims(&token).await.unwrap();
+    let _: AuthClaims = verifier.extract_claims(&token).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_unknown_key_id_respects_refresh_cooldown() {
+    // arrange
+    let document = serde_json::to_string(&ed_signer("ed-1").jwks()).unwrap();
+    let mut fetcher = MockIJwksFetcher::new();
+    fetcher.expect_fetch().times(1).returning(move || Ok(document.clone()));
+    let verifier = JwksVerifier::new(Box::new(fetcher), Duration::minutes(5));
+    let token = ed_signer("ed-2").generate_token(&sample_claims()).unwrap();
+
+    // act
+    let first_result: Result<AuthClaims, Error> = verifier.extract_claims(&token).await;
+    let second_result: Result<AuthClaims, Error> = verifier.extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(first_result, Err(Error::JwtTokenInvalid(_))));
+    assert!(matches!(second_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_validation_policy_is_applied() {
+    // arrange
+    let signer = ed_signer("ed-1");
+    let document = serde_json::to_string(&signer.jwks()).unwrap();
+    let verifier = JwksVerifier::from_document(&document)
+      .with_validation_policy(JwtValidationPolicy::new().allow_audience("orders"));
+    let claims = sample_claims().with_audience("orders");
+    let token = signer.generate_token(&claims).unwrap();
+    let other_token = signer.generate_token(&sample_claims()).unwrap();
+
+    // act
+    let extract_claims_result = verifier.extract_claims(&token).await;
+    let other_result: Result<AuthClaims, Error> = verifier.extract_claims(&other_token).await;
+
+    // assert
+    assert_eq!(extract_claims_result.unwrap(), claims);
+    assert!(matches!(other_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_extract_raw_claims() {
+    // arrange
+    let signer = ed_signer("ed-1");
+    let document = serde_json::to_string(&signer.jwks()).unwrap();
+    let verifier = JwksVerifier::from_document(&document);
+    let claims = sample_claims();
+    let token = signer.generate_token(&claims).unwrap();
+
+    // act
+    let raw_claims = verifier.extract_raw_claims(&token).await.unwrap();
+
+    // assert
+    assert_eq!(raw_claims.get("sub").and_then(Value::as_str), Some(claims.subject.as_str()));
+  }
+
+  struct CognitoClaimsMapper;
+
+  impl IClaimsMapper<AuthUser> for CognitoClaimsMapper {
+    fn map(&self, claims: &Map<String, Value>) -> Result<AuthUser, Error> {
+      let claim = |name: &str| claims.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+
+      Ok(AuthUser {
+        id: Uuid::parse_str(&claim("sub")).map_err(|error| Error::JwtTokenInvalid(SerializableError {
+          message: error.to_string()
+        }))?,
+        first_name: claim("given_name"),
+        middle_name: None,
+        last_name: claim("family_name"),
+        email: claim("email"),
+      })
+    }
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_with_claims_mapper() {
+    // arrange
+    let document = serde_json::to_string(&ed_signer("ed-1").jwks()).unwrap();
+    let verifier = JwksVerifier::from_document(&document).with_claims_mapper(Box::new(CognitoClaimsMapper));
+    let user_id = Uuid::new_v4();
+    let now = Utc::now().timestamp();
+    let cognito_claims = serde_json::json!({
+      "sub": user_id,
+      "iss": "https://cognito-idp.eu-west-1.amazonaws.com/eu-west-1_Example",
+      "client_id": "3n4b5urk1ft4fl3mg5e62d9ado",
+      "token_use": "access",
+      "scope": "orders/read orders/write",
+      "cognito:groups": ["admins"],
+      "username": "john.doe",
+      "given_name": "John",
+      "family_name": "Doe",
+      "email": "john.doe@example.com",
+      "auth_time": now,
+      "iat": now,
+      "exp": now + 3600,
+      "jti": Uuid::new_v4(),
+    });
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some("ed-1".to_string());
+    let encoding_key = EncodingKey::from_ed_pem(include_bytes!("../../tests/fixtures/keys/ed_private.pem")).unwrap();
+    let token = encode(&header, &cognito_claims, &encoding_key).unwrap();
+
+    // act
+    let claims = verifier.extract_claims(&token).await.unwrap();
+
+    // assert
+    assert_eq!(claims.subject, user_id.to_string());
+    assert_eq!(claims.user_details.id, user_id);
+    assert_eq!(claims.user_details.email, "john.doe@example.com");
+    assert_eq!(claims.token_type, TokenType::AccessToken);
+    assert_eq!(claims.scopes(), vec!["orders/read", "orders/write"]);
+  }
+
+  #[tokio::test]
+  async fn test_claims_mapper_error_is_returned() {
+    // arrange
+    let signer = ed_signer("ed-1");
+    let document = serde_json::to_string(&signer.jwks()).unwrap();
+    let mut claims_mapper = MockIClaimsMapper::<AuthUser>::new();
+    claims_mapper.expect_map().times(1).returning(|_| Err(Error::Unauthorized(SerializableError {
+      message: "Unknown user".to_string()
+    })));
+    let verifier = JwksVerifier::from_document(&document).with_claims_mapper(Box::new(claims_mapper));
+    let token = signer.generate_token(&sample_claims()).unwrap();
+
+    // act
+    let extract_claims_result = verifier.extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(extract_claims_result, Err(Error::Unauthorized(_))));
+  }
+
+  #[tokio::test]
+  async fn test_invalid_document() {
+    // arrange
+    let verifier = JwksVerifier::from_document("not json");
+    let token = ed_signer("ed-1").generate_token(&sample_claims()).unwrap();
+
+    // act
+    let extract_claims_result: Result<AuthClaims, Error> = verifier.extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(extract_claims_result, Err(Error::JwksFetch(_))));
+  }
+
+  #[test]
+  fn test_generate_token_is_not_supported() {
+    // arrange
+    let verifier = JwksVerifier::from_document(r#"{"keys": []}"#);
+
+    // act
+    let generate_result = verifier.generate_token(&sample_claims());
+
+    // assert
+    assert!(matches!(generate_result, Err(Error::JwtGenerate(_))));
+  }
+}
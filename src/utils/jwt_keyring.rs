@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use jsonwebtoken::{decode_header, jwk::JwkSet};
+
+use crate::{
+  error::SerializableError,
+  types::{auth::AuthPayload, utils::jwt_util::AuthClaims},
+  utils::{jwt_util::{IJwtUtil, JwtUtil}, jwt_validation_policy::JwtValidationPolicy},
+  Error,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyStatus {
+  /// Signs new tokens and verifies existing ones. Only one key is active.
+  Active,
+  /// Verifies tokens signed before the key was rotated out.
+  Verifying,
+  /// Kept in the keyring but no longer accepted.
+  Retired,
+}
+
+#[derive(Clone)]
+struct KeyringEntry {
+  jwt_util: JwtUtil,
+  status: KeyStatus,
+}
+
+/// Holds several `JwtUtil` keys addressed by `kid` so keys can be rotated
+/// without invalidating tokens signed with the previous key.
+#[derive(Clone, Default)]
+pub struct JwtKeyring {
+  keys: HashMap<String, KeyringEntry>,
+  active_key_id: Option<String>,
+  validation_policy: Option<JwtValidationPolicy>,
+}
+
+impl JwtKeyring {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Applies `validation_policy` to every key, including keys inserted later.
+  pub fn with_validation_policy(mut self, validation_policy: JwtValidationPolicy) -> Self {
+    for entry in self.keys.values_mut() {
+      entry.jwt_util = entry.jwt_util.clone().with_validation_policy(validation_policy.clone());
+    }
+    self.validation_policy = Some(validation_policy);
+
+    self
+  }
+
+  /// Adds a key that verifies tokens but does not sign new ones until it is
+  /// activated. Replaces any existing key with the same id.
+  pub fn insert(&mut self, key_id: &str, jwt_util: JwtUtil) {
+    if self.active_key_id.as_deref() == Some(key_id) {
+      self.active_key_id = None;
+    }
+
+    let jwt_util = match self.validation_policy.clone() {
+      Some(validation_policy) => jwt_util.with_validation_policy(validation_policy),
+      None => jwt_util,
+    };
+
+    self.keys.insert(key_id.to_string(), KeyringEntry {
+      jwt_util: jwt_util.with_key_id(key_id),
+      status: KeyStatus::Verifying,
+    });
+  }
+
+  /// Makes `key_id` the signing key. The previously active key keeps
+  /// verifying tokens until it is retired.
+  pub fn activate(&mut self, key_id: &str) -> Result<(), Error> {
+    let entry = self.keys.get(key_id).ok_or_else(|| unknown_key_error(key_id))?;
+
+    if !entry.jwt_util.can_sign() {
+      return Err(Error::JwtKeyInvalid(SerializableError {
+        message: format!("Key {} has no signing key and cannot be activated", key_id)
+      }));
+    }
+
+    if let Some(previous_key_id) = self.active_key_id.take()
+      && let Some(previous_entry) = self.keys.get_mut(&previous_key_id) {
+      previous_entry.status = KeyStatus::Verifying;
+    }
+
+    if let Some(entry) = self.keys.get_mut(key_id) {
+      entry.status = KeyStatus::Active;
+    }
+    self.active_key_id = Some(key_id.to_string());
+
+    Ok(())
+  }
+
+  /// Stops accepting tokens signed with `key_id`. The active key can't be
+  /// retired; activate its replacement first.
+  pub fn retire(&mut self, key_id: &str) -> Result<(), Error> {
+    if self.active_key_id.as_deref() == Some(key_id) {
+      return Err(Error::JwtKeyInvalid(SerializableError {
+        message: format!("Key {} is active and cannot be retired", key_id)
+      }));
+    }
+
+    let entry = self.keys.get_mut(key_id).ok_or_else(|| unknown_key_error(key_id))?;
+    entry.status = KeyStatus::Retired;
+
+    Ok(())
+  }
+
+  pub fn remove(&mut self, key_id: &str) -> Option<JwtUtil> {
+    if self.active_key_id.as_deref() == Some(key_id) {
+      self.active_key_id = None;
+    }
+
+    self.keys.remove(key_id).map(|entry| entry.jwt_util)
+  }
+
+  pub fn status(&self, key_id: &str) -> Option<KeyStatus> {
+    self.keys.get(key_id).map(|entry| entry.status)
+  }
+
+  pub fn active_key_id(&self) -> Option<&str> {
+    self.active_key_id.as_deref()
+  }
+
+  /// Public keys of every key that still verifies tokens, ready to be served
+  /// from a `/.well-known/jwks.json` handler.
+  pub fn jwks(&self) -> JwkSet {
+    let mut key_ids: Vec<&String> = self.keys.iter()
+      .filter(|(_, entry)| entry.status != KeyStatus::Retired)
+      .map(|(key_id, _)| key_id)
+      .collect();
+    key_ids.sort();
+
+    JwkSet {
+      keys: key_ids.into_iter()
+        .filter_map(|key_id| self.keys[key_id].jwt_util.jwk())
+        .collect(),
+    }
+  }
+}
+
+fn unknown_key_error(key_id: &str) -> Error {
+  Error::JwtKeyInvalid(SerializableError {
+    message: format!("Unknown key {}", key_id)
+  })
+}
+
+#[async_trait]
+impl<T: AuthPayload> IJwtUtil<T> for JwtKeyring {
+  fn generate_token(&self, claims: &AuthClaims<T>) -> Result<String, Error> {
+    let entry = self.active_key_id.as_ref()
+      .and_then(|key_id| self.keys.get(key_id))
+      .ok_or_else(|| Error::JwtGenerate(SerializableError {
+        message: "Keyring has no active key".to_string()
+      }))?;
+
+    entry.jwt_util.generate_token(claims)
+  }
+
+  async fn extract_claims(&self, token: &str) -> Result<AuthClaims<T>, Error> {
+    let key_id = decode_header(token)?.kid.ok_or_else(|| {
+      Error::JwtTokenInvalid(SerializableError {
+        message: "Token is missing a key id".to_string()
+      })
+    })?;
+
+    let entry = self.keys.get(&key_id)
+      .filter(|entry| entry.status != KeyStatus::Retired)
+      .ok_or_else(|| Error::JwtTokenInvalid(SerializableError {
+        message: "Token was signed with an unknown or retired key".to_string()
+      }))?;
+
+    entry.jwt_util.extract_claims(token).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::{Duration, Utc};
+  use uuid::Uuid;
+
+  use crate::types::{auth::AuthUser, utils::jwt_util::TokenType};
+
+  fn sample_claims() -> AuthClaims {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "Jane".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "jane.doe@example.com".to_string(),
+    };
+
+    AuthClaims {
+      subject: auth_user.id.to_string(),
+      expires_in: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+      user_details: auth_user,
+      token_type: TokenType::AccessToken,
+      jti: None,
+      token_version: None,
+      issuer: None,
+      audience: None,
+      issued_at: None,
+      not_before: None,
+      roles: Vec::new(),
+      permissions: Vec::new(),
+      extra: Default::default(),
+    }
+  }
+
+  fn keyring_with_active(key_id: &str, app_key: &str) -> JwtKeyring {
+    let mut keyring = JwtKeyring::new();
+    keyring.insert(key_id, JwtUtil::new(app_key));
+    keyring.activate(key_id).unwrap();
+
+    keyring
+  }
+
+  #[test]
+  fn test_generate_token_stamps_active_key_id() {
+    // arrange
+    let keyring = keyring_with_active("key-1", "first_key");
+
+    // act
+    let token = keyring.generate_token(&sample_claims()).unwrap();
+
+    // assert
+    assert_eq!(decode_header(&token).unwrap().kid, Some("key-1".to_string()));
+  }
+
+  #[tokio::test]
+  async fn test_generate_token_without_active_key() {
+    // arrange
+    let mut keyring = JwtKeyring::new();
+    keyring.insert("key-1", JwtUtil::new("first_key"));
+
+    // act
+    let generate_result = keyring.generate_token(&sample_claims());
+
+    // assert
+    assert!(matches!(generate_result, Err(Error::JwtGenerate(_))));
+  }
+
+  #[tokio::test]
+  async fn test_rotation_keeps_old_tokens_valid() {
+    // arrange
+    let mut keyring = keyring_with_active("key-1", "first_key");
+    let claims = sample_claims();
+    let old_token = keyring.generate_token(&claims).unwrap();
+
+    // act
+    keyring.insert("key-2", JwtUtil::new("second_key"));
+    keyring.activate("key-2").unwrap();
+    let new_token = keyring.generate_token(&claims).unwrap();
+
+    // assert
+    assert_eq!(keyring.status("key-1"), Some(KeyStatus::Verifying));
+    assert_eq!(decode_header(&new_token).unwrap().kid, Some("key-2".to_string()));
+    assert_eq!(keyring.extract_claims(&old_token).await.unwrap(), claims);
+    assert_eq!(keyring.extract_claims(&new_token).await.unwrap(), claims);
+  }
+
+  #[tokio::test]
+  async fn test_retired_key_is_rejected() {
+    // arrange
+    let mut keyring = keyring_with_active("key-1", "first_key");
+    let old_token = keyring.generate_token(&sample_claims()).unwrap();
+    keyring.insert("key-2", JwtUtil::new("second_key"));
+    keyring.activate("key-2").unwrap();
+
+    // act
+    keyring.retire("key-1").unwrap();
+    let extract_claims_result: Result<AuthClaims, Error> = keyring.extract_claims(&old_token).await;
+
+    // assert
+    assert!(matches!(extract_claims_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[test]
+  fn test_retire_active_key_fails() {
+    // arrange
+    let mut keyring = keyring_with_active("key-1", "first_key");
+
+    // act
+    let retire_result = keyring.retire("key-1");
+
+    // assert
+    assert!(matches!(retire_result, Err(Error::JwtKeyInvalid(_))));
+    assert_eq!(keyring.active_key_id(), Some("key-1"));
+  }
+
+  #[test]
+  fn test_activate_verify_only_key_fails() {
+    // arrange
+    let mut keyring = JwtKeyring::new();
+    keyring.insert("key-1", JwtUtil::from_ed_public_pem(
+      include_bytes!("../../tests/fixtures/keys/ed_public.pem")
+    ).unwrap());
+
+    // act
+    let activate_result = keyring.activate("key-1");
+
+    // assert
+    assert!(matches!(activate_result, Err(Error::JwtKeyInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_validation_policy_applies_to_all_keys() {
+    // arrange
+    let policy = JwtValidationPolicy::new().allow_issuer("https://auth.example.com");
+    let mut keyring = JwtKeyring::new().with_validation_policy(policy);
+    keyring.insert("key-1", JwtUtil::new("first_key"));
+    keyring.activate("key-1").unwrap();
+    let claims = sample_claims();
+
+    // act
+    let token = keyring.generate_token(&claims).unwrap();
+    let extract_claims_result: Result<AuthClaims, Error> = keyring.extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(extract_claims_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_jwks_skips_retired_and_symmetric_keys() {
+    // arrange
+    let mut keyring = JwtKeyring::new();
+    keyring.insert("ed-1", JwtUtil::from_ed_pem(
+      include_bytes!("../../tests/fixtures/keys/ed_private.pem"),
+      include_bytes!("../../tests/fixtures/keys/ed_public.pem"),
+    ).unwrap());
+    keyring.insert("ec-1", JwtUtil::from_ec_public_pem(
+      include_bytes!("../../tests/fixtures/keys/ec_public.pem"),
+    ).unwrap());
+    keyring.insert("hmac-1", JwtUtil::new("secret"));
+    keyring.activate("ed-1").unwrap();
+    keyring.retire("ec-1").unwrap();
+
+    // act
+    let jwks = keyring.jwks();
+
+    // assert
+    assert_eq!(jwks.keys.len(), 1);
+    assert!(jwks.find("ed-1").is_some());
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_missing_or_unknown_key_id() {
+    // arrange
+    let keyring = keyring_with_active("key-1", "first_key");
+    let claims = sample_claims();
+    let token_without_kid = JwtUtil::new("first_key").generate_token(&claims).unwrap();
+    let token_with_unknown_kid = JwtUtil::new("first_key")
+      .with_key_id("key-9")
+      .generate_token(&claims)
+      .unwrap();
+
+    // act
+    let missing_kid_result: Result<AuthClaims, Error> = keyring.extract_claims(&token_without_kid).await;
+    let unknown_kid_result: Result<AuthClaims, Error> = keyring.extract_claims(&token_with_unknown_kid).await;
+
+    // assert
+    assert!(matches!(missing_kid_result, Err(Error::JwtTokenInvalid(_))));
+    assert!(matches!(unknown_kid_result, Err(Error::JwtTokenInvalid(_))));
+  }
+}
@@ -1,19 +1,48 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use std::str::FromStr;
 
-use crate::{types::utils::jwt_util::AuthClaims, Error};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+  decode, encode,
+  jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+    PublicKeyUse, RSAKeyParameters, RSAKeyType,
+  },
+  Algorithm, DecodingKey, EncodingKey, Header,
+};
+use rsa::{
+  pkcs1::DecodeRsaPublicKey,
+  pkcs8::{der::Document, spki::SubjectPublicKeyInfoRef, DecodePublicKey},
+  traits::PublicKeyParts,
+  RsaPublicKey,
+};
+use serde_json::{Map, Value};
+
+use crate::{
+  error::SerializableError,
+  types::{auth::{AuthPayload, AuthUser}, utils::jwt_util::AuthClaims},
+  utils::jwt_validation_policy::JwtValidationPolicy,
+  Error,
+};
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 #[cfg_attr(test, automock)]
-pub trait IJwtUtil {
-  fn generate_token(&self, claims: &AuthClaims) -> Result<String, Error>;
-  fn extract_claims(&self, token: &str) -> Result<AuthClaims, Error>;
+#[async_trait]
+pub trait IJwtUtil<T: AuthPayload = AuthUser>: Send + Sync {
+  fn generate_token(&self, claims: &AuthClaims<T>) -> Result<String, Error>;
+  async fn extract_claims(&self, token: &str) -> Result<AuthClaims<T>, Error>;
 }
 
 #[derive(Clone)]
 pub struct JwtUtil {
-  encoding_key: EncodingKey,
+  algorithm: Algorithm,
+  encoding_key: Option<EncodingKey>,
   decoding_key: DecodingKey,
+  key_id: Option<String>,
+  public_key: Option<AlgorithmParameters>,
+  validation_policy: JwtValidationPolicy,
 }
 
 impl JwtUtil {
@@ -21,19 +50,351 @@ impl JwtUtil {
     let encoding_key = EncodingKey::from_secret(app_key.as_bytes());
     let decoding_key = DecodingKey::from_secret(app_key.as_bytes());
 
-    Self { encoding_key, decoding_key }
+    Self {
+      algorithm: Algorithm::HS256,
+      encoding_key: Some(encoding_key),
+      decoding_key,
+      key_id: None,
+      public_key: None,
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  pub fn from_rsa_pem(private_key: &[u8], public_key: &[u8]) -> Result<Self, Error> {
+    Ok(Self {
+      algorithm: Algorithm::RS256,
+      encoding_key: Some(EncodingKey::from_rsa_pem(private_key)?),
+      decoding_key: DecodingKey::from_rsa_pem(public_key)?,
+      key_id: None,
+      public_key: rsa_public_key_from_pem(public_key)?,
+      validation_policy: JwtValidationPolicy::default(),
+    })
+  }
+
+  pub fn from_rsa_der(private_key: &[u8], public_key: &[u8]) -> Self {
+    Self {
+      algorithm: Algorithm::RS256,
+      encoding_key: Some(EncodingKey::from_rsa_der(private_key)),
+      decoding_key: DecodingKey::from_rsa_der(public_key),
+      key_id: None,
+      public_key: rsa_public_key_from_der(public_key),
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  pub fn from_rsa_public_pem(public_key: &[u8]) -> Result<Self, Error> {
+    Ok(Self {
+      algorithm: Algorithm::RS256,
+      encoding_key: None,
+      decoding_key: DecodingKey::from_rsa_pem(public_key)?,
+      key_id: None,
+      public_key: rsa_public_key_from_pem(public_key)?,
+      validation_policy: JwtValidationPolicy::default(),
+    })
+  }
+
+  pub fn from_rsa_public_der(public_key: &[u8]) -> Self {
+    Self {
+      algorithm: Algorithm::RS256,
+      encoding_key: None,
+      decoding_key: DecodingKey::from_rsa_der(public_key),
+      key_id: None,
+      public_key: rsa_public_key_from_der(public_key),
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  pub fn from_ec_pem(private_key: &[u8], public_key: &[u8]) -> Result<Self, Error> {
+    Ok(Self {
+      algorithm: Algorithm::ES256,
+      encoding_key: Some(EncodingKey::from_ec_pem(private_key)?),
+      decoding_key: DecodingKey::from_ec_pem(public_key)?,
+      key_id: None,
+      public_key: ec_public_key(&spki_public_key_from_pem(public_key)?),
+      validation_policy: JwtValidationPolicy::default(),
+    })
+  }
+
+  pub fn from_ec_der(private_key: &[u8], public_key: &[u8]) -> Self {
+    Self {
+      algorithm: Algorithm::ES256,
+      encoding_key: Some(EncodingKey::from_ec_der(private_key)),
+      decoding_key: DecodingKey::from_ec_der(public_key),
+      key_id: None,
+      public_key: ec_public_key(public_key),
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  pub fn from_ec_public_pem(public_key: &[u8]) -> Result<Self, Error> {
+    Ok(Self {
+      algorithm: Algorithm::ES256,
+      encoding_key: None,
+      decoding_key: DecodingKey::from_ec_pem(public_key)?,
+      key_id: None,
+      public_key: ec_public_key(&spki_public_key_from_pem(public_key)?),
+      validation_policy: JwtValidationPolicy::default(),
+    })
+  }
+
+  pub fn from_ec_public_der(public_key: &[u8]) -> Self {
+    Self {
+      algorithm: Algorithm::ES256,
+      encoding_key: None,
+      decoding_key: DecodingKey::from_ec_der(public_key),
+      key_id: None,
+      public_key: ec_public_key(public_key),
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  pub fn from_ed_pem(private_key: &[u8], public_key: &[u8]) -> Result<Self, Error> {
+    Ok(Self {
+      algorithm: Algorithm::EdDSA,
+      encoding_key: Some(EncodingKey::from_ed_pem(private_key)?),
+      decoding_key: DecodingKey::from_ed_pem(public_key)?,
+      key_id: None,
+      public_key: ed_public_key(&spki_public_key_from_pem(public_key)?),
+      validation_policy: JwtValidationPolicy::default(),
+    })
+  }
+
+  pub fn from_ed_der(private_key: &[u8], public_key: &[u8]) -> Self {
+    Self {
+      algorithm: Algorithm::EdDSA,
+      encoding_key: Some(EncodingKey::from_ed_der(private_key)),
+      decoding_key: DecodingKey::from_ed_der(public_key),
+      key_id: None,
+      public_key: ed_public_key(public_key),
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  pub fn from_ed_public_pem(public_key: &[u8]) -> Result<Self, Error> {
+    Ok(Self {
+      algorithm: Algorithm::EdDSA,
+      encoding_key: None,
+      decoding_key: DecodingKey::from_ed_pem(public_key)?,
+      key_id: None,
+      public_key: ed_public_key(&spki_public_key_from_pem(public_key)?),
+      validation_policy: JwtValidationPolicy::default(),
+    })
+  }
+
+  pub fn from_ed_public_der(public_key: &[u8]) -> Self {
+    Self {
+      algorithm: Algorithm::EdDSA,
+      encoding_key: None,
+      decoding_key: DecodingKey::from_ed_der(public_key),
+      key_id: None,
+      public_key: ed_public_key(public_key),
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  /// Builds a verify-only instance from a JSON Web Key. Symmetric (`oct`)
+  /// keys are refused so a published key set can't be used for HMAC.
+  pub fn from_jwk(jwk: &Jwk) -> Result<Self, Error> {
+    let algorithm = match (jwk.common.key_algorithm, &jwk.algorithm) {
+      (_, AlgorithmParameters::OctetKey(_)) => {
+        return Err(Error::JwtKeyInvalid(SerializableError {
+          message: "Symmetric keys are not accepted from a JWK".to_string()
+        }));
+      },
+      (Some(key_algorithm), _) => Algorithm::from_str(&key_algorithm.to_string())?,
+      (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+      (None, AlgorithmParameters::EllipticCurve(parameters)) => match parameters.curve {
+        EllipticCurve::P384 => Algorithm::ES384,
+        _ => Algorithm::ES256,
+      },
+      (None, AlgorithmParameters::OctetKeyPair(_)) => Algorithm::EdDSA,
+    };
+
+    Ok(Self {
+      algorithm,
+      encoding_key: None,
+      decoding_key: DecodingKey::from_jwk(jwk)?,
+      key_id: jwk.common.key_id.clone(),
+      public_key: Some(jwk.algorithm.clone()),
+      validation_policy: JwtValidationPolicy::default(),
+    })
+  }
+
+  /// Switches to another algorithm of the same key family, e.g. `RS256` to
+  /// `PS384` or `ES256` to `ES384`.
+  pub fn with_algorithm(mut self, algorithm: Algorithm) -> Result<Self, Error> {
+    if key_family(algorithm) != key_family(self.algorithm) {
+      return Err(Error::JwtKeyInvalid(SerializableError {
+        message: format!("{:?} cannot be used with a {:?} key", algorithm, self.algorithm)
+      }));
+    }
+
+    self.algorithm = algorithm;
+
+    Ok(self)
+  }
+
+  /// Stamps `kid` into the header of every token this instance signs.
+  pub fn with_key_id(mut self, key_id: &str) -> Self {
+    self.key_id = Some(key_id.to_string());
+
+    self
+  }
+
+  pub fn with_validation_policy(mut self, validation_policy: JwtValidationPolicy) -> Self {
+    self.validation_policy = validation_policy;
+
+    self
+  }
+
+  pub fn key_id(&self) -> Option<&str> {
+    self.key_id.as_deref()
+  }
+
+  pub fn algorithm(&self) -> Algorithm {
+    self.algorithm
+  }
+
+  pub fn can_sign(&self) -> bool {
+    self.encoding_key.is_some()
   }
+
+  /// The public verification key as a JWK. HMAC secrets are never exported.
+  pub fn jwk(&self) -> Option<Jwk> {
+    let public_key = self.public_key.clone()?;
+
+    Some(Jwk {
+      common: CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_algorithm: KeyAlgorithm::from_str(&format!("{:?}", self.algorithm)).ok(),
+        key_id: self.key_id.clone(),
+        ..Default::default()
+      },
+      algorithm: public_key,
+    })
+  }
+
+  pub fn jwks(&self) -> JwkSet {
+    JwkSet { keys: self.jwk().into_iter().collect() }
+  }
+
+  /// Verifies the token like `extract_claims` but returns the claims as
+  /// JSON, for tokens that aren't `AuthClaims` such as OIDC ID tokens.
+  pub fn extract_raw_claims(&self, token: &str) -> Result<Map<String, Value>, Error> {
+    let validation = self.validation_policy.validation(self.algorithm);
+    let claims = decode::<Map<String, Value>>(token, &self.decoding_key, &validation)?.claims;
+
+    self.validation_policy.validate_raw_claims(&claims)?;
+
+    Ok(claims)
+  }
+}
+
+fn rsa_public_key_from_pem(pem: &[u8]) -> Result<Option<AlgorithmParameters>, Error> {
+  let pem = std::str::from_utf8(pem).map_err(|error| invalid_key_error(error.to_string()))?;
+  let public_key = RsaPublicKey::from_public_key_pem(pem)
+    .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+    .map_err(|error| invalid_key_error(error.to_string()))?;
+
+  Ok(Some(rsa_parameters(&public_key)))
+}
+
+fn rsa_public_key_from_der(der: &[u8]) -> Option<AlgorithmParameters> {
+  RsaPublicKey::from_pkcs1_der(der).ok().map(|public_key| rsa_parameters(&public_key))
 }
 
-impl IJwtUtil for JwtUtil {
-  fn generate_token(&self, claims: &AuthClaims) -> Result<String, Error> {
-    Ok(encode(&Header::default(), claims, &self.encoding_key)?)
+fn rsa_parameters(public_key: &RsaPublicKey) -> AlgorithmParameters {
+  AlgorithmParameters::RSA(RSAKeyParameters {
+    key_type: RSAKeyType::RSA,
+    n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+    e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+  })
+}
+
+fn spki_public_key_from_pem(pem: &[u8]) -> Result<Vec<u8>, Error> {
+  let pem = std::str::from_utf8(pem).map_err(|error| invalid_key_error(error.to_string()))?;
+  let (_, document) = Document::from_pem(pem)
+    .map_err(|error| invalid_key_error(error.to_string()))?;
+  let public_key_info = SubjectPublicKeyInfoRef::try_from(document.as_bytes())
+    .map_err(|error| invalid_key_error(error.to_string()))?;
+
+  Ok(public_key_info.subject_public_key.raw_bytes().to_vec())
+}
+
+fn ec_public_key(point: &[u8]) -> Option<AlgorithmParameters> {
+  // Uncompressed SEC1 point: 0x04 || x || y.
+  let curve = match point.len() {
+    65 => EllipticCurve::P256,
+    97 => EllipticCurve::P384,
+    _ => return None,
+  };
+  let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+
+  Some(AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+    key_type: EllipticCurveKeyType::EC,
+    curve,
+    x: URL_SAFE_NO_PAD.encode(x),
+    y: URL_SAFE_NO_PAD.encode(y),
+  }))
+}
+
+fn ed_public_key(public_key: &[u8]) -> Option<AlgorithmParameters> {
+  if public_key.len() != 32 {
+    return None;
   }
 
-  fn extract_claims(&self, token: &str) -> Result<AuthClaims, Error> {
-    Ok(decode::<AuthClaims>(
-      token, &self.decoding_key, &Validation::default()
-    )?.claims)
+  Some(AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+    key_type: OctetKeyPairType::OctetKeyPair,
+    curve: EllipticCurve::Ed25519,
+    x: URL_SAFE_NO_PAD.encode(public_key),
+  }))
+}
+
+fn invalid_key_error(message: String) -> Error {
+  Error::JwtKeyInvalid(SerializableError { message })
+}
+
+#[derive(PartialEq)]
+enum KeyFamily {
+  Hmac,
+  Rsa,
+  Ec,
+  Ed,
+}
+
+fn key_family(algorithm: Algorithm) -> KeyFamily {
+  match algorithm {
+    Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => KeyFamily::Hmac,
+    Algorithm::ES256 | Algorithm::ES384 => KeyFamily::Ec,
+    Algorithm::EdDSA => KeyFamily::Ed,
+    _ => KeyFamily::Rsa,
+  }
+}
+
+#[async_trait]
+impl<T: AuthPayload> IJwtUtil<T> for JwtUtil {
+  fn generate_token(&self, claims: &AuthClaims<T>) -> Result<String, Error> {
+    let encoding_key = self.encoding_key.as_ref().ok_or_else(|| {
+      Error::JwtGenerate(SerializableError {
+        message: "JwtUtil was created without a signing key".to_string()
+      })
+    })?;
+
+    let mut header = Header::new(self.algorithm);
+    header.kid = self.key_id.clone();
+
+    Ok(encode(&header, claims, encoding_key)?)
+  }
+
+  async fn extract_claims(&self, token: &str) -> Result<AuthClaims<T>, Error> {
+    // The validation pins the accepted algorithm so the token header
+    // can't pick a different one.
+    let validation = self.validation_policy.validation(self.algorithm);
+    let claims = decode::<AuthClaims<T>>(token, &self.decoding_key, &validation)?.claims;
+
+    self.validation_policy.validate_claims(&claims)?;
+
+    Ok(claims)
   }
 }
 
@@ -65,6 +426,15 @@ mod tests {
       expires_in: expires_in_seconds.timestamp() as usize,
       user_details: auth_user,
       token_type: TokenType::AccessToken,
+      jti: None,
+      token_version: None,
+      issuer: None,
+      audience: None,
+      issued_at: None,
+      not_before: None,
+      roles: Vec::new(),
+      permissions: Vec::new(),
+      extra: Default::default(),
     };
 
     // act
@@ -85,8 +455,8 @@ mod tests {
     assert_eq!(decoded_claims.token_type, claims.token_type);
   }
 
-  #[test]
-  fn test_extract_claims_success() {
+  #[tokio::test]
+  async fn test_extract_claims_success() {
     // arrange
     let jwt_util = JwtUtil::new("some_key");
     let auth_user = AuthUser {
@@ -101,12 +471,21 @@ mod tests {
        expires_in: Utc::now().timestamp() as usize,
        user_details: auth_user,
        token_type: TokenType::AccessToken,
+       jti: None,
+       token_version: None,
+       issuer: None,
+       audience: None,
+       issued_at: None,
+       not_before: None,
+       roles: Vec::new(),
+       permissions: Vec::new(),
+       extra: Default::default(),
     };
     let token = jwt_util.generate_token(&claims)
       .unwrap();
 
     // act
-    let extract_claims_result = jwt_util.extract_claims(&token);
+    let extract_claims_result = jwt_util.extract_claims(&token).await;
 
     // assert
     assert!(extract_claims_result.is_ok());
@@ -114,13 +493,13 @@ mod tests {
     assert_eq!(extract_claims, claims);
   }
 
-  #[test]
-  fn test_extract_claims_invalid_token() {
+  #[tokio::test]
+  async fn test_extract_claims_invalid_token() {
     // arrange
     let jwt_util = JwtUtil::new("some_key");
 
     // act
-    let extract_claims_result = jwt_util.extract_claims("invalid token");
+    let extract_claims_result: Result<AuthClaims, Error> = jwt_util.extract_claims("invalid token").await;
 
     // assert
     assert!(extract_claims_result.is_err());
@@ -130,4 +509,302 @@ mod tests {
       panic!("Unexpected error type");
     }
   }
+
+  fn sample_claims() -> AuthClaims {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "Jane".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "jane.doe@example.com".to_string(),
+    };
+
+    AuthClaims {
+      subject: auth_user.id.to_string(),
+      expires_in: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+      user_details: auth_user,
+      token_type: TokenType::AccessToken,
+      jti: None,
+      token_version: None,
+      issuer: None,
+      audience: None,
+      issued_at: None,
+      not_before: None,
+      roles: Vec::new(),
+      permissions: Vec::new(),
+      extra: Default::default(),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_rsa_pem_round_trip() {
+    // arrange
+    let jwt_util = JwtUtil::from_rsa_pem(
+      include_bytes!("../../tests/fixtures/keys/rsa_private.pem"),
+      include_bytes!("../../tests/fixtures/keys/rsa_public.pem"),
+    ).unwrap();
+    let claims = sample_claims();
+
+    // act
+    let token = jwt_util.generate_token(&claims).unwrap();
+    let extracted_claims = jwt_util.extract_claims(&token).await.unwrap();
+
+    // assert
+    assert_eq!(jwt_util.algorithm(), Algorithm::RS256);
+    assert_eq!(jsonwebtoken::decode_header(&token).unwrap().alg, Algorithm::RS256);
+    assert_eq!(extracted_claims, claims);
+  }
+
+  #[tokio::test]
+  async fn test_rsa_der_round_trip() {
+    // arrange
+    let jwt_util = JwtUtil::from_rsa_der(
+      include_bytes!("../../tests/fixtures/keys/rsa_private.der"),
+      include_bytes!("../../tests/fixtures/keys/rsa_public.der"),
+    );
+    let claims = sample_claims();
+
+    // act
+    let token = jwt_util.generate_token(&claims).unwrap();
+
+    // assert
+    assert_eq!(jwt_util.extract_claims(&token).await.unwrap(), claims);
+  }
+
+  #[tokio::test]
+  async fn test_ec_pem_round_trip() {
+    // arrange
+    let jwt_util = JwtUtil::from_ec_pem(
+      include_bytes!("../../tests/fixtures/keys/ec_private.pem"),
+      include_bytes!("../../tests/fixtures/keys/ec_public.pem"),
+    ).unwrap();
+    let claims = sample_claims();
+
+    // act
+    let token = jwt_util.generate_token(&claims).unwrap();
+
+    // assert
+    assert_eq!(jsonwebtoken::decode_header(&token).unwrap().alg, Algorithm::ES256);
+    assert_eq!(jwt_util.extract_claims(&token).await.unwrap(), claims);
+  }
+
+  #[tokio::test]
+  async fn test_ed_pem_round_trip() {
+    // arrange
+    let jwt_util = JwtUtil::from_ed_pem(
+      include_bytes!("../../tests/fixtures/keys/ed_private.pem"),
+      include_bytes!("../../tests/fixtures/keys/ed_public.pem"),
+    ).unwrap();
+    let claims = sample_claims();
+
+    // act
+    let token = jwt_util.generate_token(&claims).unwrap();
+
+    // assert
+    assert_eq!(jsonwebtoken::decode_header(&token).unwrap().alg, Algorithm::EdDSA);
+    assert_eq!(jwt_util.extract_claims(&token).await.unwrap(), claims);
+  }
+
+  #[tokio::test]
+  async fn test_verify_only_instance() {
+    // arrange
+    let signer = JwtUtil::from_rsa_pem(
+      include_bytes!("../../tests/fixtures/keys/rsa_private.pem"),
+      include_bytes!("../../tests/fixtures/keys/rsa_public.pem"),
+    ).unwrap();
+    let verifier = JwtUtil::from_rsa_public_pem(
+      include_bytes!("../../tests/fixtures/keys/rsa_public.pem"),
+    ).unwrap();
+    let claims = sample_claims();
+    let token = signer.generate_token(&claims).unwrap();
+
+    // act
+    let extracted_claims = verifier.extract_claims(&token).await;
+    let generate_result = verifier.generate_token(&claims);
+
+    // assert
+    assert!(!verifier.can_sign());
+    assert_eq!(extracted_claims.unwrap(), claims);
+    assert!(matches!(generate_result, Err(Error::JwtGenerate(_))));
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_rejects_unexpected_algorithm() {
+    // arrange
+    let public_key = include_bytes!("../../tests/fixtures/keys/rsa_public.pem");
+    let verifier = JwtUtil::from_rsa_public_pem(public_key).unwrap();
+    // Classic algorithm confusion: HMAC-sign using the public key as the secret.
+    let forged_token = encode(
+      &Header::new(Algorithm::HS256),
+      &sample_claims(),
+      &EncodingKey::from_secret(public_key),
+    ).unwrap();
+
+    // act
+    let extract_claims_result: Result<AuthClaims, Error> = verifier.extract_claims(&forged_token).await;
+
+    // assert
+    assert!(matches!(extract_claims_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_with_algorithm() {
+    // arrange
+    let jwt_util = JwtUtil::from_rsa_pem(
+      include_bytes!("../../tests/fixtures/keys/rsa_private.pem"),
+      include_bytes!("../../tests/fixtures/keys/rsa_public.pem"),
+    ).unwrap();
+
+    // act
+    let ps256_util = jwt_util.clone().with_algorithm(Algorithm::PS256);
+    let es256_util = jwt_util.with_algorithm(Algorithm::ES256);
+
+    // assert
+    assert_eq!(ps256_util.unwrap().algorithm(), Algorithm::PS256);
+    assert!(matches!(es256_util, Err(Error::JwtKeyInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_applies_validation_policy() {
+    // arrange
+    let policy = JwtValidationPolicy::new()
+      .allow_issuer("https://auth.example.com")
+      .allow_audience("orders");
+    let jwt_util = JwtUtil::new("some_key").with_validation_policy(policy);
+    let valid_claims = sample_claims()
+      .with_issuer("https://auth.example.com")
+      .with_audience("orders");
+    let valid_token = jwt_util.generate_token(&valid_claims).unwrap();
+    let foreign_token = jwt_util.generate_token(&sample_claims()).unwrap();
+
+    // act
+    let valid_result = jwt_util.extract_claims(&valid_token).await;
+    let foreign_result: Result<AuthClaims, Error> = jwt_util.extract_claims(&foreign_token).await;
+
+    // assert
+    assert_eq!(valid_result.unwrap(), valid_claims);
+    assert!(matches!(foreign_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_extract_claims_expired_token() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key")
+      .with_validation_policy(JwtValidationPolicy::new().with_leeway(0));
+    let mut claims = sample_claims();
+    claims.expires_in = (Utc::now() - Duration::minutes(5)).timestamp() as usize;
+    let token = jwt_util.generate_token(&claims).unwrap();
+
+    // act
+    let extract_claims_result: Result<AuthClaims, Error> = jwt_util.extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(extract_claims_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[test]
+  fn test_generate_token_with_key_id() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key").with_key_id("2025-01");
+
+    // act
+    let token = jwt_util.generate_token(&sample_claims()).unwrap();
+
+    // assert
+    let header = jsonwebtoken::decode_header(&token).unwrap();
+    assert_eq!(header.kid, Some("2025-01".to_string()));
+  }
+
+  #[tokio::test]
+  async fn test_jwk_round_trip() {
+    // arrange
+    let signers = vec![
+      JwtUtil::from_rsa_pem(
+        include_bytes!("../../tests/fixtures/keys/rsa_private.pem"),
+        include_bytes!("../../tests/fixtures/keys/rsa_public.pem"),
+      ).unwrap(),
+      JwtUtil::from_rsa_der(
+        include_bytes!("../../tests/fixtures/keys/rsa_private.der"),
+        include_bytes!("../../tests/fixtures/keys/rsa_public.der"),
+      ),
+      JwtUtil::from_ec_pem(
+        include_bytes!("../../tests/fixtures/keys/ec_private.pem"),
+        include_bytes!("../../tests/fixtures/keys/ec_public.pem"),
+      ).unwrap(),
+      JwtUtil::from_ed_pem(
+        include_bytes!("../../tests/fixtures/keys/ed_private.pem"),
+        include_bytes!("../../tests/fixtures/keys/ed_public.pem"),
+      ).unwrap(),
+    ];
+    let claims = sample_claims();
+
+    for signer in signers {
+      let signer = signer.with_key_id("key-1");
+      let token = signer.generate_token(&claims).unwrap();
+
+      // act
+      let jwk = signer.jwk().unwrap();
+      let verifier = JwtUtil::from_jwk(&jwk).unwrap();
+
+      // assert
+      assert_eq!(jwk.common.key_id, Some("key-1".to_string()));
+      assert_eq!(verifier.algorithm(), signer.algorithm());
+      assert_eq!(verifier.extract_claims(&token).await.unwrap(), claims);
+    }
+  }
+
+  #[test]
+  fn test_jwks_never_exports_hmac_secret() {
+    // arrange
+    let jwt_util = JwtUtil::new("some_key");
+
+    // act
+    let jwks = jwt_util.jwks();
+
+    // assert
+    assert!(jwt_util.jwk().is_none());
+    assert!(jwks.keys.is_empty());
+  }
+
+  #[test]
+  fn test_jwks_serializes_as_rfc_7517_key_set() {
+    // arrange
+    let jwt_util = JwtUtil::from_ed_public_pem(
+      include_bytes!("../../tests/fixtures/keys/ed_public.pem"),
+    ).unwrap().with_key_id("ed-1");
+
+    // act
+    let jwks = serde_json::to_value(jwt_util.jwks()).unwrap();
+
+    // assert
+    let key = &jwks["keys"][0];
+    assert_eq!(key["kty"], "OKP");
+    assert_eq!(key["crv"], "Ed25519");
+    assert_eq!(key["alg"], "EdDSA");
+    assert_eq!(key["use"], "sig");
+    assert_eq!(key["kid"], "ed-1");
+  }
+
+  #[test]
+  fn test_from_jwk_rejects_symmetric_key() {
+    // arrange
+    let jwk: Jwk = serde_json::from_value(serde_json::json!({
+      "kty": "oct", "k": "c2VjcmV0", "alg": "HS256"
+    })).unwrap();
+
+    // act
+    let jwt_util_result = JwtUtil::from_jwk(&jwk);
+
+    // assert
+    assert!(matches!(jwt_util_result, Err(Error::JwtKeyInvalid(_))));
+  }
+
+  #[test]
+  fn test_from_rsa_pem_invalid_key() {
+    // act
+    let jwt_util_result = JwtUtil::from_rsa_pem(b"not a key", b"not a key");
+
+    // assert
+    assert!(matches!(jwt_util_result, Err(Error::JwtKeyInvalid(_))));
+  }
 }
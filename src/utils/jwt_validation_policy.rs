@@ -0,0 +1,317 @@
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, Validation};
+use serde_json::{Map, Value};
+
+use crate::{
+  error::SerializableError,
+  types::{auth::AuthPayload, utils::jwt_util::AuthClaims},
+  Error,
+};
+
+/// Claim checks applied on top of signature verification. Built with the
+/// `with_*`/`allow_*` methods and handed to `JwtUtil::with_validation_policy`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtValidationPolicy {
+  issuers: Vec<String>,
+  audiences: Vec<String>,
+  leeway: u64,
+  validate_nbf: bool,
+  validate_iat: bool,
+  required_claims: Vec<String>,
+}
+
+impl Default for JwtValidationPolicy {
+  fn default() -> Self {
+    Self {
+      issuers: Vec::new(),
+      audiences: Vec::new(),
+      leeway: 60,
+      validate_nbf: true,
+      validate_iat: false,
+      required_claims: Vec::new(),
+    }
+  }
+}
+
+impl JwtValidationPolicy {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Accepts tokens whose `iss` matches any allowed issuer. With no allowed
+  /// issuers `iss` is not checked.
+  pub fn allow_issuer(mut self, issuer: &str) -> Self {
+    self.issuers.push(issuer.to_string());
+
+    self
+  }
+
+  /// Accepts tokens whose `aud` contains any allowed audience. With no
+  /// allowed audiences, tokens that carry an `aud` are rejected.
+  pub fn allow_audience(mut self, audience: &str) -> Self {
+    self.audiences.push(audience.to_string());
+
+    self
+  }
+
+  /// Clock skew tolerated for `exp`, `nbf` and `iat`, in seconds.
+  pub fn with_leeway(mut self, leeway: u64) -> Self {
+    self.leeway = leeway;
+
+    self
+  }
+
+  pub fn validate_nbf(mut self, validate_nbf: bool) -> Self {
+    self.validate_nbf = validate_nbf;
+
+    self
+  }
+
+  /// Requires `iat` and rejects tokens issued in the future.
+  pub fn validate_iat(mut self, validate_iat: bool) -> Self {
+    self.validate_iat = validate_iat;
+
+    self
+  }
+
+  pub fn require_claim(mut self, claim: &str) -> Self {
+    self.required_claims.push(claim.to_string());
+
+    self
+  }
+
+  pub fn leeway(&self) -> u64 {
+    self.leeway
+  }
+
+  pub(crate) fn validation(&self, algorithm: Algorithm) -> Validation {
+    let mut validation = Validation::new(algorithm);
+    validation.leeway = self.leeway;
+    validation.validate_nbf = self.validate_nbf;
+
+    if !self.issuers.is_empty() {
+      validation.set_issuer(&self.issuers);
+    }
+
+    if !self.audiences.is_empty() {
+      validation.set_audience(&self.audiences);
+    }
+
+    validation
+  }
+
+  /// Checks the decoded claims against the policy. `JwtUtil` relies on this
+  /// for the checks jsonwebtoken doesn't cover (`iat`, custom required
+  /// claims); token formats without jsonwebtoken use it for everything.
+  pub fn validate_claims<T: AuthPayload>(&self, claims: &AuthClaims<T>) -> Result<(), Error> {
+    let now = Utc::now().timestamp().max(0) as u64;
+
+    if (claims.expires_in as u64).saturating_add(self.leeway) < now {
+      return Err(invalid_token("Token has expired"));
+    }
+
+    if self.validate_nbf
+      && let Some(not_before) = claims.not_before
+      && not_before as u64 > now.saturating_add(self.leeway) {
+      return Err(invalid_token("Token is not valid yet"));
+    }
+
+    if self.validate_iat {
+      let issued_at = claims.issued_at.ok_or_else(|| invalid_token("Missing required claim iat"))?;
+
+      if issued_at as u64 > now.saturating_add(self.leeway) {
+        return Err(invalid_token("Token was issued in the future"));
+      }
+    }
+
+    if !self.issuers.is_empty() {
+      let issuer_allowed = claims.issuer.as_ref()
+        .is_some_and(|issuer| self.issuers.contains(issuer));
+
+      if !issuer_allowed {
+        return Err(invalid_token("Token issuer is not allowed"));
+      }
+    }
+
+    match (claims.audience.as_ref(), self.audiences.is_empty()) {
+      (Some(_), true) => return Err(invalid_token("Token audience is not allowed")),
+      (Some(audience), false) if !self.audiences.iter().any(|allowed| audience.contains(allowed)) => {
+        return Err(invalid_token("Token audience is not allowed"));
+      },
+      (None, false) => return Err(invalid_token("Missing required claim aud")),
+      _ => {},
+    }
+
+    if !self.required_claims.is_empty() {
+      let claims_value = serde_json::to_value(claims).map_err(|error| {
+        invalid_token(&error.to_string())
+      })?;
+
+      for claim in &self.required_claims {
+        if claims_value.get(claim).is_none_or(|value| value.is_null()) {
+          return Err(invalid_token(&format!("Missing required claim {}", claim)));
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  /// The checks jsonwebtoken doesn't cover, for claims decoded as plain JSON.
+  pub(crate) fn validate_raw_claims(&self, claims: &Map<String, Value>) -> Result<(), Error> {
+    let now = Utc::now().timestamp().max(0) as u64;
+
+    if self.validate_iat {
+      let issued_at = claims.get("iat").and_then(Value::as_u64)
+        .ok_or_else(|| invalid_token("Missing required claim iat"))?;
+
+      if issued_at > now.saturating_add(self.leeway) {
+        return Err(invalid_token("Token was issued in the future"));
+      }
+    }
+
+    for claim in &self.required_claims {
+      if claims.get(claim).is_none_or(|value| value.is_null()) {
+        return Err(invalid_token(&format!("Missing required claim {}", claim)));
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn invalid_token(message: &str) -> Error {
+  Error::JwtTokenInvalid(SerializableError { message: message.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::Duration;
+  use uuid::Uuid;
+
+  use crate::types::{auth::AuthUser, utils::jwt_util::TokenType};
+
+  fn claims() -> AuthClaims {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+    let expires_in = (Utc::now() + Duration::hours(1)).timestamp() as usize;
+
+    AuthClaims::new(auth_user, TokenType::AccessToken, expires_in)
+  }
+
+  fn error_message(result: Result<(), Error>) -> String {
+    match result {
+      Err(Error::JwtTokenInvalid(error)) => error.message,
+      _ => panic!("Expected JwtTokenInvalid error"),
+    }
+  }
+
+  #[test]
+  fn test_default_policy_accepts_plain_claims() {
+    // act
+    let result = JwtValidationPolicy::new().validate_claims(&claims());
+
+    // assert
+    assert!(result.is_ok());
+  }
+
+  #[test]
+  fn test_expired_claims_respect_leeway() {
+    // arrange
+    let mut expired_claims = claims();
+    expired_claims.expires_in = (Utc::now() - Duration::seconds(30)).timestamp() as usize;
+
+    // act
+    let lenient_result = JwtValidationPolicy::new().with_leeway(60).validate_claims(&expired_claims);
+    let strict_result = JwtValidationPolicy::new().with_leeway(0).validate_claims(&expired_claims);
+
+    // assert
+    assert!(lenient_result.is_ok());
+    assert_eq!(error_message(strict_result), "Token has expired");
+  }
+
+  #[test]
+  fn test_issuer() {
+    // arrange
+    let policy = JwtValidationPolicy::new().allow_issuer("https://auth.example.com");
+
+    // act
+    let allowed_result = policy.validate_claims(&claims().with_issuer("https://auth.example.com"));
+    let other_result = policy.validate_claims(&claims().with_issuer("https://evil.example.com"));
+    let missing_result = policy.validate_claims(&claims());
+
+    // assert
+    assert!(allowed_result.is_ok());
+    assert_eq!(error_message(other_result), "Token issuer is not allowed");
+    assert_eq!(error_message(missing_result), "Token issuer is not allowed");
+  }
+
+  #[test]
+  fn test_audience() {
+    // arrange
+    let policy = JwtValidationPolicy::new().allow_audience("orders");
+
+    // act
+    let allowed_result = policy.validate_claims(&claims().with_audiences(&["billing", "orders"]));
+    let other_result = policy.validate_claims(&claims().with_audience("billing"));
+    let unexpected_result = JwtValidationPolicy::new().validate_claims(&claims().with_audience("orders"));
+
+    // assert
+    assert!(allowed_result.is_ok());
+    assert_eq!(error_message(other_result), "Token audience is not allowed");
+    assert_eq!(error_message(unexpected_result), "Token audience is not allowed");
+  }
+
+  #[test]
+  fn test_not_before() {
+    // arrange
+    let future = (Utc::now() + Duration::minutes(10)).timestamp() as usize;
+    let immature_claims = claims().with_not_before(future);
+
+    // act
+    let enforced_result = JwtValidationPolicy::new().validate_claims(&immature_claims);
+    let ignored_result = JwtValidationPolicy::new().validate_nbf(false).validate_claims(&immature_claims);
+
+    // assert
+    assert_eq!(error_message(enforced_result), "Token is not valid yet");
+    assert!(ignored_result.is_ok());
+  }
+
+  #[test]
+  fn test_issued_at() {
+    // arrange
+    let policy = JwtValidationPolicy::new().validate_iat(true);
+    let mut future_claims = claims();
+    future_claims.issued_at = Some((Utc::now() + Duration::minutes(10)).timestamp() as usize);
+
+    // act
+    let issued_now_result = policy.validate_claims(&claims().issued_now());
+    let missing_result = policy.validate_claims(&claims());
+    let future_result = policy.validate_claims(&future_claims);
+
+    // assert
+    assert!(issued_now_result.is_ok());
+    assert_eq!(error_message(missing_result), "Missing required claim iat");
+    assert_eq!(error_message(future_result), "Token was issued in the future");
+  }
+
+  #[test]
+  fn test_required_claims() {
+    // arrange
+    let policy = JwtValidationPolicy::new().require_claim("jti");
+
+    // act
+    let present_result = policy.validate_claims(&claims().with_random_jti());
+    let missing_result = policy.validate_claims(&claims());
+
+    // assert
+    assert!(present_result.is_ok());
+    assert_eq!(error_message(missing_result), "Missing required claim jti");
+  }
+}
@@ -1,3 +1,17 @@
+pub mod jwe_util;
+pub mod jwks_verifier;
+pub mod jwt_keyring;
 pub mod jwt_util;
+pub mod jwt_validation_policy;
+pub mod password_hasher;
+pub mod paseto_util;
+pub mod totp_util;
 
+pub use jwe_util::JweUtil;
+pub use jwks_verifier::{IClaimsMapper, JwksVerifier, MappedJwksVerifier};
+pub use jwt_keyring::JwtKeyring;
 pub use jwt_util::JwtUtil;
+pub use jwt_validation_policy::JwtValidationPolicy;
+pub use password_hasher::{Argon2Hasher, IPasswordHasher};
+pub use paseto_util::PasetoUtil;
+pub use totp_util::TotpUtil;
@@ -0,0 +1,559 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use blake2::{
+  digest::{consts::{U32, U56}, Mac},
+  Blake2bMac,
+};
+use chacha20::{
+  cipher::{KeyIvInit, StreamCipher},
+  XChaCha20,
+};
+use chrono::{DateTime, SecondsFormat};
+use ed25519_dalek::{
+  pkcs8::{DecodePrivateKey, DecodePublicKey},
+  Signature, Signer, SigningKey, VerifyingKey,
+};
+use serde_json::Value;
+
+use crate::{
+  error::SerializableError,
+  types::{auth::AuthPayload, utils::jwt_util::AuthClaims},
+  utils::{jwt_util::IJwtUtil, jwt_validation_policy::JwtValidationPolicy},
+  Error,
+};
+
+const LOCAL_HEADER: &str = "v4.local.";
+const PUBLIC_HEADER: &str = "v4.public.";
+
+/// Claims PASETO defines as ISO 8601 strings rather than unix timestamps.
+const TIME_CLAIMS: [&str; 3] = ["exp", "iat", "nbf"];
+
+#[derive(Clone)]
+enum PasetoKey {
+  Local([u8; 32]),
+  Public { signing_key: Option<Box<SigningKey>>, verifying_key: VerifyingKey },
+}
+
+/// `IJwtUtil` producing PASETO v4 tokens. The version and purpose are fixed
+/// by the key, so there is no algorithm to confuse.
+#[derive(Clone)]
+pub struct PasetoUtil {
+  key: PasetoKey,
+  key_id: Option<String>,
+  implicit_assertion: Vec<u8>,
+  validation_policy: JwtValidationPolicy,
+}
+
+impl PasetoUtil {
+  /// `v4.local`: claims are encrypted with a 32 byte symmetric key.
+  pub fn local(key: &[u8]) -> Result<Self, Error> {
+    let key: [u8; 32] = key.try_into().map_err(|_| invalid_key_error(format!(
+      "v4.local keys must be 32 bytes, got {}", key.len()
+    )))?;
+
+    Ok(Self::with_key(PasetoKey::Local(key)))
+  }
+
+  /// `v4.public` with a PKCS#8 Ed25519 private key.
+  pub fn from_ed_pem(private_key: &[u8]) -> Result<Self, Error> {
+    let pem = std::str::from_utf8(private_key).map_err(|error| invalid_key_error(error.to_string()))?;
+    let signing_key = SigningKey::from_pkcs8_pem(pem).map_err(|error| invalid_key_error(error.to_string()))?;
+
+    Ok(Self::with_key(PasetoKey::Public {
+      verifying_key: signing_key.verifying_key(),
+      signing_key: Some(Box::new(signing_key)),
+    }))
+  }
+
+  /// Verify-only `v4.public` instance.
+  pub fn from_ed_public_pem(public_key: &[u8]) -> Result<Self, Error> {
+    let pem = std::str::from_utf8(public_key).map_err(|error| invalid_key_error(error.to_string()))?;
+    let verifying_key = VerifyingKey::from_public_key_pem(pem)
+      .map_err(|error| invalid_key_error(error.to_string()))?;
+
+    Ok(Self::with_key(PasetoKey::Public { signing_key: None, verifying_key }))
+  }
+
+  fn with_key(key: PasetoKey) -> Self {
+    Self {
+      key,
+      key_id: None,
+      implicit_assertion: Vec::new(),
+      validation_policy: JwtValidationPolicy::default(),
+    }
+  }
+
+  /// Adds `{"kid": ...}` as the token footer. Tokens whose footer names
+  /// another key are rejected.
+  pub fn with_key_id(mut self, key_id: &str) -> Self {
+    self.key_id = Some(key_id.to_string());
+
+    self
+  }
+
+  /// Data bound to the token without being part of it; the same assertion
+  /// must be configured to read the token back.
+  pub fn with_implicit_assertion(mut self, implicit_assertion: &[u8]) -> Self {
+    self.implicit_assertion = implicit_assertion.to_vec();
+
+    self
+  }
+
+  pub fn with_validation_policy(mut self, validation_policy: JwtValidationPolicy) -> Self {
+    self.validation_policy = validation_policy;
+
+    self
+  }
+
+  pub fn key_id(&self) -> Option<&str> {
+    self.key_id.as_deref()
+  }
+
+  fn footer(&self) -> Result<Vec<u8>, Error> {
+    match &self.key_id {
+      Some(key_id) => serde_json::to_vec(&serde_json::json!({ "kid": key_id })).map_err(generate_error),
+      None => Ok(Vec::new()),
+    }
+  }
+
+  fn seal(&self, message: &[u8], nonce: [u8; 32]) -> Result<String, Error> {
+    let footer = self.footer()?;
+
+    let (header, body) = match &self.key {
+      PasetoKey::Local(key) => {
+        let (encryption_key, counter_nonce, authentication_key) = local_keys(key, &nonce)?;
+        let mut ciphertext = message.to_vec();
+        XChaCha20::new(&encryption_key.into(), &counter_nonce.into()).apply_keystream(&mut ciphertext);
+
+        let pre_auth = pae(&[LOCAL_HEADER.as_bytes(), &nonce, &ciphertext, &footer, &self.implicit_assertion]);
+        let tag = blake2b_mac::<U32>(&authentication_key, &[&pre_auth])?;
+
+        (LOCAL_HEADER, [&nonce[..], &ciphertext, &tag].concat())
+      },
+      PasetoKey::Public { signing_key, .. } => {
+        let signing_key = signing_key.as_ref().ok_or_else(|| Error::JwtGenerate(SerializableError {
+          message: "PasetoUtil was created without a signing key".to_string()
+        }))?;
+        let pre_auth = pae(&[PUBLIC_HEADER.as_bytes(), message, &footer, &self.implicit_assertion]);
+        let signature = signing_key.sign(&pre_auth);
+
+        (PUBLIC_HEADER, [message, &signature.to_bytes()].concat())
+      },
+    };
+
+    let mut token = format!("{}{}", header, URL_SAFE_NO_PAD.encode(body));
+    if !footer.is_empty() {
+      token.push('.');
+      token.push_str(&URL_SAFE_NO_PAD.encode(footer));
+    }
+
+    Ok(token)
+  }
+
+  fn open(&self, token: &str) -> Result<Vec<u8>, Error> {
+    let header = match &self.key {
+      PasetoKey::Local(_) => LOCAL_HEADER,
+      PasetoKey::Public { .. } => PUBLIC_HEADER,
+    };
+    let payload = token.strip_prefix(header).ok_or_else(|| invalid_token("InvalidAlgorithm"))?;
+    let (body, footer) = match payload.split_once('.') {
+      Some((body, footer)) => (body, decode_part(footer)?),
+      None => (payload, Vec::new()),
+    };
+    let body = decode_part(body)?;
+
+    if let Some(key_id) = &self.key_id {
+      let footer_key_id = serde_json::from_slice::<Value>(&footer).ok()
+        .and_then(|footer| footer.get("kid").and_then(Value::as_str).map(str::to_string));
+
+      if footer_key_id.as_deref() != Some(key_id) {
+        return Err(invalid_token("Token was issued for another key"));
+      }
+    }
+
+    match &self.key {
+      PasetoKey::Local(key) => {
+        if body.len() < 64 {
+          return Err(invalid_token("Invalid token"));
+        }
+
+        let (nonce, rest) = body.split_at(32);
+        let (ciphertext, tag) = rest.split_at(rest.len() - 32);
+        let (encryption_key, counter_nonce, authentication_key) = local_keys(key, nonce)?;
+        let pre_auth = pae(&[LOCAL_HEADER.as_bytes(), nonce, ciphertext, &footer, &self.implicit_assertion]);
+
+        <Blake2bMac<U32> as Mac>::new_from_slice(&authentication_key)
+          .map_err(|_| invalid_token("Invalid token"))?
+          .chain_update(&pre_auth)
+          .verify_slice(tag)
+          .map_err(|_| invalid_token("InvalidSignature"))?;
+
+        let mut message = ciphertext.to_vec();
+        XChaCha20::new(&encryption_key.into(), &counter_nonce.into()).apply_keystream(&mut message);
+
+        Ok(message)
+      },
+      PasetoKey::Public { verifying_key, .. } => {
+        if body.len() < 64 {
+          return Err(invalid_token("Invalid token"));
+        }
+
+        let (message, signature) = body.split_at(body.len() - 64);
+        let signature = Signature::from_slice(signature).map_err(|_| invalid_token("Invalid token"))?;
+        let pre_auth = pae(&[PUBLIC_HEADER.as_bytes(), message, &footer, &self.implicit_assertion]);
+
+        verifying_key.verify_strict(&pre_auth, &signature)
+          .map_err(|_| invalid_token("InvalidSignature"))?;
+
+        Ok(message.to_vec())
+      },
+    }
+  }
+}
+
+#[async_trait]
+impl<T: AuthPayload> IJwtUtil<T> for PasetoUtil {
+  fn generate_token(&self, claims: &AuthClaims<T>) -> Result<String, Error> {
+    let mut claims_value = serde_json::to_value(claims).map_err(generate_error)?;
+
+    for claim in TIME_CLAIMS {
+      if let Some(value) = claims_value.get_mut(claim)
+        && let Some(timestamp) = value.as_i64() {
+        let date_time = DateTime::from_timestamp(timestamp, 0)
+          .ok_or_else(|| generate_error(format!("Invalid {} timestamp {}", claim, timestamp)))?;
+        *value = Value::String(date_time.to_rfc3339_opts(SecondsFormat::Secs, true));
+      }
+    }
+
+    let message = serde_json::to_vec(&claims_value).map_err(generate_error)?;
+    let mut nonce = [0u8; 32];
+    OsRng.fill_bytes(&mut nonce);
+
+    self.seal(&message, nonce)
+  }
+
+  async fn extract_claims(&self, token: &str) -> Result<AuthClaims<T>, Error> {
+    let message = self.open(token)?;
+    let mut claims_value: Value = serde_json::from_slice(&message)
+      .map_err(|_| invalid_token("Invalid token"))?;
+
+    for claim in TIME_CLAIMS {
+      if let Some(value) = claims_value.get_mut(claim)
+        && let Some(date_time) = value.as_str() {
+        let timestamp = DateTime::parse_from_rfc3339(date_time)
+          .map_err(|_| invalid_token(&format!("Invalid {} claim", claim)))?
+          .timestamp();
+        *value = Value::from(timestamp);
+      }
+    }
+
+    let claims: AuthClaims<T> = serde_json::from_value(claims_value)
+      .map_err(|_| invalid_token("Invalid token"))?;
+    self.validation_policy.validate_claims(&claims)?;
+
+    Ok(claims)
+  }
+}
+
+/// Pre-authentication encoding: every piece prefixed with its length so
+/// boundaries can't be shifted between them.
+fn pae(pieces: &[&[u8]]) -> Vec<u8> {
+  let mut output = (pieces.len() as u64).to_le_bytes().to_vec();
+
+  for piece in pieces {
+    output.extend_from_slice(&(piece.len() as u64 & (u64::MAX >> 1)).to_le_bytes());
+    output.extend_from_slice(piece);
+  }
+
+  output
+}
+
+/// XChaCha20 key and nonce plus BLAKE2b authentication key.
+type LocalKeys = ([u8; 32], [u8; 24], [u8; 32]);
+
+/// Derives the per-token keys from the shared key and the token's nonce.
+fn local_keys(key: &[u8], nonce: &[u8]) -> Result<LocalKeys, Error> {
+  let derived = blake2b_mac::<U56>(key, &[b"paseto-encryption-key", nonce])?;
+  let authentication_key = blake2b_mac::<U32>(key, &[b"paseto-auth-key-for-aead", nonce])?;
+
+  let mut encryption_key = [0u8; 32];
+  let mut counter_nonce = [0u8; 24];
+  encryption_key.copy_from_slice(&derived[..32]);
+  counter_nonce.copy_from_slice(&derived[32..]);
+
+  let mut authentication_key_bytes = [0u8; 32];
+  authentication_key_bytes.copy_from_slice(&authentication_key);
+
+  Ok((encryption_key, counter_nonce, authentication_key_bytes))
+}
+
+fn blake2b_mac<OutSize>(key: &[u8], parts: &[&[u8]]) -> Result<Vec<u8>, Error>
+where
+  OutSize: blake2::digest::generic_array::ArrayLength<u8>
+    + blake2::digest::typenum::IsLessOrEqual<blake2::digest::consts::U64>,
+  blake2::digest::typenum::LeEq<OutSize, blake2::digest::consts::U64>: blake2::digest::typenum::NonZero,
+{
+  let mut mac = <Blake2bMac<OutSize> as Mac>::new_from_slice(key).map_err(generate_error)?;
+
+  for part in parts {
+    mac.update(part);
+  }
+
+  Ok(mac.finalize().into_bytes().to_vec())
+}
+
+fn decode_part(part: &str) -> Result<Vec<u8>, Error> {
+  URL_SAFE_NO_PAD.decode(part).map_err(|_| invalid_token("Invalid token"))
+}
+
+fn invalid_token(message: &str) -> Error {
+  Error::JwtTokenInvalid(SerializableError { message: message.to_string() })
+}
+
+fn invalid_key_error(message: String) -> Error {
+  Error::JwtKeyInvalid(SerializableError { message })
+}
+
+fn generate_error(error: impl ToString) -> Error {
+  Error::JwtGenerate(SerializableError { message: error.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::{Duration, Utc};
+  use uuid::Uuid;
+
+  use crate::{
+    auth::{Auth, IAuth},
+    types::{auth::AuthUser, utils::jwt_util::TokenType},
+  };
+
+  const ED_PRIVATE_PEM: &[u8] = include_bytes!("../../tests/fixtures/keys/ed_private.pem");
+  const ED_PUBLIC_PEM: &[u8] = include_bytes!("../../tests/fixtures/keys/ed_public.pem");
+
+  fn sample_claims() -> AuthClaims {
+    let auth_user = AuthUser {
+      id: Uuid::new_v4(),
+      first_name: "John".to_string(),
+      middle_name: None,
+      last_name: "Doe".to_string(),
+      email: "john.doe@example.com".to_string(),
+    };
+    let expires_in = (Utc::now() + Duration::hours(1)).timestamp() as usize;
+
+    AuthClaims::new(auth_user, TokenType::AccessToken, expires_in).issued_now()
+  }
+
+  #[test]
+  fn test_pae() {
+    // assert
+    assert_eq!(pae(&[]), vec![0u8; 8]);
+    assert_eq!(pae(&[b""]), [&[1u8, 0, 0, 0, 0, 0, 0, 0][..], &[0u8; 8]].concat());
+    assert_eq!(pae(&[b"test"]), [&[1u8, 0, 0, 0, 0, 0, 0, 0][..], &[4u8, 0, 0, 0, 0, 0, 0, 0], b"test"].concat());
+  }
+
+  fn hex(value: &str) -> Vec<u8> {
+    (0..value.len()).step_by(2).map(|index| u8::from_str_radix(&value[index..index + 2], 16).unwrap()).collect()
+  }
+
+  // Official PASETO v4 test vectors, https://github.com/paseto-standard/test-vectors
+  const VECTOR_MESSAGE: &[u8] = br#"{"data":"this is a signed message","exp":"2022-01-01T00:00:00+00:00"}"#;
+  const VECTOR_KEY_ID: &str = "zVhMiPBP9fRf2snEcT7gFTioeA9COcNy9DfgL1W60haN";
+
+  fn vector_signer() -> PasetoUtil {
+    let secret_key = hex("b4cbfb43df4ce210727d953e4a713307fa19bb7d9f85041438d9e11b942a3774");
+    let signing_key = SigningKey::from_bytes(&secret_key.try_into().unwrap());
+
+    PasetoUtil::with_key(PasetoKey::Public {
+      verifying_key: signing_key.verifying_key(),
+      signing_key: Some(Box::new(signing_key)),
+    })
+  }
+
+  #[tokio::test]
+  async fn test_local_matches_test_vector() {
+    // arrange
+    // 4-E-1
+    let paseto_util = PasetoUtil::local(&hex("707172737475767778797a7b7c7d7e7f808182838485868788898a8b8c8d8e8f")).unwrap();
+    let expected_token = "v4.local.AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQAr68PS4AXe7If_ZgesdkUMvSwssBiAllpk5HC0e8kApeaqMfGo_7OpBnwJOAbY9V7WU6abu74MmcUE8YWAiaArVI8XICrrTL1sp7lagKb3YyK5Pv6LZ8yEHNExlr_UvWC1nz_w";
+
+    // act
+    let token = paseto_util.seal(VECTOR_MESSAGE, [0u8; 32]).unwrap();
+    let message = paseto_util.open(expected_token).unwrap();
+
+    // assert
+    assert_eq!(token, expected_token);
+    assert_eq!(message, VECTOR_MESSAGE);
+  }
+
+  #[tokio::test]
+  async fn test_public_matches_test_vectors() {
+    // arrange
+    // 4-S-1 and 4-S-2
+    let expected_token = "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9bg_XBBzds8lTZShVlwwKSgeKpLT3yukTw6JUz3W4h_ExsQV-P0V54zemZDcAxFaSeef1QlXEFtkqxT1ciiQEDA";
+    let expected_token_with_footer = "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9v3Jt8mx_TdM2ceTGoqwrh4yDFn0XsHvvV_D0DtwQxVrJEBMl0F2caAdgnpKlt4p7xBnx1HcO-SPo8FPp214HDw.eyJraWQiOiJ6VmhNaVBCUDlmUmYyc25FY1Q3Z0ZUaW9lQTlDT2NOeTlEZmdMMVc2MGhhTiJ9";
+    let signer_with_key_id = vector_signer().with_key_id(VECTOR_KEY_ID);
+
+    // act
+    let token = vector_signer().seal(VECTOR_MESSAGE, [0u8; 32]).unwrap();
+    let token_with_footer = signer_with_key_id.seal(VECTOR_MESSAGE, [0u8; 32]).unwrap();
+    let message = signer_with_key_id.open(expected_token_with_footer).unwrap();
+
+    // assert
+    assert_eq!(token, expected_token);
+    assert_eq!(token_with_footer, expected_token_with_footer);
+    assert_eq!(message, VECTOR_MESSAGE);
+  }
+
+  #[tokio::test]
+  async fn test_rejects_footer_for_another_key() {
+    // arrange
+    let issuer = PasetoUtil::from_ed_pem(ED_PRIVATE_PEM).unwrap();
+    let verifier = PasetoUtil::from_ed_public_pem(ED_PUBLIC_PEM).unwrap().with_key_id("ed-1");
+    let claims = sample_claims().issued_now();
+    let other_key_token = issuer.clone().with_key_id("ed-2").generate_token(&claims).unwrap();
+    let footerless_token = issuer.generate_token(&claims).unwrap();
+
+    // act
+    let other_key_result: Result<AuthClaims, Error> = verifier.extract_claims(&other_key_token).await;
+    let footerless_result: Result<AuthClaims, Error> = verifier.extract_claims(&footerless_token).await;
+
+    // assert
+    match other_key_result {
+      Err(Error::JwtTokenInvalid(error)) => assert_eq!(error.message, "Token was issued for another key"),
+      _ => panic!("Expected JwtTokenInvalid error"),
+    }
+    assert!(matches!(footerless_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_local_round_trip() {
+    // arrange
+    let paseto_util = PasetoUtil::local(&[7u8; 32]).unwrap();
+    let claims = sample_claims();
+
+    // act
+    let token = paseto_util.generate_token(&claims).unwrap();
+    let extracted_claims: AuthClaims = paseto_util.extract_claims(&token).await.unwrap();
+
+    // assert
+    assert!(token.starts_with("v4.local."));
+    assert_eq!(extracted_claims.user_details, claims.user_details);
+    assert_eq!(extracted_claims.expires_in, claims.expires_in);
+    assert_eq!(extracted_claims.issued_at, claims.issued_at);
+  }
+
+  #[tokio::test]
+  async fn test_local_rejects_tampered_token() {
+    // arrange
+    let paseto_util = PasetoUtil::local(&[7u8; 32]).unwrap();
+    let token = paseto_util.generate_token(&sample_claims()).unwrap();
+    let mut body = decode_part(&token[LOCAL_HEADER.len()..]).unwrap();
+    body[40] ^= 1;
+    let tampered_token = format!("{}{}", LOCAL_HEADER, URL_SAFE_NO_PAD.encode(body));
+
+    // act
+    let result: Result<AuthClaims, Error> = paseto_util.extract_claims(&tampered_token).await;
+    let other_key_result: Result<AuthClaims, Error> = PasetoUtil::local(&[8u8; 32]).unwrap().extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(result, Err(Error::JwtTokenInvalid(_))));
+    assert!(matches!(other_key_result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_public_round_trip() {
+    // arrange
+    let issuer = PasetoUtil::from_ed_pem(ED_PRIVATE_PEM).unwrap().with_key_id("ed-1");
+    let verifier = PasetoUtil::from_ed_public_pem(ED_PUBLIC_PEM).unwrap().with_key_id("ed-1");
+    let claims = sample_claims();
+
+    // act
+    let token = issuer.generate_token(&claims).unwrap();
+    let extracted_claims: AuthClaims = verifier.extract_claims(&token).await.unwrap();
+    let generate_result = verifier.generate_token(&claims);
+
+    // assert
+    assert!(token.starts_with("v4.public."));
+    assert_eq!(token.split('.').count(), 4);
+    assert_eq!(extracted_claims.user_details, claims.user_details);
+    assert!(matches!(generate_result, Err(Error::JwtGenerate(_))));
+  }
+
+  #[tokio::test]
+  async fn test_rejects_other_purpose() {
+    // arrange
+    let token = PasetoUtil::from_ed_pem(ED_PRIVATE_PEM).unwrap().generate_token(&sample_claims()).unwrap();
+
+    // act
+    let result: Result<AuthClaims, Error> = PasetoUtil::local(&[7u8; 32]).unwrap().extract_claims(&token).await;
+
+    // assert
+    match result {
+      Err(Error::JwtTokenInvalid(error)) => assert_eq!(error.message, "InvalidAlgorithm"),
+      _ => panic!("Expected JwtTokenInvalid error"),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_implicit_assertion_must_match() {
+    // arrange
+    let paseto_util = PasetoUtil::local(&[7u8; 32]).unwrap().with_implicit_assertion(b"tenant-1");
+    let token = paseto_util.generate_token(&sample_claims()).unwrap();
+
+    // act
+    let result: Result<AuthClaims, Error> = PasetoUtil::local(&[7u8; 32]).unwrap()
+      .with_implicit_assertion(b"tenant-2")
+      .extract_claims(&token).await;
+
+    // assert
+    assert!(matches!(result, Err(Error::JwtTokenInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_applies_validation_policy() {
+    // arrange
+    let paseto_util = PasetoUtil::local(&[7u8; 32]).unwrap();
+    let mut claims = sample_claims();
+    claims.expires_in = (Utc::now() - Duration::hours(1)).timestamp() as usize;
+    let token = paseto_util.generate_token(&claims).unwrap();
+
+    // act
+    let result: Result<AuthClaims, Error> = paseto_util.extract_claims(&token).await;
+
+    // assert
+    match result {
+      Err(Error::JwtTokenInvalid(error)) => assert_eq!(error.message, "Token has expired"),
+      _ => panic!("Expected JwtTokenInvalid error"),
+    }
+  }
+
+  #[test]
+  fn test_local_rejects_invalid_key_length() {
+    // act
+    let result = PasetoUtil::local(b"short");
+
+    // assert
+    assert!(matches!(result, Err(Error::JwtKeyInvalid(_))));
+  }
+
+  #[tokio::test]
+  async fn test_interchangeable_with_jwt_util_in_auth() {
+    // arrange
+    let paseto_util = PasetoUtil::from_ed_pem(ED_PRIVATE_PEM).unwrap();
+    let claims = sample_claims();
+    let token = paseto_util.generate_token(&claims).unwrap();
+    let mut headers = lambda_http::http::HeaderMap::new();
+    headers.insert("Authorization", format!("Watashiwasta {}", token).parse().unwrap());
+
+    // act
+    let mut auth = Auth::new(Box::new(paseto_util));
+    let authenticate_result = auth.authenticate(&headers).await;
+
+    // assert
+    assert!(authenticate_result.is_ok());
+    assert_eq!(auth.user(), Some(claims.user_details));
+  }
+}
@@ -0,0 +1,140 @@
+use argon2::{
+  password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+  Algorithm, Argon2, Params, Version,
+};
+
+use crate::{error::SerializableError, Error};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+#[cfg_attr(test, automock)]
+pub trait IPasswordHasher: Send + Sync {
+  /// Hashes `password` into a PHC string, e.g. `$argon2id$v=19$m=...`.
+  fn hash(&self, password: &str) -> Result<String, Error>;
+
+  /// Checks `password` against a PHC string produced by `hash`, with the
+  /// parameters recorded in the string.
+  fn verify(&self, password: &str, password_hash: &str) -> Result<bool, Error>;
+
+  /// Whether `password_hash` was produced with other parameters than the
+  /// current ones and should be replaced after the next successful login.
+  fn needs_rehash(&self, password_hash: &str) -> bool;
+}
+
+/// Argon2id with the OWASP recommended parameters (19 MiB, 2 iterations,
+/// 1 lane) unless configured otherwise.
+pub struct Argon2Hasher {
+  argon2: Argon2<'static>,
+}
+
+impl Argon2Hasher {
+  pub fn new() -> Self {
+    Self { argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default()) }
+  }
+
+  /// `memory_cost` is in KiB.
+  pub fn with_params(mut self, memory_cost: u32, iterations: u32, parallelism: u32) -> Result<Self, Error> {
+    let params = Params::new(memory_cost, iterations, parallelism, None).map_err(|error| {
+      Error::Unhandled(SerializableError { message: error.to_string() })
+    })?;
+    self.argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+    Ok(self)
+  }
+}
+
+impl Default for Argon2Hasher {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl IPasswordHasher for Argon2Hasher {
+  fn hash(&self, password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    self.argon2.hash_password(password.as_bytes(), &salt)
+      .map(|password_hash| password_hash.to_string())
+      .map_err(|error| Error::Unhandled(SerializableError { message: error.to_string() }))
+  }
+
+  fn verify(&self, password: &str, password_hash: &str) -> Result<bool, Error> {
+    let password_hash = parse(password_hash)?;
+
+    // The digest comparison inside `verify_password` is constant time.
+    match self.argon2.verify_password(password.as_bytes(), &password_hash) {
+      Ok(()) => Ok(true),
+      Err(argon2::password_hash::Error::Password) => Ok(false),
+      Err(error) => Err(Error::Unhandled(SerializableError { message: error.to_string() })),
+    }
+  }
+
+  fn needs_rehash(&self, password_hash: &str) -> bool {
+    let Ok(password_hash) = parse(password_hash) else {
+      return true;
+    };
+
+    password_hash.algorithm != Algorithm::Argon2id.ident()
+      || password_hash.version != Some(Version::V0x13.into())
+      || Params::try_from(&password_hash).map_or(true, |params| {
+        let current = self.argon2.params();
+
+        params.m_cost() != current.m_cost()
+          || params.t_cost() != current.t_cost()
+          || params.p_cost() != current.p_cost()
+      })
+  }
+}
+
+fn parse(password_hash: &str) -> Result<PasswordHash<'_>, Error> {
+  PasswordHash::new(password_hash).map_err(|error| Error::Unhandled(SerializableError {
+    message: format!("Malformed password hash: {}", error)
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fast_hasher() -> Argon2Hasher {
+    Argon2Hasher::new().with_params(1024, 1, 1).unwrap()
+  }
+
+  #[test]
+  fn test_hash_and_verify() {
+    // arrange
+    let hasher = fast_hasher();
+
+    // act
+    let password_hash = hasher.hash("correct horse battery staple").unwrap();
+
+    // assert
+    assert!(password_hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+    assert!(hasher.verify("correct horse battery staple", &password_hash).unwrap());
+    assert!(!hasher.verify("correct horse battery stapler", &password_hash).unwrap());
+    assert_ne!(password_hash, hasher.hash("correct horse battery staple").unwrap());
+  }
+
+  #[test]
+  fn test_needs_rehash_when_params_change() {
+    // arrange
+    let old_hasher = fast_hasher();
+    let new_hasher = Argon2Hasher::new().with_params(2048, 1, 1).unwrap();
+    let password_hash = old_hasher.hash("secret").unwrap();
+
+    // assert
+    assert!(!old_hasher.needs_rehash(&password_hash));
+    assert!(new_hasher.needs_rehash(&password_hash));
+    assert!(new_hasher.verify("secret", &password_hash).unwrap());
+    assert!(new_hasher.needs_rehash("not-a-phc-string"));
+  }
+
+  #[test]
+  fn test_verify_malformed_hash() {
+    // act
+    let result = fast_hasher().verify("secret", "not-a-phc-string");
+
+    // assert
+    assert!(matches!(result, Err(Error::Unhandled(_))));
+  }
+}
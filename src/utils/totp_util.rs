@@ -0,0 +1,211 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use crate::{error::SerializableError, Error};
+
+const SECRET_ALPHABET: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+/// RFC 6238 time-based one-time passwords (HMAC-SHA1), compatible with
+/// the common authenticator apps. Six digits every 30 seconds by default,
+/// accepting one step of clock drift either way.
+#[derive(Debug, Clone)]
+pub struct TotpUtil {
+  issuer: String,
+  digits: u32,
+  period: u64,
+  skew: u64,
+}
+
+impl TotpUtil {
+  /// `issuer` is the name authenticator apps show next to the account.
+  pub fn new(issuer: &str) -> Self {
+    Self { issuer: issuer.to_string(), digits: 6, period: 30, skew: 1 }
+  }
+
+  pub fn with_digits(mut self, digits: u32) -> Self {
+    self.digits = digits;
+
+    self
+  }
+
+  /// Seconds per step. Fails for 0.
+  pub fn with_period(mut self, period: u64) -> Result<Self, Error> {
+    if period == 0 {
+      return Err(Error::Unhandled(SerializableError { message: "TOTP period must be at least one second".to_string() }));
+    }
+
+    self.period = period;
+
+    Ok(self)
+  }
+
+  /// Number of steps before and after the current one still accepted.
+  pub fn with_skew(mut self, skew: u64) -> Self {
+    self.skew = skew;
+
+    self
+  }
+
+  pub fn digits(&self) -> u32 {
+    self.digits
+  }
+
+  /// A random 160-bit secret, base32 encoded.
+  pub fn generate_secret(&self) -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+
+    base32::encode(SECRET_ALPHABET, &secret)
+  }
+
+  /// The `otpauth://` URI to render as a QR code for enrollment.
+  pub fn provisioning_uri(&self, secret: &str, account_name: &str) -> String {
+    format!(
+      "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+      percent_encode(&self.issuer),
+      percent_encode(account_name),
+      secret,
+      percent_encode(&self.issuer),
+      self.digits,
+      self.period,
+    )
+  }
+
+  pub fn step(&self, timestamp: u64) -> u64 {
+    timestamp / self.period
+  }
+
+  pub fn code_at(&self, secret: &str, timestamp: u64) -> Result<String, Error> {
+    Ok(self.code_for_step(&decode_secret(secret)?, self.step(timestamp)))
+  }
+
+  /// Returns the time step `code` belongs to if it is valid at `timestamp`,
+  /// so callers can refuse to accept the same step twice.
+  pub fn verify(&self, secret: &str, code: &str, timestamp: u64) -> Result<Option<u64>, Error> {
+    let secret = decode_secret(secret)?;
+    let code = code.trim();
+    let current_step = self.step(timestamp);
+
+    let step = (current_step.saturating_sub(self.skew)..=current_step + self.skew)
+      .find(|step| constant_time_eq(self.code_for_step(&secret, *step).as_bytes(), code.as_bytes()));
+
+    Ok(step)
+  }
+
+  fn code_for_step(&self, secret: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+
+    format!("{:0width$}", binary as u64 % 10u64.pow(self.digits), width = self.digits as usize)
+  }
+}
+
+fn decode_secret(secret: &str) -> Result<Vec<u8>, Error> {
+  let normalized: String = secret.chars()
+    .filter(|character| !character.is_whitespace() && *character != '=')
+    .map(|character| character.to_ascii_uppercase())
+    .collect();
+
+  base32::decode(SECRET_ALPHABET, &normalized)
+    .filter(|secret| !secret.is_empty())
+    .ok_or_else(|| Error::Unhandled(SerializableError { message: "Malformed TOTP secret".to_string() }))
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+  left.len() == right.len() && left.iter()
+    .zip(right)
+    .fold(0u8, |difference, (left, right)| difference | (left ^ right)) == 0
+}
+
+fn percent_encode(value: &str) -> String {
+  value.bytes().map(|byte| match byte {
+    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => (byte as char).to_string(),
+    _ => format!("%{:02X}", byte),
+  }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The SHA1 seed of RFC 6238 appendix B, "12345678901234567890".
+  const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+  #[test]
+  fn test_rfc_6238_vectors() {
+    // arrange
+    let totp_util = TotpUtil::new("Ferrum").with_digits(8);
+
+    // assert
+    assert_eq!(totp_util.code_at(RFC_SECRET, 59).unwrap(), "94287082");
+    assert_eq!(totp_util.code_at(RFC_SECRET, 1111111109).unwrap(), "07081804");
+    assert_eq!(totp_util.code_at(RFC_SECRET, 1111111111).unwrap(), "14050471");
+    assert_eq!(totp_util.code_at(RFC_SECRET, 1234567890).unwrap(), "89005924");
+    assert_eq!(totp_util.code_at(RFC_SECRET, 2000000000).unwrap(), "69279037");
+  }
+
+  #[test]
+  fn test_verify_with_drift() {
+    // arrange
+    let totp_util = TotpUtil::new("Ferrum");
+    let secret = totp_util.generate_secret();
+    let now = 1_700_000_000;
+    let previous_code = totp_util.code_at(&secret, now - 30).unwrap();
+    let stale_code = totp_util.code_at(&secret, now - 90).unwrap();
+
+    // act
+    let previous_step = totp_util.verify(&secret, &previous_code, now).unwrap();
+    let stale_step = totp_util.verify(&secret, &stale_code, now).unwrap();
+
+    // assert
+    assert_eq!(previous_step, Some(totp_util.step(now) - 1));
+    assert_eq!(stale_step, None);
+    assert_eq!(totp_util.verify(&secret, "12345", now).unwrap(), None);
+  }
+
+  #[test]
+  fn test_with_period() {
+    // act
+    let totp_util = TotpUtil::new("Ferrum").with_period(60).unwrap();
+    let zero_result = TotpUtil::new("Ferrum").with_period(0);
+
+    // assert
+    assert_eq!(totp_util.step(120), 2);
+    assert!(matches!(zero_result, Err(Error::Unhandled(_))));
+  }
+
+  #[test]
+  fn test_provisioning_uri() {
+    // arrange
+    let totp_util = TotpUtil::new("Acme Corp");
+
+    // act
+    let uri = totp_util.provisioning_uri("JBSWY3DPEHPK3PXP", "john.doe@example.com");
+
+    // assert
+    assert_eq!(
+      uri,
+      "otpauth://totp/Acme%20Corp:john.doe@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Acme%20Corp&algorithm=SHA1&digits=6&period=30"
+    );
+  }
+
+  #[test]
+  fn test_generate_secret() {
+    // arrange
+    let totp_util = TotpUtil::new("Ferrum");
+
+    // act
+    let secret = totp_util.generate_secret();
+
+    // assert
+    assert_eq!(secret.len(), 32);
+    assert_eq!(decode_secret(&secret).unwrap().len(), 20);
+    assert!(matches!(totp_util.code_at("not base32!", 0), Err(Error::Unhandled(_))));
+  }
+}